@@ -0,0 +1,220 @@
+// Times guard pathfinding on the largest maps the generator makes, crowded with
+// guards, both patrolling and chasing the thief. Run it with optimizations:
+//
+//     cargo run --release --example pathing_bench
+
+use rand::prelude::*;
+use std::time::{Duration, Instant};
+use thiefrl3::cell_grid::*;
+use thiefrl3::guard::{guard_act_all, new_lines};
+use thiefrl3::random_map::generate_map;
+
+const MAPS_TO_TRY: usize = 200;
+const NUM_GUARDS: usize = 60;
+const NUM_TURNS: usize = 200;
+const NUM_QUERIES: usize = 2000;
+
+fn main() {
+    let mut rng = MyRng::seed_from_u64(1234);
+
+    // The map size stops growing past level 5, but it is still random, so keep
+    // the biggest of a batch.
+
+    let mut map = generate_map(&mut rng, 10);
+    for _ in 1..MAPS_TO_TRY {
+        let candidate = generate_map(&mut rng, 10);
+        if num_cells(&candidate) > num_cells(&map) {
+            map = candidate;
+        }
+    }
+
+    println!("map: {} x {}, {} patrol regions, {} guards generated",
+        map.cells.extents()[0], map.cells.extents()[1], map.patrol_regions.len(), map.guards.len());
+
+    add_guards(&mut rng, &mut map, NUM_GUARDS);
+
+    println!("guards after crowding: {}", map.guards.len());
+    println!();
+
+    bench_region_fields(&map);
+    bench_position_fields(&mut rng, &map);
+    bench_turns(&mut rng, &mut map);
+    bench_chase(&mut rng, &mut map);
+}
+
+fn num_cells(map: &Map) -> usize {
+    map.cells.extents()[0] * map.cells.extents()[1]
+}
+
+fn bench_region_fields(map: &Map) {
+    let num_regions = map.patrol_regions.len();
+
+    let start = Instant::now();
+    for i in 0..NUM_QUERIES {
+        map.compute_distances_to_region(i % num_regions);
+    }
+    let uncached = start.elapsed();
+
+    map.distance_fields.invalidate();
+
+    let start = Instant::now();
+    for i in 0..NUM_QUERIES {
+        map.distances_to_region(i % num_regions);
+    }
+    let cached = start.elapsed();
+
+    println!("distances to region, {} queries:", NUM_QUERIES);
+    report("computed every time", uncached, NUM_QUERIES);
+    report("cached", cached, NUM_QUERIES);
+    println!();
+}
+
+fn bench_position_fields(rng: &mut MyRng, map: &Map) {
+    let open = open_positions(map);
+
+    let queries: Vec<(Point, Point)> = (0..NUM_QUERIES)
+        .map(|_| (*open.choose(rng).unwrap(), *open.choose(rng).unwrap()))
+        .collect();
+
+    let start = Instant::now();
+    for &(pos_goal, _) in &queries {
+        map.compute_distances_to_position(pos_goal);
+    }
+    let full = start.elapsed();
+
+    let start = Instant::now();
+    for &(pos_goal, pos_from) in &queries {
+        map.compute_distances_to_position_from(pos_goal, pos_from);
+    }
+    let bounded = start.elapsed();
+
+    println!("distances to position, {} queries:", NUM_QUERIES);
+    report("whole map", full, NUM_QUERIES);
+    report("bounded by the guard", bounded, NUM_QUERIES);
+    println!();
+}
+
+// Whole guard turns, with the thief out of the way so the guards just patrol.
+
+fn bench_turns(rng: &mut MyRng, map: &mut Map) {
+    let mut lines = new_lines();
+    let mut player = make_player(&Point::new(0, 0));
+    let mut events = Vec::new();
+
+    let start = Instant::now();
+    for _ in 0..NUM_TURNS {
+        events.clear();
+        guard_act_all(rng, &mut lines, &mut events, map, &mut player);
+    }
+    let elapsed = start.elapsed();
+
+    println!("guard turns, {} guards:", map.guards.len());
+    report("per turn", elapsed, NUM_TURNS);
+}
+
+// Whole guard turns with every guard converging on the thief in the middle of
+// the map, as after an alarm, so they all path to a position rather than to a
+// patrol region. Alongside, the distance fields the chasing guards need each
+// turn are timed both ways: one whole-map field per guard, as before the
+// search was bounded, and the two bounded fields per guard it takes now (one
+// to plan the step, one to take it).
+
+fn bench_chase(rng: &mut MyRng, map: &mut Map) {
+    let center = Point::new(map.cells.extents()[0] as i32 / 2, map.cells.extents()[1] as i32 / 2);
+    let pos_player = open_positions(map).into_iter()
+        .filter(|&pos| !map.pos_blocked_by_guard(pos))
+        .min_by_key(|&pos| (pos - center).length_squared())
+        .unwrap();
+
+    let mut lines = new_lines();
+    let mut player = make_player(&pos_player);
+    let mut events = Vec::new();
+
+    for guard in map.guards.iter_mut() {
+        guard.mode = GuardMode::MoveToGuardShout;
+        guard.mode_timeout = NUM_TURNS;
+        guard.goal = pos_player;
+    }
+
+    let mut whole_map = Duration::default();
+    let mut bounded = Duration::default();
+    let mut turns = Duration::default();
+    let mut num_chasing = 0;
+
+    for _ in 0..NUM_TURNS {
+        let chasing: Vec<(Point, Point)> = map.guards.iter()
+            .filter(|guard| paths_to_position(guard.mode))
+            .map(|guard| (guard.goal, guard.pos))
+            .collect();
+        num_chasing += chasing.len();
+
+        let start = Instant::now();
+        for &(pos_goal, _) in &chasing {
+            map.compute_distances_to_position(pos_goal);
+        }
+        whole_map += start.elapsed();
+
+        let start = Instant::now();
+        for &(pos_goal, pos_from) in &chasing {
+            map.compute_distances_to_position_from(pos_goal, pos_from);
+            map.compute_distances_to_position_from(pos_goal, pos_from);
+        }
+        bounded += start.elapsed();
+
+        // Keep the thief standing so the guards keep coming.
+
+        player.health = make_player(&pos_player).health;
+
+        events.clear();
+        let start = Instant::now();
+        guard_act_all(rng, &mut lines, &mut events, map, &mut player);
+        turns += start.elapsed();
+    }
+
+    println!();
+    println!("chase, {} guards, {:.1} of them pathing to the thief per turn:", map.guards.len(), num_chasing as f64 / NUM_TURNS as f64);
+    report("whole-map fields (old)", whole_map, NUM_TURNS);
+    report("bounded fields (new)", bounded, NUM_TURNS);
+    report("whole guard turn", turns, NUM_TURNS);
+}
+
+fn paths_to_position(mode: GuardMode) -> bool {
+    !matches!(mode, GuardMode::Patrol | GuardMode::Look | GuardMode::Listen | GuardMode::Unconscious)
+}
+
+fn report(label: &str, elapsed: Duration, count: usize) {
+    println!("    {:24} {:10.1} us", label, elapsed.as_secs_f64() * 1.0e6 / count as f64);
+}
+
+fn open_positions(map: &Map) -> Vec<Point> {
+    let mut positions = Vec::new();
+    for x in 0..map.cells.extents()[0] {
+        for y in 0..map.cells.extents()[1] {
+            let cell = &map.cells[[x, y]];
+            if cell.move_cost != INFINITE_COST && cell.region != INVALID_REGION && cell.cell_type != CellType::GroundWater {
+                positions.push(Point::new(x as i32, y as i32));
+            }
+        }
+    }
+    positions
+}
+
+fn add_guards(rng: &mut MyRng, map: &mut Map, num_guards: usize) {
+    let mut open = open_positions(map);
+    open.retain(|&pos| !map.pos_blocked_by_guard(pos));
+    open.shuffle(rng);
+
+    while map.guards.len() < num_guards {
+        let pos = match open.pop() {
+            Some(pos) => pos,
+            None => break,
+        };
+
+        let mut guard = Guard::new(pos, Point::new(1, 0));
+
+        guard.setup_goal_region(rng, map);
+        guard.dir = guard.initial_dir(map);
+
+        map.guards.push(guard);
+    }
+}
@@ -0,0 +1,50 @@
+// Takes levels to and from the Tiled map editor. Export a generated level, with
+// the level number and seed to generate it from:
+//
+//     cargo run --example tiled_convert -- export 4 1234 mansion.json
+//
+// Tiled looks for the tileset next to the map, so copy static/tiles.png there
+// too. Once the map has been edited and saved as JSON, bring it back as a level
+// file that can go in the campaign:
+//
+//     cargo run --example tiled_convert -- import mansion.json levels/mansion.txt
+
+use rand::prelude::*;
+use std::process::exit;
+use thiefrl3::cell_grid::*;
+use thiefrl3::random_map::generate_map;
+
+const USAGE: &str = "usage: tiled_convert export LEVEL SEED OUT.json\n       tiled_convert import IN.json OUT.txt";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+
+    let result = match args[..] {
+        ["export", level, seed, path_out] => export(level, seed, path_out),
+        ["import", path_in, path_out] => import(path_in, path_out),
+        _ => Err(USAGE.to_string()),
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
+
+fn export(level: &str, seed: &str, path_out: &str) -> Result<(), String> {
+    let level: usize = level.parse().map_err(|_| format!("bad level number \"{}\"", level))?;
+    let seed: u64 = seed.parse().map_err(|_| format!("bad seed \"{}\"", seed))?;
+
+    let mut rng = MyRng::seed_from_u64(seed);
+    let map = generate_map(&mut rng, level);
+
+    std::fs::write(path_out, map.to_tiled_json("tiles.png")).map_err(|err| format!("{}: {}", path_out, err))
+}
+
+fn import(path_in: &str, path_out: &str) -> Result<(), String> {
+    let text = std::fs::read_to_string(path_in).map_err(|err| format!("{}: {}", path_in, err))?;
+    let map = Map::from_tiled_json(&text).map_err(|err| format!("{}: {}", path_in, err))?;
+
+    std::fs::write(path_out, map.to_ascii()).map_err(|err| format!("{}: {}", path_out, err))
+}
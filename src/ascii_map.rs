@@ -0,0 +1,1175 @@
+// A plain-text form of a level, for checking fixture levels into the repository
+// and for diffing what the generator makes. It looks like this:
+//
+//     thiefrl3-map 1
+//     cells
+//     ...........
+//     .#-#######.
+//     .#__#::::#.
+//     .#__|::::#.
+//     .#-###-###.
+//     .#__#::::#.
+//     .#__|::::#.
+//     .#########.
+//     ...........
+//     items
+//     ...........
+//     ..L........
+//     ..t....$...
+//     ....+......
+//     ..+...+....
+//     .....>.....
+//     ....+..h...
+//     ...........
+//     ......@....
+//     candle 2 6
+//     torch 7 4 0 -1
+//     key 9 8 door 2 7
+//     moonlit 0 0 11 1
+//
+// The first grid has one character per cell type and the second shows what is
+// on each cell, with "." for nothing; the characters for each are listed below.
+// Guards are drawn as arrows the way they face, and "@" marks the start. The
+// top row of each grid is the highest y.
+// After the grids come the things that don't fit in a grid, one per line:
+//
+//     torch X Y DX DY [out]       a wall-mounted torch shining toward DX DY
+//     candle X Y [out]            a candle, usually on a table
+//     key X Y door X Y            a key lying loose, and the locked door it opens
+//     guard-key X Y door X Y      the key carried by the guard standing at X Y
+//     moonlit X0 Y0 X1 Y1         cells open to the sky, X1 and Y1 exclusive
+//     region X0 Y0 X1 Y1          a patrol region, numbered from 0 in order
+//     route A B                   guards may walk between regions A and B
+//     item C X Y                  a second item on a cell, C as in the items grid
+//     guard C X Y                 a guard on a cell that already shows an item
+//
+// Only the layout is kept. What has been seen and what is lit are worked out
+// again when the map is read back, and guards start on patrol. A map with no
+// region lines gets patrol regions made from its rooms; see find_patrol_regions.
+
+use crate::cell_grid::*;
+use crate::random_map::{cache_cell_info, fixup_walls, is_wall, mark_exterior_as_seen};
+use multiarray::Array2D;
+use std::fmt;
+
+pub const ASCII_MAP_VERSION: u32 = 1;
+
+const HEADER: &str = "thiefrl3-map";
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct ParseMapError {
+    pub line: usize, // 1-based line of the text where the problem was found
+    pub reason: String,
+}
+
+impl fmt::Display for ParseMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl std::error::Error for ParseMapError {}
+
+fn parse_error(line: usize, reason: String) -> ParseMapError {
+    ParseMapError { line, reason }
+}
+
+// Walls are all written the same way; which way each one joins up to its
+// neighbors is recomputed on reading.
+
+pub fn char_for_cell_type(cell_type: CellType) -> char {
+    match cell_type {
+        CellType::GroundNormal     => '.',
+        CellType::GroundGrass      => ',',
+        CellType::GroundWater      => '~',
+        CellType::GroundMarble     => ':',
+        CellType::GroundWood       => '_',
+        CellType::GroundWoodCreaky => ';',
+
+        CellType::Wall0000 |
+        CellType::Wall0001 |
+        CellType::Wall0010 |
+        CellType::Wall0011 |
+        CellType::Wall0100 |
+        CellType::Wall0101 |
+        CellType::Wall0110 |
+        CellType::Wall0111 |
+        CellType::Wall1000 |
+        CellType::Wall1001 |
+        CellType::Wall1010 |
+        CellType::Wall1011 |
+        CellType::Wall1100 |
+        CellType::Wall1101 |
+        CellType::Wall1110 |
+        CellType::Wall1111 => '#',
+
+        CellType::OneWayWindowE => 'E',
+        CellType::OneWayWindowW => 'W',
+        CellType::OneWayWindowN => 'N',
+        CellType::OneWayWindowS => 'S',
+        CellType::PortcullisNS  => '!',
+        CellType::PortcullisEW  => '=',
+        CellType::DoorNS        => '|',
+        CellType::DoorEW        => '-',
+    }
+}
+
+pub fn cell_type_for_char(c: char) -> Option<CellType> {
+    match c {
+        '.' => Some(CellType::GroundNormal),
+        ',' => Some(CellType::GroundGrass),
+        '~' => Some(CellType::GroundWater),
+        ':' => Some(CellType::GroundMarble),
+        '_' => Some(CellType::GroundWood),
+        ';' => Some(CellType::GroundWoodCreaky),
+        '#' => Some(CellType::Wall0000),
+        'E' => Some(CellType::OneWayWindowE),
+        'W' => Some(CellType::OneWayWindowW),
+        'N' => Some(CellType::OneWayWindowN),
+        'S' => Some(CellType::OneWayWindowS),
+        '!' => Some(CellType::PortcullisNS),
+        '=' => Some(CellType::PortcullisEW),
+        '|' => Some(CellType::DoorNS),
+        '-' => Some(CellType::DoorEW),
+        _ => None,
+    }
+}
+
+// Doors and portcullises take their orientation from the cell they are in.
+
+pub fn char_for_item_kind(kind: ItemKind) -> char {
+    match kind {
+        ItemKind::Chair => 'h',
+        ItemKind::Table => 't',
+        ItemKind::Bush => 'b',
+        ItemKind::Coin => '$',
+        ItemKind::DoorNS | ItemKind::DoorEW => '+',
+        ItemKind::DoorOpenNS | ItemKind::DoorOpenEW => '/',
+        ItemKind::DoorLockedNS | ItemKind::DoorLockedEW => 'L',
+        ItemKind::PortcullisNS | ItemKind::PortcullisEW => 'p',
+        ItemKind::PortcullisLoweredNS | ItemKind::PortcullisLoweredEW => 'P',
+        ItemKind::Winch => 'w',
+        ItemKind::Stones => 's',
+        ItemKind::SmokeBomb => 'm',
+        ItemKind::WaterFlask => 'f',
+    }
+}
+
+pub fn item_kind_for_char(c: char, cell_type: CellType) -> Option<ItemKind> {
+    let ns = cell_type == CellType::DoorNS || cell_type == CellType::PortcullisNS;
+    match c {
+        'h' => Some(ItemKind::Chair),
+        't' => Some(ItemKind::Table),
+        'b' => Some(ItemKind::Bush),
+        '$' => Some(ItemKind::Coin),
+        '+' => Some(if ns {ItemKind::DoorNS} else {ItemKind::DoorEW}),
+        '/' => Some(if ns {ItemKind::DoorOpenNS} else {ItemKind::DoorOpenEW}),
+        'L' => Some(if ns {ItemKind::DoorLockedNS} else {ItemKind::DoorLockedEW}),
+        'p' => Some(if ns {ItemKind::PortcullisNS} else {ItemKind::PortcullisEW}),
+        'P' => Some(if ns {ItemKind::PortcullisLoweredNS} else {ItemKind::PortcullisLoweredEW}),
+        'w' => Some(ItemKind::Winch),
+        's' => Some(ItemKind::Stones),
+        'm' => Some(ItemKind::SmokeBomb),
+        'f' => Some(ItemKind::WaterFlask),
+        _ => None,
+    }
+}
+
+pub const CHAR_START: char = '@';
+pub const CHAR_NOTHING: char = '.';
+
+pub fn char_for_guard_dir(dir: Point) -> char {
+    if dir.y > 0 {'^'}
+    else if dir.y < 0 {'v'}
+    else if dir.x < 0 {'<'}
+    else {'>'}
+}
+
+pub fn guard_dir_for_char(c: char) -> Option<Point> {
+    match c {
+        '^' => Some(Point::new(0, 1)),
+        'v' => Some(Point::new(0, -1)),
+        '>' => Some(Point::new(1, 0)),
+        '<' => Some(Point::new(-1, 0)),
+        _ => None,
+    }
+}
+
+fn door_cell(cell_type: CellType) -> bool {
+    matches!(cell_type, CellType::DoorNS | CellType::DoorEW)
+}
+
+fn portcullis_cell(cell_type: CellType) -> bool {
+    matches!(cell_type, CellType::PortcullisNS | CellType::PortcullisEW)
+}
+
+fn window_cell(cell_type: CellType) -> bool {
+    matches!(cell_type,
+        CellType::OneWayWindowE | CellType::OneWayWindowW | CellType::OneWayWindowN | CellType::OneWayWindowS)
+}
+
+fn floor_cell(cell_type: CellType) -> bool {
+    matches!(cell_type,
+        CellType::GroundNormal | CellType::GroundGrass | CellType::GroundWater |
+        CellType::GroundMarble | CellType::GroundWood | CellType::GroundWoodCreaky)
+}
+
+impl Map {
+
+pub fn to_ascii(&self) -> String {
+    let size_x = self.cells.extents()[0];
+    let size_y = self.cells.extents()[1];
+
+    let mut overlay: Array2D<char> = Array2D::new([size_x, size_y], CHAR_NOTHING);
+    let mut extra_lines: Vec<String> = Vec::new();
+
+    overlay[[self.pos_start.x as usize, self.pos_start.y as usize]] = CHAR_START;
+
+    for item in &self.items {
+        let c = char_for_item_kind(item.kind);
+        let p = [item.pos.x as usize, item.pos.y as usize];
+        if overlay[p] == CHAR_NOTHING {
+            overlay[p] = c;
+        } else {
+            extra_lines.push(format!("item {} {} {}", c, item.pos.x, item.pos.y));
+        }
+    }
+
+    for guard in &self.guards {
+        let c = char_for_guard_dir(guard.dir);
+        let p = [guard.pos.x as usize, guard.pos.y as usize];
+        if overlay[p] == CHAR_NOTHING {
+            overlay[p] = c;
+        } else {
+            extra_lines.push(format!("guard {} {} {}", c, guard.pos.x, guard.pos.y));
+        }
+    }
+
+    let mut s = format!("{} {}\n", HEADER, ASCII_MAP_VERSION);
+
+    s.push_str("cells\n");
+    for y in (0..size_y).rev() {
+        for x in 0..size_x {
+            s.push(char_for_cell_type(self.cells[[x, y]].cell_type));
+        }
+        s.push('\n');
+    }
+
+    s.push_str("items\n");
+    for y in (0..size_y).rev() {
+        for x in 0..size_x {
+            s.push(overlay[[x, y]]);
+        }
+        s.push('\n');
+    }
+
+    for light in &self.lights {
+        let out = if light.lit {""} else {" out"};
+        match light.kind {
+            LightKind::Torch => s.push_str(&format!("torch {} {} {} {}{}\n", light.pos.x, light.pos.y, light.dir.x, light.dir.y, out)),
+            LightKind::Candle => s.push_str(&format!("candle {} {}{}\n", light.pos.x, light.pos.y, out)),
+        }
+    }
+
+    for key in &self.keys {
+        s.push_str(&format!("key {} {} door {} {}\n", key.pos.x, key.pos.y, key.door.x, key.door.y));
+    }
+
+    // Guards are read back in grid order, top row first, so their keys are
+    // listed in that order too.
+
+    let mut guards_with_keys: Vec<&Guard> = self.guards.iter().filter(|guard| guard.key.is_some()).collect();
+    guards_with_keys.sort_by_key(|guard| (-guard.pos.y, guard.pos.x));
+
+    for guard in guards_with_keys {
+        if let Some(key) = &guard.key {
+            s.push_str(&format!("guard-key {} {} door {} {}\n", guard.pos.x, guard.pos.y, key.door.x, key.door.y));
+        }
+    }
+
+    let moonlit: Array2D<bool> = {
+        let mut moonlit = Array2D::new([size_x, size_y], false);
+        for x in 0..size_x {
+            for y in 0..size_y {
+                moonlit[[x, y]] = self.cells[[x, y]].moonlit;
+            }
+        }
+        moonlit
+    };
+
+    for rect in cover_with_rects(&moonlit) {
+        s.push_str(&format!("moonlit {} {} {} {}\n", rect.pos_min.x, rect.pos_min.y, rect.pos_max.x, rect.pos_max.y));
+    }
+
+    for rect in &self.patrol_regions {
+        s.push_str(&format!("region {} {} {} {}\n", rect.pos_min.x, rect.pos_min.y, rect.pos_max.x, rect.pos_max.y));
+    }
+
+    for &(region0, region1) in &self.patrol_routes {
+        s.push_str(&format!("route {} {}\n", region0, region1));
+    }
+
+    for line in extra_lines {
+        s.push_str(&line);
+        s.push('\n');
+    }
+
+    s
+}
+
+pub fn from_ascii(text: &str) -> Result<Map, ParseMapError> {
+    let mut lines = text.lines().enumerate().map(|(i, line)| (i + 1, line.trim_end()));
+
+    match lines.next() {
+        Some((_, line)) if line == format!("{} {}", HEADER, ASCII_MAP_VERSION) => (),
+        Some((i, line)) => return Err(parse_error(i, format!("expected \"{} {}\" but found \"{}\"", HEADER, ASCII_MAP_VERSION, line))),
+        None => return Err(parse_error(1, "the text is empty".to_string())),
+    }
+
+    match lines.next() {
+        Some((_, "cells")) => (),
+        Some((i, _)) => return Err(parse_error(i, "expected \"cells\"".to_string())),
+        None => return Err(parse_error(2, "expected \"cells\"".to_string())),
+    }
+
+    // Read the cells grid, which runs up to the "items" line.
+
+    let mut cell_rows: Vec<(usize, &str)> = Vec::new();
+    let line_items = loop {
+        match lines.next() {
+            Some((i, "items")) => break i,
+            Some((i, line)) => cell_rows.push((i, line)),
+            None => return Err(parse_error(text.lines().count() + 1, "expected \"items\" after the cells".to_string())),
+        }
+    };
+
+    if cell_rows.is_empty() {
+        return Err(parse_error(line_items, "the map has no rows".to_string()));
+    }
+
+    let size_x = cell_rows[0].1.chars().count();
+    let size_y = cell_rows.len();
+
+    if size_x == 0 {
+        return Err(parse_error(cell_rows[0].0, "the map has no columns".to_string()));
+    }
+
+    let mut cell_types: Array2D<CellType> = Array2D::new([size_x, size_y], CellType::GroundNormal);
+
+    for (row, &(i, line)) in cell_rows.iter().enumerate() {
+        let y = size_y - 1 - row;
+        if line.chars().count() != size_x {
+            return Err(parse_error(i, format!("row is {} characters wide but the first row is {}", line.chars().count(), size_x)));
+        }
+        for (x, c) in line.chars().enumerate() {
+            cell_types[[x, y]] = match cell_type_for_char(c) {
+                Some(cell_type) => cell_type,
+                None => return Err(parse_error(i, format!("'{}' at ({}, {}) is not a cell type", c, x, y))),
+            };
+        }
+    }
+
+    let mut map = new_map(&cell_types);
+
+    // Read the items grid, the same size as the cells grid.
+
+    let mut found_start = false;
+    let mut regions: Vec<(usize, Rect)> = Vec::new();
+    let mut routes: Vec<(usize, usize, usize)> = Vec::new();
+
+    for row in 0..size_y {
+        let y = size_y - 1 - row;
+        let (i, line) = match lines.next() {
+            Some(line) => line,
+            None => return Err(parse_error(line_items + row + 1, format!("the items grid needs {} rows", size_y))),
+        };
+        if line.chars().count() != size_x {
+            return Err(parse_error(i, format!("row is {} characters wide but the map is {}", line.chars().count(), size_x)));
+        }
+        for (x, c) in line.chars().enumerate() {
+            let pos = Point::new(x as i32, y as i32);
+            if c == CHAR_NOTHING {
+                continue;
+            }
+            if c == CHAR_START {
+                if found_start {
+                    return Err(parse_error(i, format!("second start position at ({}, {})", x, y)));
+                }
+                if inside_wall(&map, pos) {
+                    return Err(parse_error(i, format!("start position at ({}, {}) is inside a wall", x, y)));
+                }
+                found_start = true;
+                map.pos_start = pos;
+                continue;
+            }
+            if let Some(dir) = guard_dir_for_char(c) {
+                add_guard(&mut map, i, pos, dir)?;
+                continue;
+            }
+            add_item(&mut map, i, c, pos)?;
+        }
+    }
+
+    if !found_start {
+        return Err(parse_error(line_items, format!("no start position ('{}') in the items grid", CHAR_START)));
+    }
+
+    // Everything else is one thing per line.
+
+    for (i, line) in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+
+        let point = |j: usize| -> Result<Point, ParseMapError> {
+            let coord = |k: usize| -> Result<i32, ParseMapError> {
+                words.get(k)
+                    .and_then(|word| word.parse::<i32>().ok())
+                    .ok_or_else(|| parse_error(i, format!("missing or bad number in \"{}\"", line)))
+            };
+            Ok(Point::new(coord(j)?, coord(j + 1)?))
+        };
+
+        let on_map = |pos: Point| -> Result<Point, ParseMapError> {
+            if pos.x < 0 || pos.y < 0 || pos.x >= size_x as i32 || pos.y >= size_y as i32 {
+                Err(parse_error(i, format!("({}, {}) is off the map", pos.x, pos.y)))
+            } else {
+                Ok(pos)
+            }
+        };
+
+        let expect_word = |j: usize, word: &str| -> Result<(), ParseMapError> {
+            if words.get(j) == Some(&word) {
+                Ok(())
+            } else {
+                Err(parse_error(i, format!("expected \"{}\"", word)))
+            }
+        };
+
+        let in_doorway = |pos: Point| -> Result<Point, ParseMapError> {
+            check_key_door(&map, pos).map(|()| pos).map_err(|reason| parse_error(i, reason))
+        };
+
+        let no_more_words = |j: usize| -> Result<(), ParseMapError> {
+            match words.get(j) {
+                None => Ok(()),
+                Some(word) => Err(parse_error(i, format!("unexpected \"{}\"", word))),
+            }
+        };
+
+        let lit = |j: usize| -> Result<bool, ParseMapError> {
+            match words.get(j) {
+                None => Ok(true),
+                Some(&"out") if words.len() == j + 1 => Ok(false),
+                Some(word) => Err(parse_error(i, format!("unexpected \"{}\"", word))),
+            }
+        };
+
+        match words[0] {
+            "torch" => {
+                let pos = on_map(point(1)?)?;
+                let dir = point(3)?;
+                let lit = lit(5)?;
+                check_torch(&map, pos, dir).map_err(|reason| parse_error(i, reason))?;
+                map.lights.push(Light { pos, dir, kind: LightKind::Torch, lit });
+            },
+            "candle" => {
+                let pos = on_map(point(1)?)?;
+                let lit = lit(3)?;
+                map.lights.push(Light { pos, dir: Point::new(0, 0), kind: LightKind::Candle, lit });
+            },
+            "key" => {
+                let pos = on_map(point(1)?)?;
+                expect_word(3, "door")?;
+                let door = in_doorway(on_map(point(4)?)?)?;
+                no_more_words(6)?;
+                map.keys.push(Key { pos, door });
+            },
+            "guard-key" => {
+                let pos = on_map(point(1)?)?;
+                expect_word(3, "door")?;
+                let door = in_doorway(on_map(point(4)?)?)?;
+                no_more_words(6)?;
+                match map.guards.iter_mut().find(|guard| guard.pos == pos) {
+                    Some(guard) => guard.key = Some(Key { pos, door }),
+                    None => return Err(parse_error(i, format!("no guard at ({}, {}) to carry the key", pos.x, pos.y))),
+                }
+            },
+            "moonlit" => {
+                let pos_min = on_map(point(1)?)?;
+                let pos_max = point(3)?;
+                no_more_words(5)?;
+                if pos_max.x < pos_min.x || pos_max.y < pos_min.y || pos_max.x > size_x as i32 || pos_max.y > size_y as i32 {
+                    return Err(parse_error(i, format!("the moonlit rectangle from ({}, {}) to ({}, {}) is off the map", pos_min.x, pos_min.y, pos_max.x, pos_max.y)));
+                }
+                for x in pos_min.x..pos_max.x {
+                    for y in pos_min.y..pos_max.y {
+                        map.cells[[x as usize, y as usize]].moonlit = true;
+                    }
+                }
+            },
+            "region" => {
+                let pos_min = on_map(point(1)?)?;
+                let pos_max = point(3)?;
+                no_more_words(5)?;
+                if pos_max.x <= pos_min.x || pos_max.y <= pos_min.y || pos_max.x > size_x as i32 || pos_max.y > size_y as i32 {
+                    return Err(parse_error(i, format!("region {} from ({}, {}) to ({}, {}) is empty or runs off the map", regions.len(), pos_min.x, pos_min.y, pos_max.x, pos_max.y)));
+                }
+                regions.push((i, Rect { pos_min, pos_max }));
+            },
+            "route" => {
+                let region = |j: usize| -> Result<usize, ParseMapError> {
+                    words.get(j)
+                        .and_then(|word| word.parse::<usize>().ok())
+                        .ok_or_else(|| parse_error(i, format!("missing or bad region number in \"{}\"", line)))
+                };
+                let (region0, region1) = (region(1)?, region(2)?);
+                no_more_words(3)?;
+                routes.push((i, region0, region1));
+            },
+            "item" | "guard" => {
+                let c = match words.get(1).map(|word| word.chars().collect::<Vec<char>>()) {
+                    Some(chars) if chars.len() == 1 => chars[0],
+                    _ => return Err(parse_error(i, format!("expected a single character after \"{}\"", words[0]))),
+                };
+                let pos = on_map(point(2)?)?;
+                no_more_words(4)?;
+                if words[0] == "item" {
+                    add_item(&mut map, i, c, pos)?;
+                } else {
+                    match guard_dir_for_char(c) {
+                        Some(dir) => add_guard(&mut map, i, pos, dir)?,
+                        None => return Err(parse_error(i, format!("'{}' is not a guard", c))),
+                    }
+                }
+            },
+            word => return Err(parse_error(i, format!("unknown line \"{}\"", word))),
+        }
+    }
+
+    if regions.is_empty() {
+        if let Some(&(i, _, _)) = routes.first() {
+            return Err(parse_error(i, "route given but there are no regions".to_string()));
+        }
+        find_patrol_regions(&mut map);
+    } else {
+        let region_rects: Vec<Rect> = regions.iter().map(|&(_, rect)| rect).collect();
+        let region_routes: Vec<(usize, usize)> = routes.iter().map(|&(_, region0, region1)| (region0, region1)).collect();
+        match set_patrol_regions(&mut map, &region_rects, &region_routes) {
+            Ok(()) => (),
+            Err(PatrolError::Region(i_region, reason)) => return Err(parse_error(regions[i_region].0, reason)),
+            Err(PatrolError::Route(i_route, reason)) => return Err(parse_error(routes[i_route].0, reason)),
+        }
+    }
+
+    if !map.guards.is_empty() && map.patrol_regions.is_empty() {
+        return Err(parse_error(line_items, "there are guards but nowhere for them to patrol".to_string()));
+    }
+
+    finish_map(&mut map);
+
+    Ok(map)
+}
+
+}
+
+fn add_item(map: &mut Map, line: usize, c: char, pos: Point) -> Result<(), ParseMapError> {
+    let cell_type = map.cells[[pos.x as usize, pos.y as usize]].cell_type;
+    let kind = match item_kind_for_char(c, cell_type) {
+        Some(kind) => kind,
+        None => return Err(parse_error(line, format!("'{}' at ({}, {}) is not an item", c, pos.x, pos.y))),
+    };
+
+    check_item_placement(map, kind, pos).map_err(|reason| parse_error(line, reason))?;
+
+    map.items.push(Item { pos, kind });
+    Ok(())
+}
+
+fn add_guard(map: &mut Map, line: usize, pos: Point, dir: Point) -> Result<(), ParseMapError> {
+    if inside_wall(map, pos) {
+        return Err(parse_error(line, format!("guard at ({}, {}) is inside a wall", pos.x, pos.y)));
+    }
+
+    map.guards.push(Guard::new(pos, dir));
+    Ok(())
+}
+
+// The steps below are shared with the other level formats, which read the
+// same things out of a different layout.
+
+// A map with the given cells and nothing else. Doors and windows join up with
+// the walls around them, so they go in as plain walls until the walls are
+// fixed up, as when generating a map.
+
+pub fn new_map(cell_types: &Array2D<CellType>) -> Map {
+    let size_x = cell_types.extents()[0];
+    let size_y = cell_types.extents()[1];
+
+    let default_cell = Cell {
+        cell_type: CellType::GroundNormal,
+        move_cost: 0,
+        region: INVALID_REGION,
+        blocks_player: false,
+        blocks_player_sight: false,
+        blocks_sight: false,
+        blocks_sound: false,
+        hides_player: false,
+        smoke: 0,
+        moonlit: false,
+        lit: false,
+        seen: false,
+    };
+
+    let mut cells = CellGrid::new([size_x, size_y], default_cell);
+
+    for x in 0..size_x {
+        for y in 0..size_y {
+            cells[[x, y]].cell_type = if is_wall(cell_types[[x, y]]) {CellType::Wall0000} else {cell_types[[x, y]]};
+        }
+    }
+
+    fixup_walls(&mut cells);
+
+    for x in 0..size_x {
+        for y in 0..size_y {
+            if door_cell(cell_types[[x, y]]) || portcullis_cell(cell_types[[x, y]]) || window_cell(cell_types[[x, y]]) {
+                cells[[x, y]].cell_type = cell_types[[x, y]];
+            }
+        }
+    }
+
+    Map {
+        cells,
+        items: Vec::new(),
+        lights: Vec::new(),
+        keys: Vec::new(),
+        patrol_regions: Vec::new(),
+        patrol_routes: Vec::new(),
+        guards: Vec::new(),
+        pos_start: Point::new(0, 0),
+        total_loot: 0,
+        alarm_raised: false,
+        distance_fields: DistanceFieldCache::default(),
+    }
+}
+
+pub fn inside_wall(map: &Map, pos: Point) -> bool {
+    tile_def(map.cells[[pos.x as usize, pos.y as usize]].cell_type).blocks_player
+}
+
+pub fn check_item_placement(map: &Map, kind: ItemKind, pos: Point) -> Result<(), String> {
+    let cell_type = map.cells[[pos.x as usize, pos.y as usize]].cell_type;
+
+    if is_door(kind) && !door_cell(cell_type) {
+        return Err(format!("door at ({}, {}) is not in a doorway", pos.x, pos.y));
+    }
+
+    if is_portcullis(kind) && !portcullis_cell(cell_type) {
+        return Err(format!("portcullis at ({}, {}) is not in a portcullis gateway", pos.x, pos.y));
+    }
+
+    Ok(())
+}
+
+// A key has to open a locked door, so the locked doors go in before the keys.
+
+pub fn check_key_door(map: &Map, door: Point) -> Result<(), String> {
+    if !door_cell(map.cells[[door.x as usize, door.y as usize]].cell_type) {
+        return Err(format!("({}, {}) is not a doorway for the key to open", door.x, door.y));
+    }
+
+    if !map.items.iter().any(|item| item.pos == door && matches!(item.kind, ItemKind::DoorLockedNS | ItemKind::DoorLockedEW)) {
+        return Err(format!("the door at ({}, {}) is not locked", door.x, door.y));
+    }
+
+    Ok(())
+}
+
+// A torch shines one cell along a wall, and a guard relighting it walks to
+// that cell, so it has to be a cell on the map that a guard can stand in.
+
+pub fn check_torch(map: &Map, pos: Point, dir: Point) -> Result<(), String> {
+    if dir.x.abs() + dir.y.abs() != 1 {
+        return Err(format!("the torch at ({}, {}) must shine one step north, south, east or west, not ({}, {})", pos.x, pos.y, dir.x, dir.y));
+    }
+
+    let pos_lit = pos + dir;
+    let (size_x, size_y) = (map.cells.extents()[0], map.cells.extents()[1]);
+    if pos_lit.x < 0 || pos_lit.y < 0 || pos_lit.x >= size_x as i32 || pos_lit.y >= size_y as i32 {
+        return Err(format!("the torch at ({}, {}) shines off the map", pos.x, pos.y));
+    }
+
+    if inside_wall(map, pos_lit) {
+        return Err(format!("the torch at ({}, {}) shines into the wall at ({}, {})", pos.x, pos.y, pos_lit.x, pos_lit.y));
+    }
+
+    Ok(())
+}
+
+// Which listed region or route was wrong, and how.
+
+pub enum PatrolError {
+    Region(usize, String),
+    Route(usize, String),
+}
+
+// Install patrol regions and routes as listed, checking that regions don't
+// overlap and that routes join regions that exist.
+
+pub fn set_patrol_regions(map: &mut Map, regions: &[Rect], routes: &[(usize, usize)]) -> Result<(), PatrolError> {
+    for (i_region, &rect) in regions.iter().enumerate() {
+        for x in rect.pos_min.x..rect.pos_max.x {
+            for y in rect.pos_min.y..rect.pos_max.y {
+                let cell = &mut map.cells[[x as usize, y as usize]];
+                if cell.region != INVALID_REGION {
+                    return Err(PatrolError::Region(i_region, format!("region {} overlaps region {} at ({}, {})", i_region, cell.region, x, y)));
+                }
+                cell.region = i_region;
+            }
+        }
+        map.patrol_regions.push(rect);
+    }
+
+    for (i_route, &(region0, region1)) in routes.iter().enumerate() {
+        for &region in &[region0, region1] {
+            if region >= regions.len() {
+                return Err(PatrolError::Route(i_route, format!("route to region {} but there are only {} regions", region, regions.len())));
+            }
+        }
+        if region0 == region1 {
+            return Err(PatrolError::Route(i_route, format!("route from region {} to itself", region0)));
+        }
+        map.patrol_routes.push((region0, region1));
+    }
+
+    map.distance_fields.invalidate();
+    Ok(())
+}
+
+// Work out everything that follows from the layout, once the cells, items,
+// guards and patrol regions are all in.
+
+pub fn finish_map(map: &mut Map) {
+    cache_cell_info(map);
+
+    for i_guard in 0..map.guards.len() {
+        let region = map.closest_region(&map.guards[i_guard].pos);
+        map.guards[i_guard].region_goal = region;
+    }
+
+    mark_exterior_as_seen(map);
+    map.compute_lighting();
+
+    map.total_loot = map.items.iter().filter(|&item| item.kind == ItemKind::Coin).count();
+}
+
+// Split the marked cells into rectangles, taking the widest run from the
+// lowest unclaimed cell and then as many rows above it as fit.
+
+pub fn cover_with_rects(marked: &Array2D<bool>) -> Vec<Rect> {
+    let size_x = marked.extents()[0];
+    let size_y = marked.extents()[1];
+    let mut claimed = Array2D::new([size_x, size_y], false);
+    let mut rects = Vec::new();
+
+    for y in 0..size_y {
+        for x in 0..size_x {
+            if !marked[[x, y]] || claimed[[x, y]] {
+                continue;
+            }
+
+            let mut x_end = x + 1;
+            while x_end < size_x && marked[[x_end, y]] && !claimed[[x_end, y]] {
+                x_end += 1;
+            }
+
+            let mut y_end = y + 1;
+            while y_end < size_y && (x..x_end).all(|x| marked[[x, y_end]] && !claimed[[x, y_end]]) {
+                y_end += 1;
+            }
+
+            for x in x..x_end {
+                for y in y..y_end {
+                    claimed[[x, y]] = true;
+                }
+            }
+
+            rects.push(Rect {
+                pos_min: Point::new(x as i32, y as i32),
+                pos_max: Point::new(x_end as i32, y_end as i32),
+            });
+        }
+    }
+
+    rects
+}
+
+// Rebuild the patrol regions from the layout alone. The rooms are the areas of
+// floor closed off by walls, windows and doors; anything that reaches the edge
+// of the map is the grounds outside, which guards don't patrol. Each room is
+// split into rectangles, and routes join rectangles that touch and rooms that
+// share a door. Rooms with fewer than two ways onward are dropped, repeatedly,
+// so guards don't walk into dead ends.
+
+pub fn find_patrol_regions(map: &mut Map) {
+    let size_x = map.cells.extents()[0];
+    let size_y = map.cells.extents()[1];
+
+    // Find the rooms, marking off the outside.
+
+    let mut room: Array2D<usize> = Array2D::new([size_x, size_y], INVALID_REGION);
+    let mut room_outside: Vec<bool> = Vec::new();
+
+    for x in 0..size_x {
+        for y in 0..size_y {
+            if room[[x, y]] != INVALID_REGION || !floor_cell(map.cells[[x, y]].cell_type) {
+                continue;
+            }
+
+            let i_room = room_outside.len();
+            let mut outside = false;
+            let mut stack = vec![(x, y)];
+            room[[x, y]] = i_room;
+
+            while let Some((x, y)) = stack.pop() {
+                if x == 0 || y == 0 || x == size_x - 1 || y == size_y - 1 {
+                    outside = true;
+                }
+
+                let neighbors = [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)];
+                for &(nx, ny) in &neighbors {
+                    if nx < size_x && ny < size_y && room[[nx, ny]] == INVALID_REGION && floor_cell(map.cells[[nx, ny]].cell_type) {
+                        room[[nx, ny]] = i_room;
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+
+            room_outside.push(outside);
+        }
+    }
+
+    // Split the rooms into rectangles.
+
+    let mut region: Array2D<usize> = Array2D::new([size_x, size_y], INVALID_REGION);
+    let mut regions: Vec<Rect> = Vec::new();
+
+    for i_room in 0..room_outside.len() {
+        if room_outside[i_room] {
+            continue;
+        }
+
+        let mut in_room = Array2D::new([size_x, size_y], false);
+        for x in 0..size_x {
+            for y in 0..size_y {
+                in_room[[x, y]] = room[[x, y]] == i_room;
+            }
+        }
+
+        for rect in cover_with_rects(&in_room) {
+            for x in rect.pos_min.x..rect.pos_max.x {
+                for y in rect.pos_min.y..rect.pos_max.y {
+                    region[[x as usize, y as usize]] = regions.len();
+                }
+            }
+            regions.push(rect);
+        }
+    }
+
+    // Join regions that touch, or that face each other across a door.
+
+    let mut routes: Vec<(usize, usize)> = Vec::new();
+
+    let mut add_route = |region0: usize, region1: usize| {
+        if region0 == INVALID_REGION || region1 == INVALID_REGION || region0 == region1 {
+            return;
+        }
+        let route = min_max(region0, region1);
+        if !routes.contains(&route) {
+            routes.push(route);
+        }
+    };
+
+    for x in 0..size_x {
+        for y in 0..size_y {
+            if x + 1 < size_x {
+                add_route(region[[x, y]], region[[x + 1, y]]);
+            }
+            if y + 1 < size_y {
+                add_route(region[[x, y]], region[[x, y + 1]]);
+            }
+            if door_cell(map.cells[[x, y]].cell_type) && x > 0 && y > 0 && x + 1 < size_x && y + 1 < size_y {
+                add_route(region[[x - 1, y]], region[[x + 1, y]]);
+                add_route(region[[x, y - 1]], region[[x, y + 1]]);
+            }
+        }
+    }
+
+    // Trim dead ends.
+
+    let mut include = vec![true; regions.len()];
+
+    loop {
+        let mut trimmed = false;
+
+        for i_region in 0..regions.len() {
+            if !include[i_region] {
+                continue;
+            }
+
+            let num_exits = routes.iter()
+                .filter(|&&(region0, region1)| {
+                    (region0 == i_region && include[region1]) || (region1 == i_region && include[region0])
+                })
+                .count();
+
+            if num_exits < 2 {
+                include[i_region] = false;
+                trimmed = true;
+            }
+        }
+
+        if !trimmed {
+            break;
+        }
+    }
+
+    // Store what's left, renumbered.
+
+    let mut renumbered = vec![INVALID_REGION; regions.len()];
+
+    map.patrol_regions.clear();
+    map.patrol_routes.clear();
+
+    for x in 0..size_x {
+        for y in 0..size_y {
+            map.cells[[x, y]].region = INVALID_REGION;
+        }
+    }
+
+    for i_region in 0..regions.len() {
+        if !include[i_region] {
+            continue;
+        }
+
+        renumbered[i_region] = map.patrol_regions.len();
+
+        let rect = &regions[i_region];
+        for x in rect.pos_min.x..rect.pos_max.x {
+            for y in rect.pos_min.y..rect.pos_max.y {
+                map.cells[[x as usize, y as usize]].region = renumbered[i_region];
+            }
+        }
+
+        map.patrol_regions.push(Rect { pos_min: rect.pos_min, pos_max: rect.pos_max });
+    }
+
+    for &(region0, region1) in &routes {
+        if include[region0] && include[region1] {
+            map.patrol_routes.push((renumbered[region0], renumbered[region1]));
+        }
+    }
+
+    map.distance_fields.invalidate();
+}
+
+fn min_max(a: usize, b: usize) -> (usize, usize) {
+    if a < b {(a, b)} else {(b, a)}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random_map::generate_map;
+    use rand::SeedableRng;
+
+    // The example from the top of the file.
+
+    const EXAMPLE: &str = "\
+thiefrl3-map 1
+cells
+...........
+.#-#######.
+.#__#::::#.
+.#__|::::#.
+.#-###-###.
+.#__#::::#.
+.#__|::::#.
+.#########.
+...........
+items
+...........
+..L........
+..t....$...
+....+......
+..+...+....
+.....>.....
+....+..h...
+...........
+......@....
+candle 2 6
+torch 7 4 0 -1
+key 9 8 door 2 7
+moonlit 0 0 11 1
+";
+
+    // The example with one line swapped out. Lines are numbered from 1.
+
+    fn example_with(line: usize, replacement: &str) -> String {
+        let mut lines: Vec<&str> = EXAMPLE.lines().collect();
+        lines[line - 1] = replacement;
+        lines.join("\n")
+    }
+
+    fn example_plus(extra: &str) -> String {
+        format!("{}{}\n", EXAMPLE, extra)
+    }
+
+    fn parse_err(text: &str) -> ParseMapError {
+        match Map::from_ascii(text) {
+            Ok(_) => panic!("expected an error parsing:\n{}", text),
+            Err(err) => err,
+        }
+    }
+
+    // What has been seen isn't written out, so only the layout of the cells is
+    // compared.
+
+    fn assert_same_layout(a: &Map, b: &Map) {
+        assert_eq!(a.cells.extents(), b.cells.extents());
+        for x in 0..a.cells.extents()[0] {
+            for y in 0..a.cells.extents()[1] {
+                let (cell_a, cell_b) = (&a.cells[[x, y]], &b.cells[[x, y]]);
+                assert_eq!((cell_a.cell_type, cell_a.moonlit, cell_a.lit), (cell_b.cell_type, cell_b.moonlit, cell_b.lit), "cell ({}, {}) differs", x, y);
+            }
+        }
+
+        // Items and guards are read back in grid order, so sort them first.
+
+        let items = |map: &Map| -> Vec<(i32, i32, ItemKind)> {
+            let mut items: Vec<(i32, i32, ItemKind)> = map.items.iter().map(|item| (item.pos.x, item.pos.y, item.kind)).collect();
+            items.sort();
+            items
+        };
+        assert_eq!(items(a), items(b));
+
+        let guards = |map: &Map| -> Vec<(Point, Point, Option<Point>)> {
+            let mut guards: Vec<&Guard> = map.guards.iter().collect();
+            guards.sort_by_key(|guard| (guard.pos.x, guard.pos.y));
+            guards.iter().map(|guard| (guard.pos, guard.dir, guard.key.map(|key| key.door))).collect()
+        };
+        assert_eq!(guards(a), guards(b));
+
+        let regions = |map: &Map| -> Vec<(Point, Point)> {
+            map.patrol_regions.iter().map(|rect| (rect.pos_min, rect.pos_max)).collect()
+        };
+        assert_eq!(regions(a), regions(b));
+        assert_eq!(a.patrol_routes, b.patrol_routes);
+        assert_eq!(a.pos_start, b.pos_start);
+    }
+
+    #[test]
+    fn generated_maps_round_trip() {
+        for seed in 0..12 {
+            for level in 0..4 {
+                let mut rng = MyRng::seed_from_u64(seed);
+                let map = generate_map(&mut rng, level);
+                let text = map.to_ascii();
+                let map_read = Map::from_ascii(&text).unwrap_or_else(|err| panic!("seed {} level {}: {}", seed, level, err));
+                assert_same_layout(&map, &map_read);
+                assert_eq!(map_read.to_ascii(), text);
+            }
+        }
+    }
+
+    #[test]
+    fn level_files_parse() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/levels");
+        let mut num_files = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let text = std::fs::read_to_string(&path).unwrap();
+            if let Err(err) = Map::from_ascii(&text) {
+                panic!("{}: {}", path.display(), err);
+            }
+            num_files += 1;
+        }
+        assert!(num_files > 0);
+    }
+
+    #[test]
+    fn example_parses() {
+        let map = Map::from_ascii(EXAMPLE).unwrap();
+        assert_eq!(map.pos_start, Point::new(6, 0));
+        assert_eq!(map.guards.len(), 1);
+        assert_eq!(map.keys.len(), 1);
+    }
+
+    #[test]
+    fn bad_cell_reports_line_and_position() {
+        let err = parse_err(&example_with(5, ".#__#::?:#."));
+        assert_eq!(err, ParseMapError { line: 5, reason: "'?' at (7, 6) is not a cell type".to_string() });
+    }
+
+    #[test]
+    fn ragged_row_reports_line() {
+        let err = parse_err(&example_with(7, ".#-###-###"));
+        assert_eq!(err, ParseMapError { line: 7, reason: "row is 10 characters wide but the first row is 11".to_string() });
+    }
+
+    #[test]
+    fn second_start_reports_line_and_position() {
+        let err = parse_err(&example_with(18, "...@......."));
+        assert_eq!(err, ParseMapError { line: 21, reason: "second start position at (6, 0)".to_string() });
+    }
+
+    #[test]
+    fn bad_item_reports_line_and_position() {
+        let err = parse_err(&example_with(15, "..t....Q..."));
+        assert_eq!(err, ParseMapError { line: 15, reason: "'Q' at (7, 6) is not an item".to_string() });
+    }
+
+    #[test]
+    fn off_map_point_reports_line_and_position() {
+        let err = parse_err(&example_plus("candle 11 3"));
+        assert_eq!(err, ParseMapError { line: 26, reason: "(11, 3) is off the map".to_string() });
+    }
+
+    #[test]
+    fn key_must_open_a_locked_door() {
+        let err = parse_err(&example_with(24, "key 9 8 door 4 5"));
+        assert_eq!(err, ParseMapError { line: 24, reason: "the door at (4, 5) is not locked".to_string() });
+    }
+
+    #[test]
+    fn trailing_words_are_rejected() {
+        let err = parse_err(&example_with(24, "key 9 8 door 2 7 now"));
+        assert_eq!(err, ParseMapError { line: 24, reason: "unexpected \"now\"".to_string() });
+
+        let err = parse_err(&example_plus("route 0 1 2"));
+        assert_eq!(err, ParseMapError { line: 26, reason: "unexpected \"2\"".to_string() });
+    }
+
+    #[test]
+    fn torch_must_shine_into_an_open_cell() {
+        let err = parse_err(&example_with(23, "torch 7 4 1 -1"));
+        assert_eq!(err, ParseMapError { line: 23, reason: "the torch at (7, 4) must shine one step north, south, east or west, not (1, -1)".to_string() });
+
+        let err = parse_err(&example_with(23, "torch 7 4 1 0"));
+        assert_eq!(err, ParseMapError { line: 23, reason: "the torch at (7, 4) shines into the wall at (8, 4)".to_string() });
+
+        let err = parse_err(&example_with(23, "torch 10 4 1 0"));
+        assert_eq!(err, ParseMapError { line: 23, reason: "the torch at (10, 4) shines off the map".to_string() });
+    }
+
+    #[test]
+    fn bad_rectangles_report_their_corners() {
+        let err = parse_err(&example_with(25, "moonlit 0 0 12 1"));
+        assert_eq!(err, ParseMapError { line: 25, reason: "the moonlit rectangle from (0, 0) to (12, 1) is off the map".to_string() });
+
+        let err = parse_err(&example_plus("region 3 3 3 5"));
+        assert_eq!(err, ParseMapError { line: 26, reason: "region 0 from (3, 3) to (3, 5) is empty or runs off the map".to_string() });
+    }
+}
@@ -0,0 +1,179 @@
+use crossterm::style::Color;
+use thiefrl3::cell_grid::*;
+use thiefrl3::color_preset;
+use thiefrl3::inventory::ToolKind;
+
+// Characters standing in for the tiles of the graphical version. Terminals that
+// can't show the box-drawing and symbol characters get plain ASCII instead.
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Charset {
+    Unicode,
+    Ascii,
+}
+
+// The palette is the sixteen colors of the old PC text modes, which are also
+// the sixteen ANSI colors, so each preset has a terminal color of its own.
+
+pub fn ansi_color(color: color_preset::Color) -> Color {
+    if color == color_preset::BLACK {Color::Black}
+    else if color == color_preset::DARK_BLUE {Color::DarkBlue}
+    else if color == color_preset::DARK_GREEN {Color::DarkGreen}
+    else if color == color_preset::DARK_CYAN {Color::DarkCyan}
+    else if color == color_preset::DARK_RED {Color::DarkRed}
+    else if color == color_preset::DARK_MAGENTA {Color::DarkMagenta}
+    else if color == color_preset::DARK_BROWN {Color::DarkYellow}
+    else if color == color_preset::LIGHT_GRAY {Color::Grey}
+    else if color == color_preset::DARK_GRAY {Color::DarkGrey}
+    else if color == color_preset::LIGHT_BLUE {Color::Blue}
+    else if color == color_preset::LIGHT_GREEN {Color::Green}
+    else if color == color_preset::LIGHT_CYAN {Color::Cyan}
+    else if color == color_preset::LIGHT_RED {Color::Red}
+    else if color == color_preset::LIGHT_MAGENTA {Color::Magenta}
+    else if color == color_preset::LIGHT_YELLOW {Color::Yellow}
+    else if color == color_preset::WHITE {Color::White}
+    else {
+        Color::Rgb {
+            r: (color.r * 255.0) as u8,
+            g: (color.g * 255.0) as u8,
+            b: (color.b * 255.0) as u8,
+        }
+    }
+}
+
+impl Charset {
+    fn pick(self, unicode: char, ascii: char) -> char {
+        match self {
+            Charset::Unicode => unicode,
+            Charset::Ascii => ascii,
+        }
+    }
+}
+
+pub fn char_for_cell(charset: Charset, cell_type: CellType) -> char {
+    let (unicode, ascii) = match cell_type {
+        CellType::GroundNormal     => ('.', '.'),
+        CellType::GroundGrass      => ('\u{201a}', ','), // low single quote
+        CellType::GroundWater      => ('\u{2248}', '~'), // almost equal to
+        CellType::GroundMarble     => ('.', '.'),
+        CellType::GroundWood       => ('.', '.'),
+        CellType::GroundWoodCreaky => ('.', '.'),
+
+                  //  NSEW
+        CellType::Wall0000 => ('\u{25a0}', '#'), // black square
+        CellType::Wall0001 => ('\u{2500}', '#'),
+        CellType::Wall0010 => ('\u{2500}', '#'),
+        CellType::Wall0011 => ('\u{2500}', '#'),
+        CellType::Wall0100 => ('\u{2502}', '#'),
+        CellType::Wall0101 => ('\u{2510}', '#'),
+        CellType::Wall0110 => ('\u{250c}', '#'),
+        CellType::Wall0111 => ('\u{252c}', '#'),
+        CellType::Wall1000 => ('\u{2502}', '#'),
+        CellType::Wall1001 => ('\u{2518}', '#'),
+        CellType::Wall1010 => ('\u{2514}', '#'),
+        CellType::Wall1011 => ('\u{2534}', '#'),
+        CellType::Wall1100 => ('\u{2502}', '#'),
+        CellType::Wall1101 => ('\u{2524}', '#'),
+        CellType::Wall1110 => ('\u{251c}', '#'),
+        CellType::Wall1111 => ('\u{253c}', '#'),
+
+        CellType::OneWayWindowE => ('=', '='),
+        CellType::OneWayWindowW => ('=', '='),
+        CellType::OneWayWindowN => ('=', '='),
+        CellType::OneWayWindowS => ('=', '='),
+        CellType::PortcullisNS  => ('.', '.'),
+        CellType::PortcullisEW  => ('.', '.'),
+        CellType::DoorNS        => ('.', '.'),
+        CellType::DoorEW        => ('.', '.'),
+    };
+    charset.pick(unicode, ascii)
+}
+
+pub fn char_for_item(charset: Charset, kind: ItemKind) -> char {
+    let (unicode, ascii) = match kind {
+        ItemKind::Chair => ('h', 'h'),
+        ItemKind::Table => ('\u{03c0}', 'T'), // pi
+        ItemKind::Bush => ('\u{2663}', '%'), // club suit
+        ItemKind::Coin => ('$', '$'),
+        ItemKind::DoorNS => ('+', '+'),
+        ItemKind::DoorEW => ('+', '+'),
+        ItemKind::DoorOpenNS => ('\'', '\''),
+        ItemKind::DoorOpenEW => ('\'', '\''),
+        ItemKind::DoorLockedNS => ('+', '+'),
+        ItemKind::DoorLockedEW => ('+', '+'),
+        ItemKind::PortcullisNS => ('\u{00a6}', '|'), // broken bar
+        ItemKind::PortcullisEW => ('\u{00a6}', '|'),
+        ItemKind::PortcullisLoweredNS => ('#', '#'),
+        ItemKind::PortcullisLoweredEW => ('#', '#'),
+        ItemKind::Winch => ('&', '&'),
+        ItemKind::Stones => (':', ':'),
+        ItemKind::SmokeBomb => ('o', 'o'),
+        ItemKind::WaterFlask => ('!', '!'),
+    };
+    charset.pick(unicode, ascii)
+}
+
+pub fn color_for_item(kind: ItemKind) -> color_preset::Color {
+    match kind {
+        ItemKind::Chair => color_preset::DARK_BROWN,
+        ItemKind::Table => color_preset::DARK_BROWN,
+        ItemKind::Bush => color_preset::DARK_GREEN,
+        ItemKind::Coin => color_preset::LIGHT_YELLOW,
+        ItemKind::DoorNS => color_preset::DARK_BROWN,
+        ItemKind::DoorEW => color_preset::DARK_BROWN,
+        ItemKind::DoorOpenNS => color_preset::DARK_BROWN,
+        ItemKind::DoorOpenEW => color_preset::DARK_BROWN,
+        ItemKind::DoorLockedNS => color_preset::DARK_RED,
+        ItemKind::DoorLockedEW => color_preset::DARK_RED,
+        ItemKind::PortcullisNS => color_preset::LIGHT_GRAY,
+        ItemKind::PortcullisEW => color_preset::LIGHT_GRAY,
+        ItemKind::PortcullisLoweredNS => color_preset::LIGHT_GRAY,
+        ItemKind::PortcullisLoweredEW => color_preset::LIGHT_GRAY,
+        ItemKind::Winch => color_preset::DARK_BROWN,
+        ItemKind::Stones => color_preset::LIGHT_GRAY,
+        ItemKind::SmokeBomb => color_preset::WHITE,
+        ItemKind::WaterFlask => color_preset::LIGHT_BLUE,
+    }
+}
+
+pub fn item_for_tool(tool: ToolKind) -> ItemKind {
+    match tool {
+        ToolKind::Stone => ItemKind::Stones,
+        ToolKind::SmokeBomb => ItemKind::SmokeBomb,
+        ToolKind::WaterFlask => ItemKind::WaterFlask,
+    }
+}
+
+pub fn char_for_light(kind: LightKind) -> char {
+    match kind {
+        LightKind::Torch => '*',
+        LightKind::Candle => '*',
+    }
+}
+
+pub const CHAR_KEY: char = 'k';
+pub const CHAR_PLAYER: char = '@';
+
+// Patrolling guards show which way they face, since that's what the thief
+// needs to know to sneak up on them. Guards on alert show their state instead.
+
+pub fn char_for_guard(charset: Charset, guard: &Guard) -> char {
+    match guard.mode {
+        GuardMode::Patrol => char_for_facing(charset, guard.dir),
+        GuardMode::ChaseVisibleTarget => '!',
+        GuardMode::Unconscious => 'z',
+        _ => '?',
+    }
+}
+
+fn char_for_facing(charset: Charset, dir: Point) -> char {
+    if dir.y > 0 {charset.pick('\u{25b2}', '^')}
+    else if dir.y < 0 {charset.pick('\u{25bc}', 'v')}
+    else if dir.x > 0 {charset.pick('\u{25b6}', '>')}
+    else if dir.x < 0 {charset.pick('\u{25c0}', '<')}
+    else {charset.pick('\u{25bc}', 'v')}
+}
+
+pub fn health_chars(charset: Charset) -> (char, char) {
+    (charset.pick('\u{2665}', '*'), charset.pick('\u{00b7}', '-')) // heart, middle dot
+}
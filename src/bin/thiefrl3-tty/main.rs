@@ -0,0 +1,331 @@
+// A terminal frontend, for playing over SSH or anywhere else without a window.
+// It runs the same game as the graphical version and shares its saves and
+// replays, drawing the map with characters and ANSI colors instead of tiles.
+//
+//     cargo run --bin thiefrl3-tty [-- --ascii] [--level FILE]
+//
+// With --level it starts a new game on a level file, as described in
+// ascii_map, instead of continuing the saved one.
+
+mod glyphs;
+mod render;
+mod storage;
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyEventState, KeyModifiers,
+        KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags},
+    execute,
+    style::ResetColor,
+    terminal::{self, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use std::cmp::min;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use thiefrl3::color_preset;
+use thiefrl3::game::*;
+use thiefrl3::help_text::HELP_PAGES;
+use thiefrl3::keymap::{self, command_for_key, Command, Key};
+use thiefrl3::session::*;
+use crate::glyphs::Charset;
+use crate::render::*;
+use crate::storage::FileStorage;
+
+// Said on top of the shared help pages: what the characters on the map stand
+// for, and how to get the keypad to move.
+
+static TTY_HELP_LINES: &[&str] = &[
+    "@ is you. Guards point the way they are facing while on patrol, and show",
+    "? when searching, ! when chasing and z when knocked out. $ is gold, k a key,",
+    "+ a door (red if locked) and * a torch or candle.",
+    "",
+    "Terminals that can't tell the keypad from the digits above the letters",
+    "give the digits to the tools; turn Num Lock off to move with the keypad.",
+    "Ctrl+C quits, like Escape.",
+];
+
+struct App {
+    session: Session, // errors saving are reported once the terminal is back to normal
+    charset: Charset,
+    help_scroll: Option<usize>, // lines scrolled down in the help view, if it is open
+    history_scroll: Option<usize>, // messages scrolled back in the history view, if it is open
+    show_vision: bool, // shade the cells the guards in view could see the thief in
+    quit: bool,
+}
+
+fn main() {
+    let mut charset = Charset::Unicode;
+    let mut level_file: Option<PathBuf> = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ascii" => charset = Charset::Ascii,
+            "--level" => match args.next() {
+                Some(path) => level_file = Some(PathBuf::from(path)),
+                None => usage(),
+            },
+            _ => usage(),
+        }
+    }
+
+    let session = match level_file {
+        Some(path) => Session::on_level_file(Box::new(FileStorage), &path).unwrap_or_else(|err| {
+            eprintln!("{}: {}", path.display(), err);
+            std::process::exit(1);
+        }),
+        None => Session::continue_or_new(Box::new(FileStorage)),
+    };
+
+    let mut app = App {
+        session,
+        charset,
+        help_scroll: None,
+        history_scroll: None,
+        show_vision: false,
+        quit: false,
+    };
+
+    // Put the terminal back the way it was even if the game panics, so the
+    // message is readable.
+
+    let default_panic = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let _ = restore_terminal(&mut io::stdout());
+        default_panic(info);
+    }));
+
+    let mut stdout = io::stdout();
+
+    let result = setup_terminal(&mut stdout).and_then(|()| app.run(&mut stdout));
+
+    app.session.save_game();
+
+    if let Err(err) = restore_terminal(&mut stdout) {
+        eprintln!("Could not restore the terminal: {}", err);
+    }
+
+    for err in &app.session.errors {
+        eprintln!("{}", err);
+    }
+
+    if let Err(err) = result {
+        eprintln!("Terminal error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("usage: thiefrl3-tty [--ascii] [--level FILE]");
+    std::process::exit(2);
+}
+
+// Terminals that can report keys unambiguously are asked to, so that the keypad
+// can be told from the digits above the letters.
+
+fn setup_terminal<W: Write>(out: &mut W) -> io::Result<()> {
+    terminal::enable_raw_mode()?;
+    execute!(out, EnterAlternateScreen, cursor::Hide, terminal::Clear(ClearType::All))?;
+    if terminal::supports_keyboard_enhancement()? {
+        execute!(out, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES))?;
+    }
+    Ok(())
+}
+
+fn restore_terminal<W: Write>(out: &mut W) -> io::Result<()> {
+    if terminal::supports_keyboard_enhancement().unwrap_or(false) {
+        execute!(out, PopKeyboardEnhancementFlags)?;
+    }
+    execute!(out, ResetColor, cursor::Show, LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()
+}
+
+impl App {
+    fn run<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        while !self.quit {
+            let (size_x, size_y) = terminal::size()?;
+            let mut screen = Screen::new(size_x as usize, size_y as usize);
+            self.draw(&mut screen);
+            screen.present(out)?;
+
+            match event::read()? {
+                Event::Key(key) if key.kind != KeyEventKind::Release => self.key_pressed(key, &screen),
+                Event::Resize(..) => execute!(out, terminal::Clear(ClearType::All))?,
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn key_pressed(&mut self, event: KeyEvent, screen: &Screen) {
+        let ctrl_down = event.modifiers.contains(KeyModifiers::CONTROL);
+
+        if ctrl_down && event.code == KeyCode::Char('c') {
+            self.quit = true;
+            return;
+        }
+
+        let key = match shared_key(&event) {
+            Some(key) => key,
+            None => return,
+        };
+
+        // The help screen swallows key presses until it is closed.
+
+        if let Some(scroll) = self.help_scroll {
+            let scroll_max = help_lines().len().saturating_sub(page_lines(screen));
+            match key {
+                Key::Numpad(8) | Key::Up   => self.help_scroll = Some(scroll.saturating_sub(1)),
+                Key::Numpad(2) | Key::Down => self.help_scroll = Some(min(scroll + 1, scroll_max)),
+                Key::Numpad(9) | Key::PageUp   => self.help_scroll = Some(scroll.saturating_sub(page_lines(screen))),
+                Key::Numpad(3) | Key::PageDown => self.help_scroll = Some(min(scroll + page_lines(screen), scroll_max)),
+                Key::F(1) | Key::Char('?') | Key::Escape => self.help_scroll = None,
+                _ => (),
+            }
+            return;
+        }
+
+        if let Some(scroll) = self.history_scroll {
+            let page_lines = page_lines(screen);
+            let scroll_max = self.session.message_log.messages.len().saturating_sub(1);
+            match key {
+                Key::Numpad(8) | Key::Up       => self.history_scroll = Some(min(scroll + 1, scroll_max)),
+                Key::Numpad(2) | Key::Down     => self.history_scroll = Some(scroll.saturating_sub(1)),
+                Key::Numpad(9) | Key::PageUp   => self.history_scroll = Some(min(scroll + page_lines, scroll_max)),
+                Key::Numpad(3) | Key::PageDown => self.history_scroll = Some(scroll.saturating_sub(page_lines)),
+                Key::Char('m') | Key::Escape   => self.history_scroll = None,
+                _ => (),
+            }
+            return;
+        }
+
+        // While aiming a tool the movement keys move the cursor. The throw is only
+        // made if it can reach the target.
+
+        if self.session.targeting.is_some() {
+            if let Some(dir) = keymap::direction_for_key(key) {
+                self.session.move_target(dir);
+            } else {
+                match key {
+                    Key::Enter | Key::Char(' ') => self.throw_at_target(),
+                    Key::Escape => self.session.targeting = None,
+                    _ => (),
+                }
+            }
+            return;
+        }
+
+        // After C, P, K or G, the next direction key picks the target; anything else cancels.
+
+        if self.session.direction_prompt.is_some() {
+            self.session.answer_direction_prompt(keymap::direction_for_key(key));
+            return;
+        }
+
+        match command_for_key(key, ctrl_down) {
+            Some(Command::Perform(action)) => self.perform(action),
+            Some(Command::Prompt(prompt)) => self.prompt_for_direction(prompt),
+            Some(Command::Throw(tool)) => self.session.start_targeting(tool),
+            Some(Command::Help) => self.help_scroll = Some(0),
+            Some(Command::History) => self.history_scroll = Some(0),
+            Some(Command::ToggleVision) => self.show_vision = !self.show_vision,
+            Some(Command::NewGame) => self.session.new_game(),
+            Some(Command::Quit) => self.quit = true,
+            None => (),
+        }
+    }
+
+    fn draw(&self, screen: &mut Screen) {
+        if let Some(scroll) = self.help_scroll {
+            draw_page(screen, "Help", &help_lines()[scroll..]);
+        } else if let Some(scroll) = self.history_scroll {
+            // scroll is how many messages back from the newest the bottom line of the page is.
+
+            let messages = &self.session.message_log.messages;
+            let lines: Vec<(String, color_preset::Color)> =
+                if messages.is_empty() {
+                    vec![("Nothing has happened yet.".to_string(), color_preset::LIGHT_GRAY)]
+                } else {
+                    let end = messages.len() - min(scroll, messages.len() - 1);
+                    let start = end.saturating_sub(page_lines(screen));
+                    messages[start..end].iter().map(|message| (message.describe(), message.category.color())).collect()
+                };
+            draw_page(screen, "Message History", &lines);
+        } else {
+            let overlays = Overlays {
+                show_vision: self.show_vision,
+                target: self.session.targeting.map(|targeting| targeting.pos),
+            };
+            draw_game(screen, self.charset, &self.session.game, &overlays);
+            draw_messages(screen, &self.session.message_log);
+        }
+
+        draw_top_status_bar(screen, &self.status_message());
+        draw_bottom_status_bar(screen, self.charset, &self.session.game);
+    }
+
+    fn status_message(&self) -> String {
+        if self.help_scroll.is_some() {
+            "Press up/down arrow keys to scroll, or F1 to close help".to_string()
+        } else if self.history_scroll.is_some() {
+            HISTORY_STATUS_MESSAGE.to_string()
+        } else {
+            self.session.status_message()
+        }
+    }
+
+    // The terminal has no speech bubbles, so the message log the session keeps
+    // is all it shows of each turn's events.
+
+    fn perform(&mut self, action: Action) {
+        self.session.perform(action);
+    }
+
+    fn throw_at_target(&mut self) {
+        self.session.throw_at_target();
+    }
+
+    fn prompt_for_direction(&mut self, prompt: DirectionPrompt) {
+        self.session.prompt_for_direction(prompt);
+    }
+}
+
+// The help pages run together, one after another, with the terminal's own
+// notes at the end.
+
+fn help_lines() -> Vec<(String, color_preset::Color)> {
+    let mut lines = Vec::new();
+    for page in HELP_PAGES {
+        lines.push((page.title.to_string(), color_preset::LIGHT_YELLOW));
+        lines.push((String::new(), color_preset::LIGHT_GRAY));
+        lines.extend(page.lines.iter().map(|line| (line.to_string(), color_preset::LIGHT_GRAY)));
+        lines.push((String::new(), color_preset::LIGHT_GRAY));
+    }
+    lines.extend(TTY_HELP_LINES.iter().map(|line| (line.to_string(), color_preset::LIGHT_GRAY)));
+    lines
+}
+
+// Digits only count as the keypad when the terminal says they came from it.
+// Otherwise the keypad moves with Num Lock off, when it sends the arrows and
+// Home/End/Page Up/Page Down, and its 5 comes through as KeypadBegin if at all.
+
+fn shared_key(event: &KeyEvent) -> Option<Key> {
+    let key = match event.code {
+        KeyCode::Char(c) if c.is_ascii_digit() && event.state.contains(KeyEventState::KEYPAD) => Key::Numpad(c as u8 - b'0'),
+        KeyCode::Char(c) => Key::Char(c.to_ascii_lowercase()),
+        KeyCode::KeypadBegin => Key::Numpad(5),
+        KeyCode::Up => Key::Up,
+        KeyCode::Down => Key::Down,
+        KeyCode::Left => Key::Left,
+        KeyCode::Right => Key::Right,
+        KeyCode::Home => Key::Home,
+        KeyCode::End => Key::End,
+        KeyCode::PageUp => Key::PageUp,
+        KeyCode::PageDown => Key::PageDown,
+        KeyCode::Enter => Key::Enter,
+        KeyCode::Esc => Key::Escape,
+        KeyCode::F(n) => Key::F(n),
+        _ => return None,
+    };
+    Some(key)
+}
@@ -0,0 +1,371 @@
+use crossterm::{
+    cursor::MoveTo,
+    queue,
+    style::{Color, Print, SetBackgroundColor, SetForegroundColor},
+};
+use std::cmp::{max, min};
+use std::io::{self, Write};
+use thiefrl3::cell_grid::*;
+use thiefrl3::color_preset;
+use thiefrl3::game::GameState;
+use thiefrl3::message_log::MessageLog;
+use crate::glyphs::*;
+
+// Rows of recent messages kept between the map and the bottom status bar.
+
+pub const MESSAGE_LINES: usize = 3;
+
+// Messages older than this many turns are cleared from those rows.
+
+const MESSAGE_TURNS: usize = 5;
+
+const BAR_BACKGROUND_COLOR: Color = Color::Black;
+const SMOKE_COLOR: Color = Color::DarkGrey;
+const VISION_COLOR: Color = Color::DarkRed;
+
+#[derive(Clone, Copy, PartialEq)]
+struct ScreenCell {
+    ch: char,
+    fg: Color,
+    bg: Color,
+}
+
+const BLANK: ScreenCell = ScreenCell { ch: ' ', fg: Color::Reset, bg: Color::Reset };
+
+// The whole terminal, built up in memory and then written out in one go so the
+// frame doesn't flicker as it is drawn.
+
+pub struct Screen {
+    size_x: usize,
+    size_y: usize,
+    cells: Vec<ScreenCell>,
+}
+
+impl Screen {
+    pub fn new(size_x: usize, size_y: usize) -> Screen {
+        Screen { size_x, size_y, cells: vec![BLANK; size_x * size_y] }
+    }
+
+    fn put(&mut self, x: usize, y: usize, ch: char, fg: Color) {
+        if x < self.size_x && y < self.size_y {
+            let cell = &mut self.cells[y * self.size_x + x];
+            cell.ch = ch;
+            cell.fg = fg;
+        }
+    }
+
+    fn set_background(&mut self, x: usize, y: usize, bg: Color) {
+        if x < self.size_x && y < self.size_y {
+            self.cells[y * self.size_x + x].bg = bg;
+        }
+    }
+
+    // Writes the string starting at x and returns the column after it.
+
+    pub fn puts(&mut self, mut x: usize, y: usize, s: &str, fg: Color) -> usize {
+        for ch in s.chars() {
+            self.put(x, y, ch, fg);
+            x += 1;
+        }
+        x
+    }
+
+    fn fill_row(&mut self, y: usize, bg: Color) {
+        for x in 0..self.size_x {
+            self.cells[y * self.size_x + x] = ScreenCell { ch: ' ', fg: Color::Reset, bg };
+        }
+    }
+
+    // Only emits a color change when the color actually changes, which keeps the
+    // output small enough to redraw every turn over a slow connection.
+
+    pub fn present<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut fg = None;
+        let mut bg = None;
+
+        for y in 0..self.size_y {
+            queue!(out, MoveTo(0, y as u16))?;
+            for cell in &self.cells[y * self.size_x .. (y + 1) * self.size_x] {
+                if fg != Some(cell.fg) {
+                    queue!(out, SetForegroundColor(cell.fg))?;
+                    fg = Some(cell.fg);
+                }
+                if bg != Some(cell.bg) {
+                    queue!(out, SetBackgroundColor(cell.bg))?;
+                    bg = Some(cell.bg);
+                }
+                queue!(out, Print(cell.ch))?;
+            }
+        }
+
+        out.flush()
+    }
+}
+
+// Which part of the map is showing. Maps that fit are centered; bigger ones
+// scroll to keep the thief in the middle of the view.
+
+struct View {
+    x_screen: usize, // leftmost column of the view
+    y_screen: usize, // top row of the view
+    size_x: usize,
+    size_y: usize,
+    x_scroll: i32, // map column at the left edge of the view
+    y_scroll: i32, // map row at the top edge of the view, counting down from the top of the map
+    map_size_y: i32,
+}
+
+impl View {
+    fn new(screen: &Screen, map: &Map, pos_center: Point) -> View {
+        let size_x = screen.size_x;
+        let size_y = screen.size_y.saturating_sub(2 + MESSAGE_LINES);
+        let map_size_x = map.cells.extents()[0] as i32;
+        let map_size_y = map.cells.extents()[1] as i32;
+
+        View {
+            x_screen: 0,
+            y_screen: 1,
+            size_x,
+            size_y,
+            x_scroll: scroll(map_size_x, size_x as i32, pos_center.x),
+            y_scroll: scroll(map_size_y, size_y as i32, (map_size_y - 1) - pos_center.y),
+            map_size_y,
+        }
+    }
+
+    fn screen_pos(&self, pos: Point) -> Option<(usize, usize)> {
+        let x = pos.x - self.x_scroll;
+        let y = ((self.map_size_y - 1) - pos.y) - self.y_scroll;
+        if x < 0 || y < 0 || x >= self.size_x as i32 || y >= self.size_y as i32 {
+            return None;
+        }
+        Some((self.x_screen + x as usize, self.y_screen + y as usize))
+    }
+}
+
+// A negative scroll centers a map smaller than the view.
+
+fn scroll(map_size: i32, view_size: i32, pos_center: i32) -> i32 {
+    if map_size <= view_size {
+        -((view_size - map_size) / 2)
+    } else {
+        min(max(0, pos_center - view_size / 2), map_size - view_size)
+    }
+}
+
+pub struct Overlays {
+    pub show_vision: bool,
+    pub target: Option<Point>,
+}
+
+pub fn draw_game(screen: &mut Screen, charset: Charset, game: &GameState, overlays: &Overlays) {
+    let map = &game.map;
+    let player = &game.player;
+    let view = View::new(screen, map, player.pos);
+
+    let map_size_x = map.cells.extents()[0];
+    let map_size_y = map.cells.extents()[1];
+
+    for x in 0..map_size_x {
+        for y in 0..map_size_y {
+            let pos = Point::new(x as i32, y as i32);
+            let cell = &map.cells[[x, y]];
+            if !cell.seen && !player.see_all {
+                continue;
+            }
+            if let Some((sx, sy)) = view.screen_pos(pos) {
+                let tile = tile_def(cell.cell_type);
+                let color = if cell.lit || tile.ignores_lighting {tile.color} else {color_preset::DARK_BLUE};
+                screen.put(sx, sy, char_for_cell(charset, cell.cell_type), ansi_color(color));
+                if cell.smoke > 0 {
+                    screen.set_background(sx, sy, SMOKE_COLOR);
+                }
+            }
+        }
+    }
+
+    let visible = |pos: Point| {
+        let cell = &map.cells[[pos.x as usize, pos.y as usize]];
+        if cell.seen || player.see_all {Some(cell)} else {None}
+    };
+
+    for item in &map.items {
+        if let (Some(cell), Some((sx, sy))) = (visible(item.pos), view.screen_pos(item.pos)) {
+            let color = if cell.lit {color_for_item(item.kind)} else {color_preset::DARK_BLUE};
+            screen.put(sx, sy, char_for_item(charset, item.kind), ansi_color(color));
+        }
+    }
+
+    for key in &map.keys {
+        if let (Some(cell), Some((sx, sy))) = (visible(key.pos), view.screen_pos(key.pos)) {
+            let color = if cell.lit {color_preset::LIGHT_CYAN} else {color_preset::DARK_BLUE};
+            screen.put(sx, sy, CHAR_KEY, ansi_color(color));
+        }
+    }
+
+    for light in &map.lights {
+        if let (Some(_), Some((sx, sy))) = (visible(light.pos), view.screen_pos(light.pos)) {
+            let color = if light.lit {color_preset::LIGHT_YELLOW} else {color_preset::DARK_GRAY};
+            screen.put(sx, sy, char_for_light(light.kind), ansi_color(color));
+        }
+    }
+
+    if overlays.show_vision {
+        let watchers: Vec<&Guard> = map.guards.iter().filter(|guard| {
+            let cell = &map.cells[[guard.pos.x as usize, guard.pos.y as usize]];
+            !guard.unconscious() && (player.see_all || cell.seen || guard.speaking)
+        }).collect();
+
+        for x in 0..map_size_x {
+            for y in 0..map_size_y {
+                let pos = Point::new(x as i32, y as i32);
+                if let Some((sx, sy)) = view.screen_pos(pos) {
+                    if watchers.iter().any(|guard| guard.sees_thief_at(map, player, pos)) {
+                        screen.set_background(sx, sy, VISION_COLOR);
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some((sx, sy)) = view.screen_pos(player.pos) {
+        let lit = map.cells[[player.pos.x as usize, player.pos.y as usize]].lit;
+        let color =
+            if player.damaged_last_turn {color_preset::LIGHT_RED}
+            else if player.noisy {color_preset::LIGHT_CYAN}
+            else if player.hidden(map) {color_preset::DARK_GRAY}
+            else if lit {color_preset::WHITE}
+            else {color_preset::LIGHT_BLUE};
+        screen.put(sx, sy, CHAR_PLAYER, ansi_color(color));
+    }
+
+    for guard in &map.guards {
+        let cell = &map.cells[[guard.pos.x as usize, guard.pos.y as usize]];
+        let visible = player.see_all || cell.seen || guard.speaking;
+
+        if !visible && (player.pos - guard.pos).length_squared() > 36 {
+            continue;
+        }
+
+        let color =
+            if !visible {
+                color_preset::DARK_GRAY
+            } else if guard.unconscious() {
+                color_preset::DARK_MAGENTA
+            } else if guard.mode == GuardMode::Patrol && !guard.speaking && !cell.lit {
+                color_preset::DARK_BLUE
+            } else {
+                color_preset::LIGHT_MAGENTA
+            };
+
+        if let Some((sx, sy)) = view.screen_pos(guard.pos) {
+            screen.put(sx, sy, char_for_guard(charset, guard), ansi_color(color));
+        }
+    }
+
+    if let Some(pos_target) = overlays.target {
+        if let Some((sx, sy)) = view.screen_pos(pos_target) {
+            let color = if map.can_throw_to(player.pos, pos_target) {Color::DarkGreen} else {Color::DarkRed};
+            screen.set_background(sx, sy, color);
+        }
+    }
+}
+
+// The most recent messages, in the rows just above the bottom status bar.
+
+pub fn draw_messages(screen: &mut Screen, log: &MessageLog) {
+    let messages = log.recent(MESSAGE_LINES);
+    let first_current = messages.iter().position(|message| message.turn + MESSAGE_TURNS > log.turn).unwrap_or(messages.len());
+
+    let y_top = screen.size_y.saturating_sub(1 + MESSAGE_LINES);
+    for (i, message) in messages[first_current..].iter().enumerate() {
+        screen.puts(1, y_top + i, &message.text, ansi_color(message.category.color()));
+    }
+}
+
+// Full-screen text, for the help and the message history. Lines past the
+// bottom of the page are cut off.
+
+pub fn draw_page(screen: &mut Screen, title: &str, lines: &[(String, color_preset::Color)]) {
+    screen.puts(2, 2, title, ansi_color(color_preset::LIGHT_YELLOW));
+
+    for (i, (line, color)) in lines.iter().take(page_lines(screen)).enumerate() {
+        screen.puts(2, 4 + i, line, ansi_color(*color));
+    }
+}
+
+// Number of lines that fit on one page of draw_page.
+
+pub fn page_lines(screen: &Screen) -> usize {
+    max(1, screen.size_y.saturating_sub(6))
+}
+
+pub fn draw_top_status_bar(screen: &mut Screen, msg: &str) {
+    screen.fill_row(0, BAR_BACKGROUND_COLOR);
+    screen.puts(1, 0, msg, ansi_color(color_preset::WHITE));
+}
+
+pub fn draw_bottom_status_bar(screen: &mut Screen, charset: Charset, game: &GameState) {
+    let map = &game.map;
+    let player = &game.player;
+    let y = screen.size_y.saturating_sub(1);
+    let size_x = screen.size_x;
+
+    screen.fill_row(y, BAR_BACKGROUND_COLOR);
+
+    let (char_healthy, char_unhealthy) = health_chars(charset);
+    let mut x = screen.puts(1, y, "Health ", Color::DarkRed);
+    for i in 0..player.max_health {
+        screen.put(x, y, if i < player.health {char_healthy} else {char_unhealthy}, Color::DarkRed);
+        x += 1;
+    }
+
+    for _ in 0..player.keys.len() {
+        x += 1;
+        screen.put(x, y, CHAR_KEY, ansi_color(color_preset::LIGHT_CYAN));
+    }
+
+    let player_underwater = map.cells[[player.pos.x as usize, player.pos.y as usize]].cell_type == CellType::GroundWater && player.turns_remaining_underwater > 0;
+
+    if player_underwater {
+        x += 2;
+        x = screen.puts(x, y, "Air ", Color::Cyan);
+        for i in 0..5 {
+            let ch = if i + 1 < player.turns_remaining_underwater {'O'} else {'.'};
+            screen.put(x, y, ch, Color::Cyan);
+            x += 1;
+        }
+    }
+
+    // The tallies of what's been seen and collected.
+
+    let percent_seen = map.percent_seen();
+
+    let seen_msg = format!("Level {}: {}% Seen", game.level + 1, percent_seen);
+    let loot_msg =
+        if percent_seen < 100 {
+            format!("Loot {}/?", player.gold)
+        } else {
+            format!("Loot {}/{}", player.gold, map.total_loot)
+        };
+
+    let x_loot = size_x.saturating_sub(1 + loot_msg.len());
+    screen.puts(x_loot, y, &loot_msg, ansi_color(color_preset::LIGHT_YELLOW));
+
+    // The tools being carried, each with its count.
+
+    let tools: Vec<(ItemKind, String)> = player.inventory.tools().map(|(tool, count)| (item_for_tool(tool), count.to_string())).collect();
+    let tools_len: usize = tools.iter().map(|(_, tally)| 3 + tally.len()).sum();
+    let x_tools = x_loot.saturating_sub(tools_len + 1);
+
+    let mut x_tool = x_tools;
+    for (item_kind, tally) in &tools {
+        screen.put(x_tool, y, char_for_item(charset, *item_kind), ansi_color(color_for_item(*item_kind)));
+        x_tool = screen.puts(x_tool + 1, y, tally, ansi_color(color_preset::LIGHT_GRAY)) + 2;
+    }
+
+    let x_seen = max(x + 2, (size_x.saturating_sub(seen_msg.len())) / 2);
+    if x_seen + seen_msg.len() < x_tools {
+        screen.puts(x_seen, y, &seen_msg, ansi_color(color_preset::DARK_GRAY));
+    }
+}
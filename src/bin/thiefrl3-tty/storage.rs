@@ -0,0 +1,33 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use thiefrl3::save::{Storage, APP_NAME};
+
+// A file per profile in the same folder the graphical version uses on the
+// desktop, so the two frontends continue each other's games.
+
+pub struct FileStorage;
+
+impl FileStorage {
+    fn folder(&self) -> io::Result<PathBuf> {
+        let mut path = dirs::data_dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the current user has no data directory"))?;
+        path.push(APP_NAME);
+        Ok(path)
+    }
+}
+
+impl Storage for FileStorage {
+    fn load(&self, profile: &str) -> io::Result<Option<String>> {
+        match fs::read_to_string(self.folder()?.join(profile)) {
+            Ok(text) => Ok(Some(text)),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn save(&self, profile: &str, text: &str) -> io::Result<()> {
+        let folder = self.folder()?;
+        fs::create_dir_all(&folder)?;
+        fs::write(folder.join(profile), text)
+    }
+}
@@ -0,0 +1,78 @@
+use crate::ascii_map::ParseMapError;
+use crate::cell_grid::*;
+use crate::random_map;
+
+use rand::SeedableRng;
+use std::fmt;
+use std::path::Path;
+
+// Where each level comes from. Authored levels are drawn by hand in the text
+// format described in ascii_map and built into the game; the rest come from
+// the generator, which gets harder with the level number.
+
+pub enum LevelSource {
+    Generated,
+    Authored { name: &'static str, text: &'static str },
+}
+
+// The levels in the order they're played. Past the end of the list every
+// level is generated.
+
+pub const CAMPAIGN: &[LevelSource] = &[
+    LevelSource::Authored { name: "gatehouse", text: include_str!("../levels/gatehouse.txt") },
+    LevelSource::Generated,
+    LevelSource::Generated,
+    LevelSource::Authored { name: "cloister", text: include_str!("../levels/cloister.txt") },
+];
+
+pub fn level_source(level: usize) -> &'static LevelSource {
+    CAMPAIGN.get(level).unwrap_or(&LevelSource::Generated)
+}
+
+// Make the map for a level. The seed only matters for generated levels.
+// Authored levels ship with the game, so one that doesn't load is a bug. A
+// level file being tried out stands in for the first level; it was checked
+// when it was loaded.
+
+pub fn make_level(level_seed: u64, level: usize, level_file: Option<&str>) -> Map {
+    if let (Some(text), 0) = (level_file, level) {
+        return Map::from_ascii(text).unwrap_or_else(|err| panic!("level file: {}", err));
+    }
+
+    match level_source(level) {
+        LevelSource::Generated => {
+            let mut rng = MyRng::seed_from_u64(level_seed);
+            random_map::generate_map(&mut rng, level)
+        },
+        LevelSource::Authored { name, text } => {
+            Map::from_ascii(text).unwrap_or_else(|err| panic!("authored level {}: {}", name, err))
+        },
+    }
+}
+
+// Load a level file from disk, for trying out a level before adding it to the
+// campaign. It is checked here but kept as text, so that the level can be made
+// afresh when it is restarted.
+
+#[derive(Debug)]
+pub enum LevelFileError {
+    Read(std::io::Error),
+    Parse(ParseMapError),
+}
+
+impl fmt::Display for LevelFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LevelFileError::Read(err) => write!(f, "{}", err),
+            LevelFileError::Parse(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for LevelFileError {}
+
+pub fn load_level_file(path: &Path) -> Result<String, LevelFileError> {
+    let text = std::fs::read_to_string(path).map_err(LevelFileError::Read)?;
+    Map::from_ascii(&text).map_err(LevelFileError::Parse)?;
+    Ok(text)
+}
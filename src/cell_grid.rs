@@ -1,13 +1,19 @@
 use crate::color_preset;
+use crate::inventory::Inventory;
 use multiarray::Array2D;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::cmp::max;
+use std::cmp::min;
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 use std::collections::VecDeque;
+use std::rc::Rc;
 
 pub type MyRng = rand_pcg::Pcg32;
 
-#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
 pub enum CellType {
     GroundNormal,
     GroundGrass,
@@ -44,18 +50,25 @@ pub enum CellType {
     DoorEW,
 }
 
-pub const INVALID_REGION: usize = std::usize::MAX;
-pub const INFINITE_COST: usize = std::usize::MAX;
+pub const INVALID_REGION: usize = usize::MAX;
+pub const INFINITE_COST: usize = usize::MAX;
 
-#[derive(Clone, Debug, PartialEq)]
+// How far (squared) the player can throw things.
+
+pub const THROW_RANGE_SQUARED: i32 = 64;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Cell {
     pub cell_type: CellType,
     pub move_cost: usize,
     pub region: usize,
+    pub blocks_player: bool,
     pub blocks_player_sight: bool,
     pub blocks_sight: bool,
     pub blocks_sound: bool,
     pub hides_player: bool,
+    pub smoke: usize, // turns until smoke here clears
+    pub moonlit: bool, // open to the sky, so lit regardless of light sources
     pub lit: bool,
     pub seen: bool,
 }
@@ -63,22 +76,67 @@ pub struct Cell {
 pub type CellGrid = Array2D<Cell>;
 pub type Point = vector2d::Vector2D<i32>;
 
+#[derive(Clone, Copy, Deserialize, Serialize)]
 pub struct Rect {
+    #[serde(with = "crate::save::point")]
     pub pos_min: Point,
+    #[serde(with = "crate::save::point")]
     pub pos_max: Point,
 }
 
+#[derive(Deserialize, Serialize)]
 pub struct Map {
+    #[serde(with = "crate::save::array_2d")]
     pub cells: CellGrid,
     pub patrol_regions: Vec<Rect>,
     pub patrol_routes: Vec<(usize, usize)>,
     pub items: Vec<Item>,
+    pub lights: Vec<Light>,
+    pub keys: Vec<Key>, // keys lying loose on the floor
     pub guards: Vec<Guard>,
+    #[serde(with = "crate::save::point")]
     pub pos_start: Point,
     pub total_loot: usize,
+    pub alarm_raised: bool, // whether the guards raised the alarm on the last turn
+    #[serde(skip)]
+    pub distance_fields: DistanceFieldCache,
+}
+
+// Distance fields to each patrol region, built the first time a guard needs
+// one. They depend only on the cells' move costs, so they are all thrown away
+// whenever one of those changes.
+
+#[derive(Default)]
+pub struct DistanceFieldCache {
+    to_region: RefCell<Vec<Option<Rc<Array2D<usize>>>>>,
 }
 
-#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(Copy, Clone, Eq, PartialEq)]
+struct PathState {
+    priority: usize, // dist plus the estimated distance left to go, if searching toward something
+    dist: usize,
+    pos: Point,
+}
+
+impl Ord for PathState {
+    fn cmp(&self, other: &PathState) -> Ordering {
+        other.priority.cmp(&self.priority)
+    }
+}
+
+impl PartialOrd for PathState {
+    fn partial_cmp(&self, other: &PathState) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl DistanceFieldCache {
+    pub fn invalidate(&self) {
+        self.to_region.borrow_mut().clear();
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
 pub enum GuardMode
 {
     Patrol,
@@ -88,34 +146,56 @@ pub enum GuardMode
     MoveToLastSighting,
     MoveToLastSound,
     MoveToGuardShout,
+    Relight,
+    FoundBody,
+    Unconscious,
 }
 
+#[derive(Deserialize, Serialize)]
 pub struct Guard {
+    #[serde(with = "crate::save::point")]
     pub pos: Point,
+    #[serde(with = "crate::save::point")]
     pub dir: Point,
     pub mode: GuardMode,
     pub speaking: bool,
     pub has_moved: bool,
+    #[serde(with = "crate::save::point")]
+    pub pos_reserved: Point, // the cell the guard means to step into this turn
+    pub turns_waiting: usize, // turns spent stuck behind other guards
     pub heard_thief: bool,
+    #[serde(with = "crate::save::point")]
+    pub heard_thief_pos: Point, // where the noise came from; not always where the thief is
     pub hearing_guard: bool,
     pub heard_guard: bool,
+    #[serde(with = "crate::save::point")]
     pub heard_guard_pos: Point,
 
     // Chase
+    #[serde(with = "crate::save::point")]
     pub goal: Point,
     pub mode_timeout: usize,
 
     // Patrol
     pub region_goal: usize,
     pub region_prev: usize,
+
+    // Relight
+    pub light_goal: Option<usize>, // index of the doused light being relit
+
+    // Doors
+    pub key: Option<Key>,
+    pub door_to_lock: Option<usize>, // index of a door this guard unlocked and will lock again behind it
 }
 
+#[derive(Deserialize, Serialize)]
 pub struct Item {
+    #[serde(with = "crate::save::point")]
     pub pos: Point,
     pub kind: ItemKind,
 }
 
-#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
 pub enum ItemKind {
     Chair,
     Table,
@@ -123,12 +203,51 @@ pub enum ItemKind {
     Coin,
     DoorNS,
     DoorEW,
+    DoorOpenNS,
+    DoorOpenEW,
+    DoorLockedNS,
+    DoorLockedEW,
     PortcullisNS,
     PortcullisEW,
+    PortcullisLoweredNS,
+    PortcullisLoweredEW,
+    Winch,
+    Stones,
+    SmokeBomb,
+    WaterFlask,
+}
+
+// A key opens the one locked door at its door position.
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+pub struct Key {
+    #[serde(with = "crate::save::point")]
+    pub pos: Point, // where the key lies, or was found
+    #[serde(with = "crate::save::point")]
+    pub door: Point,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Light {
+    #[serde(with = "crate::save::point")]
+    pub pos: Point,
+    #[serde(with = "crate::save::point")]
+    pub dir: Point, // direction a wall-mounted light shines; zero for free-standing lights
+    pub kind: LightKind,
+    pub lit: bool,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum LightKind {
+    Torch,
+    Candle,
 }
 
+#[derive(Deserialize, Serialize)]
 pub struct Player {
+    #[serde(with = "crate::save::point")]
     pub pos: Point,
+    #[serde(with = "crate::save::point")]
     pub dir: Point,
     pub max_health: usize,
     pub health: usize,
@@ -140,6 +259,15 @@ pub struct Player {
 
     pub turns_remaining_underwater: usize,
 
+    pub keys: Vec<Key>,
+    pub inventory: Inventory,
+    #[serde(with = "crate::save::point")]
+    pub lockpick_pos: Point, // the lock being picked
+    pub lockpick_turns: usize, // turns spent picking it so far
+    pub dragging: bool, // pulling an unconscious guard along behind us
+    #[serde(with = "crate::save::point")]
+    pub drag_pos: Point, // where the body being dragged is
+
     pub see_all: bool,
     pub game_over: bool,
 }
@@ -147,7 +275,7 @@ pub struct Player {
 #[derive(Clone, Debug, PartialEq)]
 pub struct Tile {
     pub glyph: usize,
-    pub color: quicksilver::graphics::Color,
+    pub color: color_preset::Color,
     pub blocks_player: bool,
     pub blocks_player_sight: bool,
     pub blocks_sight: bool,
@@ -194,6 +322,50 @@ pub fn tile_def(tile_type: CellType) -> &'static Tile {
     }
 }
 
+// Tiles for the things drawn over the cells, indexing the same tileset as
+// tile_def.
+
+pub fn glyph_for_item(kind: ItemKind) -> usize {
+    match kind {
+        ItemKind::Chair => 148,
+        ItemKind::Table => 146,
+        ItemKind::Bush => 144,
+        ItemKind::Coin => 158,
+        ItemKind::DoorNS => 169,
+        ItemKind::DoorEW => 167,
+        ItemKind::DoorOpenNS => 168,
+        ItemKind::DoorOpenEW => 170,
+        ItemKind::DoorLockedNS => 169,
+        ItemKind::DoorLockedEW => 167,
+        ItemKind::PortcullisNS => 192,
+        ItemKind::PortcullisEW => 192,
+        ItemKind::PortcullisLoweredNS => 194,
+        ItemKind::PortcullisLoweredEW => 194,
+        ItemKind::Winch => 165,
+        ItemKind::Stones => 42,
+        ItemKind::SmokeBomb => 37,
+        ItemKind::WaterFlask => 33,
+    }
+}
+
+pub fn glyph_for_light(kind: LightKind) -> usize {
+    match kind {
+        LightKind::Torch => 160,
+        LightKind::Candle => 160,
+    }
+}
+
+pub const GLYPH_KEY: usize = 45;
+pub const GLYPH_PLAYER: usize = 208;
+
+pub fn glyph_for_guard(dir: Point) -> usize {
+    if dir.y > 0 {210}
+    else if dir.y < 0 {212}
+    else if dir.x > 0 {209}
+    else if dir.x < 0 {211}
+    else {212}
+}
+
 pub fn guard_move_cost_for_tile_type(tile_type: CellType) -> usize {
     match tile_type {
         CellType::GroundNormal     => 0,
@@ -237,8 +409,76 @@ pub fn guard_move_cost_for_item_kind(kind: ItemKind) -> usize {
         ItemKind::Coin => 0,
         ItemKind::DoorNS => 0,
         ItemKind::DoorEW => 0,
+        ItemKind::DoorOpenNS => 0,
+        ItemKind::DoorOpenEW => 0,
+        ItemKind::DoorLockedNS => 0,
+        ItemKind::DoorLockedEW => 0,
         ItemKind::PortcullisNS => 0,
         ItemKind::PortcullisEW => 0,
+        ItemKind::PortcullisLoweredNS => INFINITE_COST,
+        ItemKind::PortcullisLoweredEW => INFINITE_COST,
+        ItemKind::Winch => INFINITE_COST,
+        ItemKind::Stones => 0,
+        ItemKind::SmokeBomb => 0,
+        ItemKind::WaterFlask => 0,
+    }
+}
+
+pub fn is_door(kind: ItemKind) -> bool {
+    is_closed_door(kind) || kind == ItemKind::DoorOpenNS || kind == ItemKind::DoorOpenEW
+}
+
+pub fn is_closed_door(kind: ItemKind) -> bool {
+    matches!(kind, ItemKind::DoorNS | ItemKind::DoorEW | ItemKind::DoorLockedNS | ItemKind::DoorLockedEW)
+}
+
+pub fn is_locked_door(kind: ItemKind) -> bool {
+    kind == ItemKind::DoorLockedNS || kind == ItemKind::DoorLockedEW
+}
+
+pub fn is_portcullis(kind: ItemKind) -> bool {
+    matches!(kind, ItemKind::PortcullisNS | ItemKind::PortcullisEW | ItemKind::PortcullisLoweredNS | ItemKind::PortcullisLoweredEW)
+}
+
+pub fn is_lowered_portcullis(kind: ItemKind) -> bool {
+    kind == ItemKind::PortcullisLoweredNS || kind == ItemKind::PortcullisLoweredEW
+}
+
+// Set a cell's cached flags from its tile type, discarding any item effects.
+
+pub fn cache_tile_info(cell: &mut Cell) {
+    let cell_type = cell.cell_type;
+    let tile = tile_def(cell_type);
+    cell.move_cost = guard_move_cost_for_tile_type(cell_type);
+    cell.blocks_player = tile.blocks_player;
+    cell.blocks_player_sight = tile.blocks_player_sight;
+    cell.blocks_sight = tile.blocks_sight;
+    cell.blocks_sound = tile.blocks_sound;
+    cell.hides_player = tile.hides_player;
+}
+
+// Add the effects of an item sitting on a cell to its cached flags.
+
+pub fn cache_item_info(cell: &mut Cell, kind: ItemKind) {
+    cell.move_cost = max(cell.move_cost, guard_move_cost_for_item_kind(kind));
+    if is_lowered_portcullis(kind) || kind == ItemKind::Winch {
+        cell.blocks_player = true;
+    }
+    if is_closed_door(kind) {
+        cell.blocks_player_sight = true;
+    }
+    if is_closed_door(kind) || is_portcullis(kind) || kind == ItemKind::Bush {
+        cell.blocks_sight = true;
+    }
+    if kind == ItemKind::Table || kind == ItemKind::Bush {
+        cell.hides_player = true;
+    }
+}
+
+pub fn light_radius(kind: LightKind) -> i32 {
+    match kind {
+        LightKind::Torch => 5,
+        LightKind::Candle => 3,
     }
 }
 
@@ -248,12 +488,18 @@ pub fn make_player(pos: &Point) -> Player {
         pos: *pos,
         dir: Point::new(0, 0),
         max_health: health,
-        health: health,
+        health,
         gold: 0,
         noisy: false,
         damaged_last_turn: false,
         finished_level: false,
         turns_remaining_underwater: 0,
+        keys: Vec::new(),
+        inventory: Inventory::new(),
+        lockpick_pos: *pos,
+        lockpick_turns: 0,
+        dragging: false,
+        drag_pos: *pos,
         see_all: false,
         game_over: false,
     }
@@ -275,17 +521,23 @@ impl Player {
     }
 
     pub fn hidden(&self, map: &Map) -> bool {
+        self.hidden_at(map, self.pos)
+    }
+
+    // Whether the player would be hidden standing at pos.
+
+    pub fn hidden_at(&self, map: &Map, pos: Point) -> bool {
         for guard in &map.guards {
             if guard.mode == GuardMode::ChaseVisibleTarget {
                 return false;
             }
         }
 
-        if map.hides_player(self.pos.x, self.pos.y) {
+        if map.hides_player(pos.x, pos.y) {
             return true;
         }
 
-        let cell_type = map.cells[[self.pos.x as usize, self.pos.y as usize]].cell_type;
+        let cell_type = map.cells[[pos.x as usize, pos.y as usize]].cell_type;
 
         if cell_type == CellType::GroundWater && self.turns_remaining_underwater > 0 {
             return true;
@@ -398,9 +650,265 @@ pub fn mark_all_unseen(&mut self) {
 }
 
 pub fn recompute_visibility(&mut self, pos_viewer: Point) {
+    self.cast(pos_viewer, 1600, &mut |cell| cell.seen = true);
+}
+
+// Recompute which cells are lit, from the sky and from each light source. Light
+// travels through whatever the player can see through.
+
+pub fn compute_lighting(&mut self) {
+    for x in 0..self.cells.extents()[0] {
+        for y in 0..self.cells.extents()[1] {
+            let cell = &mut self.cells[[x, y]];
+            cell.lit = cell.moonlit;
+        }
+    }
+
+    for i in 0..self.lights.len() {
+        let light = &self.lights[i];
+        if !light.lit {
+            continue;
+        }
+
+        // Wall-mounted lights shine from the cell in front of them, so that the
+        // wall they are on doesn't shadow everything.
+
+        let pos_emit = light.pos + light.dir;
+        let radius = 2 * light_radius(light.kind);
+
+        self.cast(pos_emit, radius * radius, &mut |cell| cell.lit = true);
+    }
+}
+
+// Recompute one cell's cached flags after something on it has changed.
+
+pub fn update_cell_info(&mut self, pos: Point) {
+    let cell = &mut self.cells[[pos.x as usize, pos.y as usize]];
+    let move_cost_prev = cell.move_cost;
+    cache_tile_info(cell);
+    for item in &self.items {
+        if item.pos == pos {
+            cache_item_info(cell, item.kind);
+        }
+    }
+    if cell.smoke > 0 {
+        cell.blocks_sight = true;
+        cell.hides_player = true;
+    }
+    if cell.move_cost != move_cost_prev {
+        self.distance_fields.invalidate();
+    }
+}
+
+// Fill the open cells around pos with smoke for the given number of turns.
+
+pub fn add_smoke(&mut self, pos_center: Point, turns: usize) {
+    for x in pos_center.x - 1 ..= pos_center.x + 1 {
+        for y in pos_center.y - 1 ..= pos_center.y + 1 {
+            if x < 0 || y < 0 || x >= self.cells.extents()[0] as i32 || y >= self.cells.extents()[1] as i32 {
+                continue;
+            }
+
+            let cell = &mut self.cells[[x as usize, y as usize]];
+            if tile_def(cell.cell_type).blocks_player {
+                continue;
+            }
+
+            cell.smoke = max(cell.smoke, turns);
+            self.update_cell_info(Point::new(x, y));
+        }
+    }
+}
+
+// Smoke thins out a turn at a time.
+
+pub fn update_smoke(&mut self) {
+    for x in 0..self.cells.extents()[0] {
+        for y in 0..self.cells.extents()[1] {
+            let cell = &mut self.cells[[x, y]];
+            if cell.smoke == 0 {
+                continue;
+            }
+
+            cell.smoke -= 1;
+            if cell.smoke == 0 {
+                self.update_cell_info(Point::new(x as i32, y as i32));
+            }
+        }
+    }
+}
+
+// Walk the cells strictly between from and to; true if none of them blocks.
+
+pub fn line_clear<F: Fn(&Cell) -> bool>(&self, from: Point, to: Point, blocks: F) -> bool {
+    let mut x = from.x;
+    let mut y = from.y;
+
+    let dx = to.x - x;
+    let dy = to.y - y;
+
+    let mut ax = dx.abs();
+    let mut ay = dy.abs();
+
+    let x_inc = if dx > 0 {1} else {-1};
+    let y_inc = if dy > 0 {1} else {-1};
+
+    let mut error = ay - ax;
+
+    let mut n = ax + ay - 1;
+
+    ax *= 2;
+    ay *= 2;
+
+    while n > 0 {
+        if error > 0 {
+            y += y_inc;
+            error -= ax;
+        } else {
+            x += x_inc;
+            error += ay;
+        }
+
+        if blocks(&self.cells[[x as usize, y as usize]]) {
+            return false;
+        }
+
+        n -= 1;
+    }
+
+    true
+}
+
+// Thrown things fly in a straight line and stop at anything the player can't
+// walk or see through. The target itself may be solid, so that a torch on a
+// wall can be hit.
+
+pub fn can_throw_to(&self, from: Point, to: Point) -> bool {
+    if to.x < 0 || to.y < 0 || to.x >= self.cells.extents()[0] as i32 || to.y >= self.cells.extents()[1] as i32 {
+        return false;
+    }
+
+    if to == from || (to - from).length_squared() > THROW_RANGE_SQUARED {
+        return false;
+    }
+
+    self.line_clear(from, to, |cell| cell.blocks_player || cell.blocks_player_sight)
+}
+
+// Index of the door item (open or closed) at a position, if there is one.
+
+pub fn door_at(&self, pos: Point) -> Option<usize> {
+    self.items.iter().position(|item| item.pos == pos && is_door(item.kind))
+}
+
+pub fn closed_door_at(&self, pos: Point) -> Option<usize> {
+    self.items.iter().position(|item| item.pos == pos && is_closed_door(item.kind))
+}
+
+pub fn locked_door_at(&self, pos: Point) -> Option<usize> {
+    self.items.iter().position(|item| item.pos == pos && is_locked_door(item.kind))
+}
+
+// Opening a locked door unlocks it.
+
+pub fn set_door_open(&mut self, i_item: usize, open: bool) {
+    let item = &mut self.items[i_item];
+
+    item.kind = match item.kind {
+        ItemKind::DoorNS | ItemKind::DoorOpenNS | ItemKind::DoorLockedNS => if open {ItemKind::DoorOpenNS} else {ItemKind::DoorNS},
+        ItemKind::DoorEW | ItemKind::DoorOpenEW | ItemKind::DoorLockedEW => if open {ItemKind::DoorOpenEW} else {ItemKind::DoorEW},
+        kind => kind,
+    };
+
+    let pos = item.pos;
+    self.update_cell_info(pos);
+    self.compute_lighting();
+}
+
+// Only closed doors can be locked. Locking doesn't change what the door blocks.
+
+pub fn set_door_locked(&mut self, i_item: usize, locked: bool) {
+    let item = &mut self.items[i_item];
+
+    item.kind = match item.kind {
+        ItemKind::DoorNS | ItemKind::DoorLockedNS => if locked {ItemKind::DoorLockedNS} else {ItemKind::DoorNS},
+        ItemKind::DoorEW | ItemKind::DoorLockedEW => if locked {ItemKind::DoorLockedEW} else {ItemKind::DoorEW},
+        kind => kind,
+    };
+}
+
+pub fn winch_at(&self, pos: Point) -> Option<usize> {
+    self.items.iter().position(|item| item.pos == pos && item.kind == ItemKind::Winch)
+}
+
+pub fn portcullises_lowered(&self) -> bool {
+    self.items.iter().any(|item| is_lowered_portcullis(item.kind))
+}
+
+// Drop every raised portcullis that nobody is standing under. Returns whether
+// any of them came down.
+
+pub fn lower_portcullises(&mut self, pos_player: Point) -> bool {
+    let mut lowered = Vec::new();
+
+    for item in &mut self.items {
+        let kind = match item.kind {
+            ItemKind::PortcullisNS => ItemKind::PortcullisLoweredNS,
+            ItemKind::PortcullisEW => ItemKind::PortcullisLoweredEW,
+            _ => continue,
+        };
+
+        if item.pos == pos_player || self.guards.iter().any(|guard| guard.pos == item.pos) {
+            continue;
+        }
+
+        item.kind = kind;
+        lowered.push(item.pos);
+    }
+
+    for &pos in &lowered {
+        self.update_cell_info(pos);
+    }
+
+    !lowered.is_empty()
+}
+
+// Winch every lowered portcullis back up. Returns whether any were down.
+
+pub fn raise_portcullises(&mut self) -> bool {
+    let mut raised = Vec::new();
+
+    for item in &mut self.items {
+        item.kind = match item.kind {
+            ItemKind::PortcullisLoweredNS => ItemKind::PortcullisNS,
+            ItemKind::PortcullisLoweredEW => ItemKind::PortcullisEW,
+            _ => continue,
+        };
+
+        raised.push(item.pos);
+    }
+
+    for &pos in &raised {
+        self.update_cell_info(pos);
+    }
+
+    !raised.is_empty()
+}
+
+pub fn set_light_lit(&mut self, i_light: usize, lit: bool) {
+    self.lights[i_light].lit = lit;
+    self.compute_lighting();
+}
+
+// Call mark on every cell visible from pos_viewer within the given squared
+// radius (in half-cell units).
+
+fn cast<F: FnMut(&mut Cell)>(&mut self, pos_viewer: Point, radius_squared: i32, mark: &mut F) {
     for portal in &PORTAL {
         self.compute_visibility
         (
+            mark,
+            radius_squared,
             pos_viewer.x, pos_viewer.y,
             pos_viewer.x, pos_viewer.y,
             portal.lx, portal.ly,
@@ -409,8 +917,10 @@ pub fn recompute_visibility(&mut self, pos_viewer: Point) {
     }
 }
 
-fn compute_visibility(
+fn compute_visibility<F: FnMut(&mut Cell)>(
     &mut self,
+    mark: &mut F,
+    radius_squared: i32,
     // Viewer map coordinates:
     viewer_x: i32,
     viewer_y: i32,
@@ -432,7 +942,7 @@ fn compute_visibility(
     // End recursion if the target square is too far away.
     let (dx, dy) = (2 * (target_x - viewer_x), 2 * (target_y - viewer_y));
 
-    if dx*dx + dy*dy > 1600 {
+    if dx*dx + dy*dy > radius_squared {
         return;
     }
 
@@ -442,7 +952,7 @@ fn compute_visibility(
     }
 
     // This square is visible.
-    self.cells[[target_x as usize, target_y as usize]].seen = true;
+    mark(&mut self.cells[[target_x as usize, target_y as usize]]);
 
     // End recursion if the target square occludes the view.
     if self.blocks_player_sight(target_x, target_y) {
@@ -463,7 +973,7 @@ fn compute_visibility(
                (ny as usize) < self.cells.extents()[1] &&
                !a_right_of_b(ldx, ldy, cdx, cdy) &&
                !a_right_of_b(cdx, cdy, rdx, rdy) {
-                self.cells[[nx as usize, ny as usize]].seen = true;
+                mark(&mut self.cells[[nx as usize, ny as usize]]);
             }
         }
     }
@@ -490,6 +1000,8 @@ fn compute_visibility(
         if a_right_of_b(crdx, crdy, cldx, cldy) {
             self.compute_visibility
             (
+                mark,
+                radius_squared,
                 viewer_x, viewer_y,
                 target_x + portal.nx, target_y + portal.ny,
                 cldx, cldy,
@@ -523,6 +1035,17 @@ pub fn random_neighbor_region(&self, rng: &mut MyRng, region: usize, region_excl
         return region;
     }
 
+    // Don't send a guard through a doorway that another guard is about to come
+    // through the other way, unless there's no other choice.
+
+    let oncoming = |neighbor: usize| self.guards.iter().any(|guard| {
+        guard.mode == GuardMode::Patrol && guard.region_goal == region && guard.region_prev == neighbor
+    });
+
+    if neighbors.iter().any(|&neighbor| !oncoming(neighbor)) {
+        neighbors.retain(|&neighbor| !oncoming(neighbor));
+    }
+
     return neighbors[rng.gen_range(0, neighbors.len())];
 }
 
@@ -560,34 +1083,12 @@ pub fn pos_blocked_by_guard(&self, pos: Point) -> bool {
 }
 
 pub fn closest_region(&self, pos: &Point) -> usize {
-
-    #[derive(Copy, Clone, Eq, PartialEq)]
-    struct State {
-        dist: usize,
-        pos: Point,
-    }
-
-    impl Ord for State {
-        fn cmp(&self, other: &State) -> Ordering {
-            other.dist.cmp(&self.dist)
-        }
-    }
-
-    impl PartialOrd for State {
-        fn partial_cmp(&self, other: &State) -> Option<Ordering> {
-            Some(self.cmp(other))
-        }
-    }
-
     let mut heap = BinaryHeap::with_capacity(self.cells.extents()[0] * self.cells.extents()[1]);
     let mut dist_field: Array2D<usize> = Array2D::new([self.cells.extents()[0], self.cells.extents()[1]], INFINITE_COST);
 
-    heap.push(State{dist: 0, pos: *pos});
+    heap.push(PathState{priority: 0, dist: 0, pos: *pos});
 
-    let size_x = self.cells.extents()[0] as i32;
-    let size_y = self.cells.extents()[1] as i32;
-
-    while let Some(State {dist, pos}) = heap.pop() {
+    while let Some(PathState {dist, pos, ..}) = heap.pop() {
         let p = [pos.x as usize, pos.y as usize];
 
         if self.cells[p].region != INVALID_REGION {
@@ -600,26 +1101,23 @@ pub fn closest_region(&self, pos: &Point) -> usize {
 
         dist_field[p] = dist;
 
-        for (move_dir_cost, dir) in &ADJACENT_MOVES {
-            let pos_new = pos + *dir;
-            if pos_new.x < 0 || pos_new.y < 0 || pos_new.x >= size_x || pos_new.y >= size_y {
-                continue;
-            }
+        self.push_neighbors(&mut heap, &dist_field, pos, dist, |_| 0);
+    }
 
-            let move_cost = self.guard_move_cost(pos, pos_new);
-            if move_cost == INFINITE_COST {
-                continue;
-            }
+    INVALID_REGION
+}
 
-            let dist_new = dist + move_cost + move_dir_cost;
+// The distance field to a patrol region, from the cache if it has one.
 
-            if dist_new < dist_field[[pos_new.x as usize, pos_new.y as usize]] {
-                heap.push(State{dist: dist_new, pos: pos_new});
-            }
-        }
+pub fn distances_to_region(&self, i_region_goal: usize) -> Rc<Array2D<usize>> {
+    let mut to_region = self.distance_fields.to_region.borrow_mut();
+
+    if to_region.len() != self.patrol_regions.len() {
+        to_region.clear();
+        to_region.resize(self.patrol_regions.len(), None);
     }
 
-    INVALID_REGION
+    to_region[i_region_goal].get_or_insert_with(|| Rc::new(self.compute_distances_to_region(i_region_goal))).clone()
 }
 
 pub fn compute_distances_to_region(&self, i_region_goal: usize) -> Array2D<usize> {
@@ -650,37 +1148,69 @@ pub fn compute_distances_to_position(&self, pos_goal: Point) -> Array2D<usize> {
     self.compute_distance_field(&[(0, pos_goal)])
 }
 
-pub fn compute_distance_field(&self, initial_distances: &[(usize, Point)]) -> Array2D<usize> {
+// Distances to a position, filled in only as far as is needed to choose a
+// guard's next step from pos_from. This is an A* search from the goal toward
+// pos_from, which stops once every cell a step from pos_from that could be the
+// best next step has its final distance. Cells it didn't get to are left at
+// INFINITE_COST, and are all farther from the goal than the best step.
 
-    #[derive(Copy, Clone, Eq, PartialEq)]
-    struct State {
-        dist: usize,
-        pos: Point,
-    }
+pub fn compute_distances_to_position_from(&self, pos_goal: Point, pos_from: Point) -> Array2D<usize> {
+    assert!(pos_goal.x >= 0);
+    assert!(pos_goal.y >= 0);
+    assert!(pos_goal.x < self.cells.extents()[0] as i32);
+    assert!(pos_goal.y < self.cells.extents()[1] as i32);
+
+    // A lower bound on the cost of getting from a cell to pos_from, using the
+    // cost of the moves alone. It is at most MAX_STEP_ESTIMATE for the cells
+    // right around pos_from.
+
+    const MAX_STEP_ESTIMATE: usize = 3;
+
+    let estimate = |pos: Point| {
+        let dx = (pos.x - pos_from.x).unsigned_abs() as usize;
+        let dy = (pos.y - pos_from.y).unsigned_abs() as usize;
+        3 * min(dx, dy) + 2 * (max(dx, dy) - min(dx, dy))
+    };
+
+    let mut heap = BinaryHeap::new();
+    let mut dist_field: Array2D<usize> = Array2D::new([self.cells.extents()[0], self.cells.extents()[1]], INFINITE_COST);
+
+    heap.push(PathState{priority: estimate(pos_goal), dist: 0, pos: pos_goal});
+
+    let mut dist_best_step = INFINITE_COST;
 
-    impl Ord for State {
-        fn cmp(&self, other: &State) -> Ordering {
-            other.dist.cmp(&self.dist)
+    while let Some(PathState {priority, dist, pos}) = heap.pop() {
+        if dist_best_step != INFINITE_COST && priority > dist_best_step + MAX_STEP_ESTIMATE {
+            break;
         }
-    }
 
-    impl PartialOrd for State {
-        fn partial_cmp(&self, other: &State) -> Option<Ordering> {
-            Some(self.cmp(other))
+        let p = [pos.x as usize, pos.y as usize];
+        if dist >= dist_field[p] {
+            continue;
         }
+
+        dist_field[p] = dist;
+
+        let d = pos - pos_from;
+        if d.x.abs() < 2 && d.y.abs() < 2 && self.guard_can_step(pos_from, pos) && !self.pos_blocked_by_guard(pos) {
+            dist_best_step = min(dist_best_step, dist);
+        }
+
+        self.push_neighbors(&mut heap, &dist_field, pos, dist, estimate);
     }
 
+    dist_field
+}
+
+pub fn compute_distance_field(&self, initial_distances: &[(usize, Point)]) -> Array2D<usize> {
     let mut heap = BinaryHeap::with_capacity(self.cells.extents()[0] * self.cells.extents()[1]);
     let mut dist_field: Array2D<usize> = Array2D::new([self.cells.extents()[0], self.cells.extents()[1]], INFINITE_COST);
 
-    let size_x = self.cells.extents()[0] as i32;
-    let size_y = self.cells.extents()[1] as i32;
-
     for (dist, pos) in initial_distances {
-        heap.push(State{dist: *dist, pos: *pos});
+        heap.push(PathState{priority: *dist, dist: *dist, pos: *pos});
     }
 
-    while let Some(State {dist, pos}) = heap.pop() {
+    while let Some(PathState {dist, pos, ..}) = heap.pop() {
         let p = [pos.x as usize, pos.y as usize];
         if dist >= dist_field[p] {
             continue;
@@ -688,26 +1218,44 @@ pub fn compute_distance_field(&self, initial_distances: &[(usize, Point)]) -> Ar
 
         dist_field[p] = dist;
 
-        for (move_dir_cost, dir) in &ADJACENT_MOVES {
-            let pos_new = pos + *dir;
-            if pos_new.x < 0 || pos_new.y < 0 || pos_new.x >= size_x || pos_new.y >= size_y {
-                continue;
-            }
+        self.push_neighbors(&mut heap, &dist_field, pos, dist, |_| 0);
+    }
 
-            let move_cost = self.guard_move_cost(pos, pos_new);
-            if move_cost == INFINITE_COST {
-                continue;
-            }
+    dist_field
+}
 
-            let dist_new = dist + move_cost + move_dir_cost;
+// Queue up the moves out of pos that improve on what's known. The priority is
+// the distance plus the estimate of what remains.
 
-            if dist_new < dist_field[[pos_new.x as usize, pos_new.y as usize]] {
-                heap.push(State{dist: dist_new, pos: pos_new});
-            }
+fn push_neighbors<F: Fn(Point) -> usize>(&self, heap: &mut BinaryHeap<PathState>, dist_field: &Array2D<usize>, pos: Point, dist: usize, estimate: F) {
+    let size_x = self.cells.extents()[0] as i32;
+    let size_y = self.cells.extents()[1] as i32;
+
+    for (move_dir_cost, dir) in &ADJACENT_MOVES {
+        let pos_new = pos + *dir;
+        if pos_new.x < 0 || pos_new.y < 0 || pos_new.x >= size_x || pos_new.y >= size_y {
+            continue;
+        }
+
+        let move_cost = self.guard_move_cost(pos, pos_new);
+        if move_cost == INFINITE_COST {
+            continue;
+        }
+
+        let dist_new = dist + move_cost + move_dir_cost;
+
+        if dist_new < dist_field[[pos_new.x as usize, pos_new.y as usize]] {
+            heap.push(PathState{priority: dist_new + estimate(pos_new), dist: dist_new, pos: pos_new});
         }
     }
+}
 
-    dist_field
+// Whether a guard at pos_from may take a step to pos (or stay put, if they are
+// the same). Guards don't wade into water.
+
+pub fn guard_can_step(&self, pos_from: Point, pos: Point) -> bool {
+    self.guard_move_cost(pos_from, pos) != INFINITE_COST &&
+        self.cells[[pos.x as usize, pos.y as usize]].cell_type != CellType::GroundWater
 }
 
 pub fn blocks_sight(&self, x: i32, y: i32) -> bool {
@@ -723,6 +1271,25 @@ pub fn hides_player(&self, x: i32, y: i32) -> bool {
 }
 
 pub fn find_guards_in_earshot(&mut self, emitter_pos: Point, radius: i32) -> Vec<&mut Guard> {
+    let visited = self.sound_reach(emitter_pos, radius);
+
+    // Return guards that are on marked squares.
+
+    let mut guards = Vec::with_capacity(self.guards.len());
+
+    for guard in &mut self.guards {
+        if visited[[guard.pos.x as usize, guard.pos.y as usize]] {
+            guards.push(guard);
+        }
+    }
+
+    guards
+}
+
+// The cells a sound made at emitter_pos can be heard in. Sound spreads around
+// corners but not through walls, and dies out at the (squared) radius.
+
+pub fn sound_reach(&self, emitter_pos: Point, radius: i32) -> Array2D<bool> {
     let mut visited: Array2D<bool> = Array2D::new([self.cells.extents()[0], self.cells.extents()[1]], false);
 
     // Flood-fill from the emitter position.
@@ -767,17 +1334,7 @@ pub fn find_guards_in_earshot(&mut self, emitter_pos: Point, radius: i32) -> Vec
         }
     }
 
-    // Return guards that are on marked squares.
-
-    let mut guards = Vec::with_capacity(self.guards.len());
-
-    for guard in &mut self.guards {
-        if visited[[guard.pos.x as usize, guard.pos.y as usize]] {
-            guards.push(guard);
-        }
-    }
-
-    guards
+    visited
 }
 
 }
@@ -0,0 +1,225 @@
+use quicksilver::{
+    geom::{Line, Rectangle, Vector},
+    graphics::{Background::Blended, Background::Col, Color, Image},
+    lifecycle::Window,
+};
+use thiefrl3::cell_grid::*;
+use thiefrl3::color_preset;
+use thiefrl3::game::NOISE_EARSHOT;
+use crate::rgba;
+use crate::speech_bubbles::{get_horizontal_extents, puts_proportional};
+use crate::{BAR_HEIGHT, TILE_SIZE};
+
+// Overlays for seeing what the guards' pathing and hearing are working with.
+// Ctrl+F2 cycles through them; Ctrl+F3 picks which guard the distance field is for.
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DebugLayer {
+    DistanceField, // to where the selected guard is heading, with its patrol regions
+    PatrolRegions, // every patrol region, and the routes between them
+    MoveCost,      // how much each cell costs a guard to enter
+    SoundReach,    // where a noise made by the thief could be heard
+}
+
+pub struct DebugView {
+    pub layer: Option<DebugLayer>,
+    pub i_guard: usize,
+}
+
+const LABEL_BACKGROUND_COLOR: Color = Color { r: 0.0, g: 0.0, b: 0.0, a: 0.75 };
+
+const REGION_COLORS: [color_preset::Color; 6] = [
+    color_preset::LIGHT_RED,
+    color_preset::LIGHT_GREEN,
+    color_preset::LIGHT_BLUE,
+    color_preset::LIGHT_YELLOW,
+    color_preset::LIGHT_CYAN,
+    color_preset::LIGHT_MAGENTA,
+];
+
+impl DebugView {
+    pub fn new() -> DebugView {
+        DebugView { layer: None, i_guard: 0 }
+    }
+
+    pub fn cycle_layer(&mut self) {
+        self.layer = match self.layer {
+            None => Some(DebugLayer::DistanceField),
+            Some(DebugLayer::DistanceField) => Some(DebugLayer::PatrolRegions),
+            Some(DebugLayer::PatrolRegions) => Some(DebugLayer::MoveCost),
+            Some(DebugLayer::MoveCost) => Some(DebugLayer::SoundReach),
+            Some(DebugLayer::SoundReach) => None,
+        };
+    }
+
+    pub fn select_next_guard(&mut self, num_guards: usize) {
+        self.i_guard = if num_guards == 0 {0} else {(self.i_guard + 1) % num_guards};
+    }
+
+    pub fn draw(&self, window: &mut Window, font_image: &Image, tileset: &[Image], map: &Map, player: &Player, offset_px: Vector) {
+        let layer = match self.layer {
+            Some(layer) => layer,
+            None => return,
+        };
+
+        let label = match layer {
+            DebugLayer::DistanceField => {
+                match map.guards.get(self.i_guard) {
+                    Some(guard) => draw_distance_field(window, tileset, map, guard, offset_px),
+                    None => (),
+                }
+                format!("Distance field: guard {} of {} (Ctrl+F3 for next)", self.i_guard + 1, map.guards.len())
+            },
+            DebugLayer::PatrolRegions => {
+                draw_patrol_regions(window, map, offset_px);
+                format!("Patrol regions: {}, routes: {}", map.patrol_regions.len(), map.patrol_routes.len())
+            },
+            DebugLayer::MoveCost => {
+                draw_move_costs(window, map, offset_px);
+                "Guard move cost".to_string()
+            },
+            DebugLayer::SoundReach => {
+                draw_sound_reach(window, map, player.pos, offset_px);
+                "Reach of the thief's noises".to_string()
+            },
+        };
+
+        draw_label(window, font_image, &label);
+    }
+}
+
+fn cell_px(map: &Map, pos: Point, offset_px: Vector) -> Vector {
+    let map_size_y = map.cells.extents()[1] as i32;
+    offset_px + TILE_SIZE.times(Vector::new(pos.x, (map_size_y - 1) - pos.y))
+}
+
+fn shade_cell(window: &mut Window, map: &Map, pos: Point, offset_px: Vector, color: Color) {
+    window.draw(&Rectangle::new(cell_px(map, pos, offset_px), TILE_SIZE), Col(color));
+}
+
+fn shade_region(window: &mut Window, map: &Map, region: &Rect, offset_px: Vector, color: Color) {
+    for x in region.pos_min.x .. region.pos_max.x {
+        for y in region.pos_min.y .. region.pos_max.y {
+            shade_cell(window, map, Point::new(x, y), offset_px, color);
+        }
+    }
+}
+
+fn with_alpha(color: color_preset::Color, a: f32) -> Color {
+    Color { a, ..rgba(color) }
+}
+
+// The distance field the guard is following this turn, drawn as its last digit
+// and colored by tens. Off patrol it is the bounded field the guard steps by,
+// so cells past where the search stopped are left blank. The region it came from is tinted red, and the one it's
+// heading for green.
+
+fn draw_distance_field(window: &mut Window, tileset: &[Image], map: &Map, guard: &Guard, offset_px: Vector) {
+    if guard.region_prev != INVALID_REGION {
+        shade_region(window, map, &map.patrol_regions[guard.region_prev], offset_px, with_alpha(color_preset::LIGHT_RED, 0.25));
+    }
+
+    if guard.region_goal != INVALID_REGION {
+        shade_region(window, map, &map.patrol_regions[guard.region_goal], offset_px, with_alpha(color_preset::LIGHT_GREEN, 0.25));
+    }
+
+    let distance_field =
+        if guard.mode == GuardMode::Patrol {
+            if guard.region_goal == INVALID_REGION {
+                return;
+            }
+            map.distances_to_region(guard.region_goal)
+        } else {
+            std::rc::Rc::new(map.compute_distances_to_position_from(guard.goal, guard.pos))
+        };
+
+    for x in 0..map.cells.extents()[0] {
+        for y in 0..map.cells.extents()[1] {
+            let d = distance_field[[x, y]];
+            if d == 0 || d == INFINITE_COST {
+                continue;
+            }
+            let digit = (d % 10) + 48;
+            let band = d / 10;
+            let image = &tileset[digit];
+            let pos_px = cell_px(map, Point::new(x as i32, y as i32), offset_px);
+            let color = if band == 0 {rgba(color_preset::WHITE)} else if band == 1 {rgba(color_preset::LIGHT_YELLOW)} else {rgba(color_preset::DARK_GRAY)};
+            window.draw(
+                &Rectangle::new(pos_px, image.area().size()),
+                Blended(image, color),
+            )
+        }
+    }
+
+    let pos_px = cell_px(map, guard.pos, offset_px);
+    window.draw(&Line::new(pos_px, pos_px + TILE_SIZE).with_thickness(2.0), Col(rgba(color_preset::WHITE)));
+    window.draw(&Line::new(pos_px + Vector::new(0, TILE_SIZE.y), pos_px + Vector::new(TILE_SIZE.x, 0)).with_thickness(2.0), Col(rgba(color_preset::WHITE)));
+}
+
+fn draw_patrol_regions(window: &mut Window, map: &Map, offset_px: Vector) {
+    for (i_region, region) in map.patrol_regions.iter().enumerate() {
+        let color = with_alpha(REGION_COLORS[i_region % REGION_COLORS.len()], 0.25);
+        shade_region(window, map, region, offset_px, color);
+    }
+
+    let region_center_px = |region: &Rect| {
+        let pos_min = cell_px(map, Point::new(region.pos_min.x, region.pos_max.y - 1), offset_px);
+        let pos_max = cell_px(map, Point::new(region.pos_max.x - 1, region.pos_min.y), offset_px) + TILE_SIZE;
+        (pos_min + pos_max) * 0.5
+    };
+
+    for &(region0, region1) in &map.patrol_routes {
+        let pos0 = region_center_px(&map.patrol_regions[region0]);
+        let pos1 = region_center_px(&map.patrol_regions[region1]);
+        window.draw(&Line::new(pos0, pos1).with_thickness(2.0), Col(rgba(color_preset::WHITE)));
+    }
+}
+
+// Impassable cells are dark; the rest get redder the more they cost.
+
+fn draw_move_costs(window: &mut Window, map: &Map, offset_px: Vector) {
+    const MAX_SHOWN_COST: usize = 10;
+
+    for x in 0..map.cells.extents()[0] {
+        for y in 0..map.cells.extents()[1] {
+            let move_cost = map.cells[[x, y]].move_cost;
+            let color =
+                if move_cost == INFINITE_COST {
+                    Color { r: 0.0, g: 0.0, b: 0.0, a: 0.5 }
+                } else if move_cost == 0 {
+                    continue;
+                } else {
+                    with_alpha(color_preset::LIGHT_RED, 0.75 * move_cost.min(MAX_SHOWN_COST) as f32 / MAX_SHOWN_COST as f32)
+                };
+            shade_cell(window, map, Point::new(x as i32, y as i32), offset_px, color);
+        }
+    }
+}
+
+fn draw_sound_reach(window: &mut Window, map: &Map, pos_player: Point, offset_px: Vector) {
+    let reach = map.sound_reach(pos_player, NOISE_EARSHOT);
+    let color = with_alpha(color_preset::LIGHT_CYAN, 0.3);
+
+    for x in 0..map.cells.extents()[0] {
+        for y in 0..map.cells.extents()[1] {
+            if reach[[x, y]] {
+                shade_cell(window, map, Point::new(x as i32, y as i32), offset_px, color);
+            }
+        }
+    }
+}
+
+// The name of the layer, in the top-right corner just under the status bar.
+
+fn draw_label(window: &mut Window, font_image: &Image, label: &str) {
+    let (x_min, x_max) = get_horizontal_extents(label);
+    let x = window.screen_size().x as i32 - (8 + (x_max - x_min));
+    let y = BAR_HEIGHT;
+
+    window.draw(
+        &Rectangle::new((x - 8, y), (x_max - x_min + 16, BAR_HEIGHT - 2)),
+        Col(LABEL_BACKGROUND_COLOR),
+    );
+
+    puts_proportional(window, font_image, x, y, label, &rgba(color_preset::LIGHT_CYAN));
+}
@@ -11,6 +11,7 @@ pub struct Glyph {
 
 #[allow(dead_code)]
 pub const LINE_HEIGHT: i32 = 26;
+#[allow(dead_code)]
 pub const BASE: i32 = 21;
 pub static GLYPH: [Glyph; 336] = [
     Glyph { id: 32, x: 12, y: 41, width: 1, height: 1, x_offset: 0, y_offset: 25, x_advance: 5 },
@@ -0,0 +1,1020 @@
+use crate::campaign;
+use crate::cell_grid::*;
+use crate::guard::*;
+use crate::inventory::*;
+
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize)]
+pub struct GameState {
+    pub rng: MyRng,
+    pub seed: u64, // the game was started from this, as was its replay
+    pub num_steps: usize, // actions taken so far; the replay has a step for each
+    pub level: usize,
+    pub level_seed: u64, // the current level is generated from this, so it can be restarted
+    pub level_file: Option<String>, // text of a level file being tried out in place of the first level
+    pub map: Map,
+    #[serde(skip, default = "new_lines")]
+    pub lines: Lines,
+    pub player: Player,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Action {
+    Move(#[serde(with = "crate::save::point")] Point),
+    Wait,
+    ToggleDoor(#[serde(with = "crate::save::point")] Point), // open or close the door in this direction
+    PickLock(#[serde(with = "crate::save::point")] Point), // work at the lock in this direction until it opens or we're interrupted
+    DouseLight,
+    Knockout(#[serde(with = "crate::save::point")] Point), // strike the guard in this direction from behind
+    Drag(#[serde(with = "crate::save::point")] Point), // take hold of the body in this direction, or let go of the one being dragged
+    UseTool { tool: ToolKind, #[serde(with = "crate::save::point")] target: Point }, // throw a tool at the target cell
+    RestartLevel,
+
+    // Debug commands; these do not advance time.
+    ToggleSeeAll,
+    MarkAllSeen,
+    MarkAllUnseen,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum GameEvent {
+    TurnStarted,
+    LevelStarted { level: usize },
+    GuardSpoke { pos: Point, text: &'static str },
+    GuardShouted { pos: Point, text: &'static str }, // heard by guards farther away, and always shown
+    GuardModeChanged { pos: Point, mode_prev: GuardMode, mode: GuardMode },
+    NoiseMade { pos: Point, text: &'static str },
+    LootCollected { pos: Point, amount: usize },
+    LightDoused { pos: Point, kind: LightKind },
+    LightRelit { pos: Point, kind: LightKind },
+    PortcullisesLowered,
+    PortcullisesRaised,
+    DoorLocked { pos: Point },
+    DoorUnlocked { pos: Point, picked: bool },
+    LockpickInterrupted { pos: Point },
+    KeyCollected { pos: Point, from_guard: bool },
+    ToolCollected { pos: Point, tool: ToolKind, count: usize },
+    ToolUsed { pos: Point, tool: ToolKind },
+    GuardKnockedOut { pos: Point },
+    KnockoutFailed { pos: Point },
+    BodyGrabbed { pos: Point },
+    BodyDropped { pos: Point },
+    PlayerDamaged { amount: usize, text: &'static str },
+    LevelCompleted,
+    PlayerDied,
+}
+
+// How far (squared) the thief's noises carry.
+
+pub const NOISE_EARSHOT: i32 = 75;
+
+// Picking a lock takes this many turns, and makes a noise every few of them.
+
+const LOCKPICK_TURNS: usize = 8;
+const LOCKPICK_NOISE_INTERVAL: usize = 3;
+
+const DIRS: [Point; 4] = [
+    Point { x: -1, y:  0 },
+    Point { x:  1, y:  0 },
+    Point { x:  0, y: -1 },
+    Point { x:  0, y:  1 },
+];
+
+impl GameState {
+
+pub fn new(seed: u64) -> GameState {
+    GameState::with_level_file(seed, None)
+}
+
+pub fn with_level_file(seed: u64, level_file: Option<String>) -> GameState {
+    let mut rng = MyRng::seed_from_u64(seed);
+    let level = 0;
+    let level_seed = rng.gen::<u64>();
+    let mut map = campaign::make_level(level_seed, level, level_file.as_deref());
+    let player = make_player(&map.pos_start);
+    let lines = new_lines();
+
+    update_map_visibility(&mut map, player.pos);
+
+    GameState {
+        rng,
+        seed,
+        num_steps: 0,
+        level,
+        level_seed,
+        level_file,
+        map,
+        lines,
+        player,
+    }
+}
+
+pub fn step(&mut self, action: Action) -> Vec<GameEvent> {
+    let mut events = Vec::new();
+    self.num_steps += 1;
+
+    match action {
+        Action::Move(dir) => self.move_player(&mut events, dir.x, dir.y),
+        Action::Wait => self.move_player(&mut events, 0, 0),
+        Action::ToggleDoor(dir) => self.toggle_door(&mut events, dir),
+        Action::PickLock(dir) => self.pick_lock(&mut events, dir),
+        Action::DouseLight => self.douse_light(&mut events),
+        Action::Knockout(dir) => self.knockout(&mut events, dir),
+        Action::Drag(dir) => self.drag(&mut events, dir),
+        Action::UseTool { tool, target } => self.use_tool(&mut events, tool, target),
+        Action::RestartLevel => self.restart_level(&mut events),
+        Action::ToggleSeeAll => self.player.see_all = !self.player.see_all,
+        Action::MarkAllSeen => self.map.mark_all_seen(),
+        Action::MarkAllUnseen => {
+            self.map.mark_all_unseen();
+            update_map_visibility(&mut self.map, self.player.pos);
+        },
+    }
+
+    events
+}
+
+fn move_player(&mut self, events: &mut Vec<GameEvent>, mut dx: i32, mut dy: i32) {
+    let player = &mut self.player;
+
+    // Can't move if you're dead.
+
+    if player.health == 0 {
+        return;
+    }
+
+    // Are we trying to exit the level?
+
+    let pos_new = Point::new(player.pos.x + dx, player.pos.y + dy);
+
+    if !on_level(&self.map.cells, pos_new) && self.map.all_seen() && self.map.all_loot_collected() {
+        self.advance_level(events);
+        return;
+    }
+
+    // Walking into a closed door opens it, if we have the key to it.
+
+    if let Some(i_door) = self.map.locked_door_at(pos_new) {
+        self.open_locked_door(events, i_door);
+        return;
+    }
+
+    if let Some(i_door) = self.map.closed_door_at(pos_new) {
+        self.pre_turn(events);
+        self.map.set_door_open(i_door, true);
+        self.advance_time(events);
+        return;
+    }
+
+    // Working the winch raises any lowered portcullises, noisily.
+
+    if self.map.winch_at(pos_new).is_some() {
+        if self.map.portcullises_lowered() {
+            self.pre_turn(events);
+            self.map.raise_portcullises();
+            events.push(GameEvent::PortcullisesRaised);
+            make_noise(&mut self.map, &mut self.player, events, "\u{AE}clank clank\u{AF}");
+            self.advance_time(events);
+        }
+        return;
+    }
+
+    // Bumping into a patrolling guard from behind, or an unconscious one, lifts its key.
+
+    if let Some(guard) = self.map.guards.iter_mut().find(|guard| guard.pos == pos_new) {
+        if guard.unconscious() || (guard.mode == GuardMode::Patrol && Point::dot(guard.dir, self.player.pos - guard.pos) < 0) {
+            if let Some(key) = guard.key.take() {
+                self.pre_turn(events);
+                self.player.keys.push(key);
+                events.push(GameEvent::KeyCollected { pos: pos_new, from_guard: true });
+                self.advance_time(events);
+                return;
+            }
+        }
+    }
+
+    let player = &self.player;
+
+    if dx == 0 || dy == 0 {
+        if blocked(&self.map, &player.pos, &pos_new) {
+            return;
+        }
+    } else if blocked(&self.map, &player.pos, &pos_new) {
+        if halts_slide(&self.map, &pos_new) {
+            return;
+        } else {
+            // Attempting to move diagonally; may be able to slide along a wall.
+
+            let v_blocked = blocked(&self.map, &player.pos, &(player.pos + Point::new(dx, 0)));
+            let h_blocked = blocked(&self.map, &player.pos, &(player.pos + Point::new(0, dy)));
+
+            if v_blocked {
+                if h_blocked {
+                    return;
+                }
+
+                dx = 0;
+            } else {
+                if !h_blocked {
+                    return;
+                }
+
+                dy = 0;
+            }
+        }
+    }
+
+    self.pre_turn(events);
+
+    let dpos = Point::new(dx, dy);
+    let pos_old = self.player.pos;
+    self.player.dir = dpos;
+    self.player.pos += dpos;
+
+    self.drag_body(events, pos_old);
+
+    let loot = self.map.collect_loot_at(self.player.pos);
+    if loot > 0 {
+        self.player.gold += loot;
+        events.push(GameEvent::LootCollected { pos: self.player.pos, amount: loot });
+    }
+
+    if let Some(i_key) = self.map.keys.iter().position(|key| key.pos == self.player.pos) {
+        let key = self.map.keys.remove(i_key);
+        self.player.keys.push(key);
+        events.push(GameEvent::KeyCollected { pos: key.pos, from_guard: false });
+    }
+
+    while let Some(i_item) = self.map.items.iter().position(|item| item.pos == self.player.pos && tool_for_item(item.kind).is_some()) {
+        let (tool, count) = tool_for_item(self.map.items.remove(i_item).kind).unwrap();
+        self.player.inventory.add(tool, count);
+        events.push(GameEvent::ToolCollected { pos: self.player.pos, tool, count });
+    }
+
+    // Generate movement noises.
+
+    let cell_type = self.map.cells[[self.player.pos.x as usize, self.player.pos.y as usize]].cell_type;
+
+    if cell_type == CellType::GroundWoodCreaky {
+        make_noise(&mut self.map, &mut self.player, events, "\u{AE}creak\u{AF}");
+    }
+
+    self.advance_time(events);
+}
+
+fn toggle_door(&mut self, events: &mut Vec<GameEvent>, dir: Point) {
+    if self.player.health == 0 || dir == Point::new(0, 0) {
+        return;
+    }
+
+    let pos = self.player.pos + dir;
+
+    let i_door = match self.map.door_at(pos) {
+        Some(i_door) => i_door,
+        None => return,
+    };
+
+    if is_locked_door(self.map.items[i_door].kind) {
+        self.open_locked_door(events, i_door);
+        return;
+    }
+
+    let open = is_closed_door(self.map.items[i_door].kind);
+
+    if !open && is_guard_at(&self.map, pos.x, pos.y) {
+        return;
+    }
+
+    self.pre_turn(events);
+    self.map.set_door_open(i_door, open);
+    self.advance_time(events);
+}
+
+fn open_locked_door(&mut self, events: &mut Vec<GameEvent>, i_door: usize) {
+    let pos = self.map.items[i_door].pos;
+
+    if !self.player.keys.iter().any(|key| key.door == pos) {
+        events.push(GameEvent::DoorLocked { pos });
+        return;
+    }
+
+    self.pre_turn(events);
+    self.map.set_door_open(i_door, true);
+    events.push(GameEvent::DoorUnlocked { pos, picked: false });
+    self.advance_time(events);
+}
+
+// Work at a lock until it opens, a guard notices something, or we get hurt.
+// Progress on a lock is kept, so an interrupted lock can be finished later.
+
+fn pick_lock(&mut self, events: &mut Vec<GameEvent>, dir: Point) {
+    if self.player.health == 0 || dir == Point::new(0, 0) {
+        return;
+    }
+
+    let pos = self.player.pos + dir;
+
+    let i_door = match self.map.locked_door_at(pos) {
+        Some(i_door) => i_door,
+        None => return,
+    };
+
+    if self.player.lockpick_pos != pos {
+        self.player.lockpick_pos = pos;
+        self.player.lockpick_turns = 0;
+    }
+
+    loop {
+        let i_event_first = events.len();
+
+        self.pre_turn(events);
+
+        self.player.lockpick_turns += 1;
+
+        if self.player.lockpick_turns >= LOCKPICK_TURNS {
+            self.player.lockpick_turns = 0;
+            self.map.set_door_locked(i_door, false);
+            events.push(GameEvent::DoorUnlocked { pos, picked: true });
+            self.advance_time(events);
+            return;
+        }
+
+        if self.player.lockpick_turns.is_multiple_of(LOCKPICK_NOISE_INTERVAL) {
+            make_noise(&mut self.map, &mut self.player, events, "\u{AE}click\u{AF}");
+        }
+
+        self.advance_time(events);
+
+        // A guard may have come through and unlocked it in the meantime.
+
+        if !is_locked_door(self.map.items[i_door].kind) {
+            return;
+        }
+
+        let interrupted = self.player.health == 0 || events[i_event_first..].iter().any(|event| match event {
+            GameEvent::GuardModeChanged { mode, .. } => *mode != GuardMode::Patrol && *mode != GuardMode::Relight,
+            GameEvent::PlayerDamaged { .. } => true,
+            _ => false,
+        });
+
+        if interrupted {
+            events.push(GameEvent::LockpickInterrupted { pos });
+            return;
+        }
+    }
+}
+
+// Knock out a guard by sneaking up behind it while it is on its rounds. A guard
+// that is on the lookout, or facing us, hears the attempt and turns on us.
+
+fn knockout(&mut self, events: &mut Vec<GameEvent>, dir: Point) {
+    if self.player.health == 0 || dir == Point::new(0, 0) {
+        return;
+    }
+
+    let pos = self.player.pos + dir;
+    let pos_player = self.player.pos;
+
+    let i_guard = match self.map.guards.iter().position(|guard| guard.pos == pos && !guard.unconscious()) {
+        Some(i_guard) => i_guard,
+        None => return,
+    };
+
+    self.pre_turn(events);
+
+    let guard = &mut self.map.guards[i_guard];
+
+    if guard.mode == GuardMode::Patrol && Point::dot(guard.dir, pos_player - guard.pos) < 0 {
+        guard.mode = GuardMode::Unconscious;
+        guard.light_goal = None;
+        guard.door_to_lock = None;
+        events.push(GameEvent::GuardKnockedOut { pos });
+    } else {
+        guard.hear_thief(pos_player);
+        events.push(GameEvent::KnockoutFailed { pos });
+    }
+
+    self.advance_time(events);
+}
+
+// Grabbing or letting go of a body doesn't take a turn; moving with one does.
+
+fn drag(&mut self, events: &mut Vec<GameEvent>, dir: Point) {
+    if self.player.dragging {
+        self.player.dragging = false;
+        events.push(GameEvent::BodyDropped { pos: self.player.drag_pos });
+        return;
+    }
+
+    if self.player.health == 0 || dir == Point::new(0, 0) {
+        return;
+    }
+
+    let pos = self.player.pos + dir;
+
+    if !self.map.guards.iter().any(|guard| guard.pos == pos && guard.unconscious()) {
+        return;
+    }
+
+    if self.map.cells[[self.player.pos.x as usize, self.player.pos.y as usize]].cell_type == CellType::GroundWater {
+        return;
+    }
+
+    self.player.dragging = true;
+    self.player.drag_pos = pos;
+    events.push(GameEvent::BodyGrabbed { pos });
+}
+
+// The body being dragged follows into the cell the player just left. Bodies
+// can't be taken into the water, and a body that has been woken up is lost.
+
+fn drag_body(&mut self, events: &mut Vec<GameEvent>, pos_old: Point) {
+    if !self.player.dragging {
+        return;
+    }
+
+    let drag_pos = self.player.drag_pos;
+
+    let body = match self.map.guards.iter_mut().find(|guard| guard.pos == drag_pos && guard.unconscious()) {
+        Some(body) => body,
+        None => {
+            self.player.dragging = false;
+            return;
+        },
+    };
+
+    if self.map.cells[[self.player.pos.x as usize, self.player.pos.y as usize]].cell_type == CellType::GroundWater {
+        self.player.dragging = false;
+        events.push(GameEvent::BodyDropped { pos: drag_pos });
+        return;
+    }
+
+    body.dir = self.player.pos - pos_old;
+    body.pos = pos_old;
+    self.player.drag_pos = pos_old;
+}
+
+// Put out a lit torch or candle next to the player. Wall-mounted lights can
+// only be reached from the side they shine on.
+
+fn douse_light(&mut self, events: &mut Vec<GameEvent>) {
+    if self.player.health == 0 {
+        return;
+    }
+
+    let pos_player = self.player.pos;
+
+    let i_light = self.map.lights.iter().position(|light| {
+        let d = pos_player - light.pos;
+        light.lit && d.x.abs() < 2 && d.y.abs() < 2 && (light.dir == Point::new(0, 0) || Point::dot(d, light.dir) > 0)
+    });
+
+    let i_light = match i_light {
+        Some(i_light) => i_light,
+        None => return,
+    };
+
+    self.pre_turn(events);
+
+    self.map.set_light_lit(i_light, false);
+
+    let light = &self.map.lights[i_light];
+    events.push(GameEvent::LightDoused { pos: light.pos, kind: light.kind });
+
+    self.advance_time(events);
+}
+
+// Throw a tool at a cell the player can see and reach with a throw.
+
+fn use_tool(&mut self, events: &mut Vec<GameEvent>, tool: ToolKind, target: Point) {
+    if self.player.health == 0 || self.player.inventory.count(tool) == 0 {
+        return;
+    }
+
+    if !self.map.can_throw_to(self.player.pos, target) {
+        return;
+    }
+
+    self.pre_turn(events);
+
+    self.player.inventory.take(tool);
+    events.push(GameEvent::ToolUsed { pos: target, tool });
+
+    match tool {
+        ToolKind::Stone => {
+            make_noise_at(&mut self.map, events, target, "\u{AE}clack\u{AF}");
+        },
+        ToolKind::SmokeBomb => {
+            self.map.add_smoke(target, SMOKE_TURNS);
+        },
+        ToolKind::WaterFlask => {
+            for i_light in 0..self.map.lights.len() {
+                let light = &self.map.lights[i_light];
+                let d = light.pos - target;
+                if light.lit && d.x.abs() < 2 && d.y.abs() < 2 {
+                    events.push(GameEvent::LightDoused { pos: light.pos, kind: light.kind });
+                    self.map.set_light_lit(i_light, false);
+                }
+            }
+        },
+    }
+
+    self.advance_time(events);
+}
+
+fn advance_level(&mut self, events: &mut Vec<GameEvent>) {
+    self.level += 1;
+    self.level_seed = self.rng.gen::<u64>();
+    self.map = campaign::make_level(self.level_seed, self.level, self.level_file.as_deref());
+
+    self.player.pos = self.map.pos_start;
+    self.player.dir = Point::new(0, 0);
+    self.player.gold = 0;
+    self.player.noisy = false;
+    self.player.damaged_last_turn = false;
+    self.player.finished_level = false;
+    self.player.turns_remaining_underwater = 0;
+    self.player.keys.clear();
+    self.player.inventory.clear();
+    self.player.lockpick_turns = 0;
+    self.player.dragging = false;
+    self.player.game_over = false;
+
+    update_map_visibility(&mut self.map, self.player.pos);
+
+    events.push(GameEvent::LevelStarted { level: self.level });
+}
+
+fn restart_level(&mut self, events: &mut Vec<GameEvent>) {
+    self.map = campaign::make_level(self.level_seed, self.level, self.level_file.as_deref());
+
+    let see_all = self.player.see_all;
+    self.player = make_player(&self.map.pos_start);
+    self.player.see_all = see_all;
+
+    update_map_visibility(&mut self.map, self.player.pos);
+
+    events.push(GameEvent::LevelStarted { level: self.level });
+}
+
+fn pre_turn(&mut self, events: &mut Vec<GameEvent>) {
+//  s_show_msgs = true;
+    events.push(GameEvent::TurnStarted);
+    self.player.noisy = false;
+    self.player.damaged_last_turn = false;
+    self.player.dir = Point::new(0, 0);
+}
+
+fn advance_time(&mut self, events: &mut Vec<GameEvent>) {
+    if self.map.cells[[self.player.pos.x as usize, self.player.pos.y as usize]].cell_type == CellType::GroundWater {
+        if self.player.turns_remaining_underwater > 0 {
+            self.player.turns_remaining_underwater -= 1;
+        }
+    } else {
+        self.player.turns_remaining_underwater = 7;
+    }
+
+    let health_prev = self.player.health;
+
+    guard_act_all(&mut self.rng, &mut self.lines, events, &mut self.map, &mut self.player);
+
+    if health_prev > 0 && self.player.health == 0 {
+        events.push(GameEvent::PlayerDied);
+    }
+
+    self.map.update_smoke();
+
+    update_map_visibility(&mut self.map, self.player.pos);
+
+    if !self.player.finished_level && self.map.all_seen() && self.map.all_loot_collected() {
+        self.player.finished_level = true;
+        events.push(GameEvent::LevelCompleted);
+    }
+}
+
+}
+
+fn make_noise(map: &mut Map, player: &mut Player, events: &mut Vec<GameEvent>, noise: &'static str) {
+    player.noisy = true;
+    make_noise_at(map, events, player.pos, noise);
+}
+
+// Guards in earshot go to where the noise came from, which needn't be where the thief is.
+
+fn make_noise_at(map: &mut Map, events: &mut Vec<GameEvent>, pos: Point, noise: &'static str) {
+    events.push(GameEvent::NoiseMade { pos, text: noise });
+
+    let guards = map.find_guards_in_earshot(pos, NOISE_EARSHOT);
+
+    for guard in guards {
+        guard.hear_thief(pos);
+    }
+}
+
+fn halts_slide(map: &Map, pos: &Point) -> bool {
+    if pos.x < 0 || pos.x >= map.cells.extents()[0] as i32 || pos.y < 0 || pos.y >= map.cells.extents()[1] as i32 {
+        return false;
+    }
+
+    if is_guard_at(map, pos.x, pos.y) {
+        return true;
+    }
+
+    false
+}
+
+pub fn update_map_visibility(map: &mut Map, pos_viewer: Point) {
+    map.recompute_visibility(pos_viewer);
+
+    for dir in &DIRS {
+        let pos = pos_viewer + *dir;
+        if !blocked(map, &pos_viewer, &pos) {
+            map.recompute_visibility(pos);
+        }
+    }
+}
+
+pub fn on_level(map: &CellGrid, pos: Point) -> bool {
+    let size_x = map.extents()[0] as i32;
+    let size_y = map.extents()[1] as i32;
+    pos.x >= 0 && pos.y >= 0 && pos.x < size_x && pos.y < size_y
+}
+
+pub fn blocked(map: &Map, pos_old: &Point, pos_new: &Point) -> bool {
+    if !on_level(&map.cells, *pos_new) {
+        return true;
+    }
+
+    let cell = &map.cells[[pos_new.x as usize, pos_new.y as usize]];
+    let tile_type = cell.cell_type;
+
+    if cell.blocks_player {
+        return true;
+    }
+
+    // Closed doors don't block the cell for guards, who open them as they go,
+    // but the thief has to open one by walking straight into it.
+
+    if map.closed_door_at(*pos_new).is_some() {
+        return true;
+    }
+
+    if tile_type == CellType::OneWayWindowE && pos_new.x <= pos_old.x {
+        return true;
+    }
+
+    if tile_type == CellType::OneWayWindowW && pos_new.x >= pos_old.x {
+        return true;
+    }
+
+    if tile_type == CellType::OneWayWindowN && pos_new.y <= pos_old.y {
+        return true;
+    }
+
+    if tile_type == CellType::OneWayWindowS && pos_new.y >= pos_old.y {
+        return true;
+    }
+
+    if is_guard_at(map, pos_new.x, pos_new.y) {
+        return true;
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A walled yard with a gate in the north wall and a winch by it, and two
+    // guards close enough to spot the thief straight away.
+
+    const YARD: &str = "\
+thiefrl3-map 1
+cells
+...........
+.####=####.
+.#.......#.
+.#.......#.
+.#.......#.
+.#########.
+...........
+items
+...........
+.....p.....
+....w......
+..>.@...<..
+...........
+...........
+...........
+moonlit 0 0 11 7
+region 2 2 5 5
+region 6 2 9 5
+route 0 1
+";
+
+    fn game_in(text: &str) -> GameState {
+        let mut game = GameState::new(1);
+        game.map = Map::from_ascii(text).unwrap();
+        game.player = make_player(&game.map.pos_start);
+        update_map_visibility(&mut game.map, game.player.pos);
+        game
+    }
+
+    fn num_chasing(game: &GameState) -> usize {
+        game.map.guards.iter()
+            .filter(|guard| matches!(guard.mode, GuardMode::ChaseVisibleTarget | GuardMode::MoveToLastSighting))
+            .count()
+    }
+
+    #[test]
+    fn winch_keeps_gates_open_during_chase() {
+        let mut game = game_in(YARD);
+
+        for _ in 0..10 {
+            if game.map.portcullises_lowered() {
+                break;
+            }
+            game.step(Action::Wait);
+        }
+        assert!(game.map.portcullises_lowered());
+
+        let events = game.step(Action::Move(Point::new(0, 1)));
+        assert!(events.contains(&GameEvent::PortcullisesRaised));
+        assert_eq!(num_chasing(&game), 2);
+        assert!(!game.map.portcullises_lowered());
+    }
+
+    #[test]
+    fn restart_keeps_level_file() {
+        let mut game = GameState::with_level_file(1, Some(YARD.to_string()));
+        let ascii = game.map.to_ascii();
+        game.step(Action::Wait);
+        game.step(Action::RestartLevel);
+        assert_eq!(game.map.to_ascii(), ascii);
+    }
+
+    // A locked door in the wall north of the thief, with the wall to the east
+    // leaving a diagonal move nowhere to go but through the door.
+
+    const LOCKED_CLOSET: &str = "\
+thiefrl3-map 1
+cells
+#####
+#...#
+##-##
+#..##
+#####
+items
+.....
+.....
+..L..
+..@..
+.....
+";
+
+    #[test]
+    fn locked_door_stops_straight_move() {
+        let mut game = game_in(LOCKED_CLOSET);
+        let events = game.step(Action::Move(Point::new(0, 1)));
+        assert!(events.contains(&GameEvent::DoorLocked { pos: Point::new(2, 2) }));
+        assert_eq!(game.player.pos, Point::new(2, 1));
+    }
+
+    #[test]
+    fn diagonal_move_does_not_slide_through_doors() {
+        let mut game = game_in(LOCKED_CLOSET);
+        game.step(Action::Move(Point::new(1, 1)));
+        assert_eq!(game.player.pos, Point::new(2, 1));
+
+        let mut game = game_in(&LOCKED_CLOSET.replace("..L..", "..+.."));
+        game.step(Action::Move(Point::new(1, 1)));
+        assert_eq!(game.player.pos, Point::new(2, 1));
+        assert!(game.map.closed_door_at(Point::new(2, 2)).is_some());
+    }
+
+    #[test]
+    fn walking_into_a_closed_door_opens_it() {
+        let mut game = game_in(&LOCKED_CLOSET.replace("..L..", "..+.."));
+        let pos_door = Point::new(2, 2);
+
+        let events = game.step(Action::Move(Point::new(0, 1)));
+        assert!(events.contains(&GameEvent::TurnStarted));
+        assert_eq!(game.player.pos, Point::new(2, 1));
+        assert!(game.map.closed_door_at(pos_door).is_none());
+
+        game.step(Action::Move(Point::new(0, 1)));
+        assert_eq!(game.player.pos, pos_door);
+    }
+
+    #[test]
+    fn toggle_door_opens_and_closes() {
+        let mut game = game_in(&LOCKED_CLOSET.replace("..L..", "..+.."));
+        let pos_door = Point::new(2, 2);
+
+        game.step(Action::ToggleDoor(Point::new(0, 1)));
+        assert!(game.map.door_at(pos_door).is_some());
+        assert!(game.map.closed_door_at(pos_door).is_none());
+
+        game.step(Action::ToggleDoor(Point::new(0, 1)));
+        assert!(game.map.closed_door_at(pos_door).is_some());
+
+        // Nothing happens in a direction with no door.
+
+        let events = game.step(Action::ToggleDoor(Point::new(-1, 0)));
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn key_opens_its_locked_door() {
+        let mut game = game_in(&format!("{}key 1 1 door 2 2\n", LOCKED_CLOSET));
+
+        let events = game.step(Action::Move(Point::new(-1, 0)));
+        assert!(events.contains(&GameEvent::KeyCollected { pos: Point::new(1, 1), from_guard: false }));
+
+        game.step(Action::Move(Point::new(1, 0)));
+        let events = game.step(Action::Move(Point::new(0, 1)));
+        assert!(events.contains(&GameEvent::DoorUnlocked { pos: Point::new(2, 2), picked: false }));
+        assert!(game.map.closed_door_at(Point::new(2, 2)).is_none());
+    }
+
+    #[test]
+    fn picking_a_lock_takes_turns_and_makes_noise() {
+        let mut game = game_in(LOCKED_CLOSET);
+        let pos_door = Point::new(2, 2);
+
+        let events = game.step(Action::PickLock(Point::new(0, 1)));
+
+        let count = |f: fn(&GameEvent) -> bool| events.iter().filter(|event| f(event)).count();
+        assert_eq!(count(|event| *event == GameEvent::TurnStarted), LOCKPICK_TURNS);
+        assert_eq!(count(|event| matches!(event, GameEvent::NoiseMade { .. })), (LOCKPICK_TURNS - 1) / LOCKPICK_NOISE_INTERVAL);
+        assert!(events.contains(&GameEvent::DoorUnlocked { pos: pos_door, picked: true }));
+
+        // The lock is open but the door is still shut.
+
+        assert!(game.map.locked_door_at(pos_door).is_none());
+        assert!(game.map.closed_door_at(pos_door).is_some());
+    }
+
+    #[test]
+    fn interrupted_lockpicking_keeps_its_progress() {
+        let text = LOCKED_CLOSET.replace("items\n.....\n.....", "items\n.....\n.<...") + "region 1 3 4 4\n";
+        let mut game = game_in(&text);
+        let pos_door = Point::new(2, 2);
+
+        // The guard on the far side hears the first click.
+
+        let events = game.step(Action::PickLock(Point::new(0, 1)));
+        assert!(events.contains(&GameEvent::LockpickInterrupted { pos: pos_door }));
+        assert_eq!(game.player.lockpick_turns, LOCKPICK_NOISE_INTERVAL);
+        assert!(game.map.locked_door_at(pos_door).is_some());
+
+        game.map.guards.clear();
+        let events = game.step(Action::PickLock(Point::new(0, 1)));
+        let num_turns = events.iter().filter(|event| **event == GameEvent::TurnStarted).count();
+        assert_eq!(num_turns, LOCKPICK_TURNS - LOCKPICK_NOISE_INTERVAL);
+        assert!(game.map.locked_door_at(pos_door).is_none());
+    }
+
+
+    // A dark room with one of each tool in a row east of the thief, and a
+    // candle by the last of them. There's open ground past the east wall.
+
+    const STOREROOM: &str = "\
+thiefrl3-map 1
+cells
+#######..
+#.....#..
+#.....#..
+#.....#..
+#######..
+items
+.........
+.........
+..@smf...
+.........
+.........
+candle 5 3
+";
+
+    #[test]
+    fn tools_are_picked_up_and_used_up() {
+        let mut game = game_in(STOREROOM);
+
+        for _ in 0..3 {
+            game.step(Action::Move(Point::new(1, 0)));
+        }
+        assert_eq!(game.player.pos, Point::new(5, 2));
+        assert_eq!(game.player.inventory.count(ToolKind::Stone), 3);
+        assert_eq!(game.player.inventory.count(ToolKind::SmokeBomb), 1);
+        assert_eq!(game.player.inventory.count(ToolKind::WaterFlask), 1);
+
+        // Walls stop throws, and the thief's own cell isn't a target.
+
+        for &target in &[Point::new(7, 2), Point::new(5, 2)] {
+            let events = game.step(Action::UseTool { tool: ToolKind::Stone, target });
+            assert!(events.is_empty());
+        }
+        assert_eq!(game.player.inventory.count(ToolKind::Stone), 3);
+
+        let target = Point::new(2, 2);
+        let events = game.step(Action::UseTool { tool: ToolKind::Stone, target });
+        assert!(events.contains(&GameEvent::ToolUsed { pos: target, tool: ToolKind::Stone }));
+        assert!(events.iter().any(|event| matches!(event, GameEvent::NoiseMade { pos, .. } if *pos == target)));
+        assert_eq!(game.player.inventory.count(ToolKind::Stone), 2);
+
+        game.step(Action::UseTool { tool: ToolKind::SmokeBomb, target });
+        assert!(game.map.cells[[2, 2]].smoke > 0);
+        assert_eq!(game.player.inventory.count(ToolKind::SmokeBomb), 0);
+
+        // With none left, nothing happens.
+
+        let events = game.step(Action::UseTool { tool: ToolKind::SmokeBomb, target });
+        assert!(events.is_empty());
+
+        let events = game.step(Action::UseTool { tool: ToolKind::WaterFlask, target: Point::new(4, 3) });
+        assert!(events.contains(&GameEvent::LightDoused { pos: Point::new(5, 3), kind: LightKind::Candle }));
+        assert!(!game.map.lights[0].lit);
+    }
+
+
+    // A dark room with the thief right behind a guard, who carries the key to
+    // the locked door in the south wall.
+
+    const GUARD_ROOM: &str = "\
+thiefrl3-map 1
+cells
+#######
+#.....#
+#.....#
+#.....#
+###-###
+#.....#
+#######
+items
+.......
+.......
+..@>...
+.......
+...L...
+.......
+.......
+region 1 3 6 6
+guard-key 3 4 door 3 2
+";
+
+    #[test]
+    fn knockout_only_works_from_behind_a_patrolling_guard() {
+        let mut game = game_in(GUARD_ROOM);
+        let events = game.step(Action::Knockout(Point::new(1, 0)));
+        assert!(events.contains(&GameEvent::GuardKnockedOut { pos: Point::new(3, 4) }));
+        assert!(game.map.guards[0].unconscious());
+
+        let mut game = game_in(GUARD_ROOM);
+        game.map.guards[0].dir = Point::new(-1, 0);
+        let events = game.step(Action::Knockout(Point::new(1, 0)));
+        assert!(events.contains(&GameEvent::KnockoutFailed { pos: Point::new(3, 4) }));
+        assert_eq!(game.map.guards[0].mode, GuardMode::ChaseVisibleTarget);
+    }
+
+    #[test]
+    fn key_is_lifted_only_from_behind_or_from_a_body() {
+        let mut game = game_in(GUARD_ROOM);
+        let events = game.step(Action::Move(Point::new(1, 0)));
+        assert!(events.contains(&GameEvent::KeyCollected { pos: Point::new(3, 4), from_guard: true }));
+        assert_eq!(game.player.pos, Point::new(2, 4));
+        assert_eq!(game.player.keys.len(), 1);
+        assert!(game.map.guards[0].key.is_none());
+
+        let mut game = game_in(GUARD_ROOM);
+        game.map.guards[0].dir = Point::new(-1, 0);
+        game.step(Action::Move(Point::new(1, 0)));
+        assert!(game.player.keys.is_empty());
+        assert!(game.map.guards[0].key.is_some());
+
+        let mut game = game_in(GUARD_ROOM);
+        game.map.guards[0].dir = Point::new(-1, 0);
+        game.map.guards[0].mode = GuardMode::Unconscious;
+        game.step(Action::Move(Point::new(1, 0)));
+        assert_eq!(game.player.keys.len(), 1);
+    }
+
+    #[test]
+    fn dragged_body_follows_the_thief() {
+        let mut game = game_in(GUARD_ROOM);
+        game.step(Action::Knockout(Point::new(1, 0)));
+
+        let events = game.step(Action::Drag(Point::new(1, 0)));
+        assert!(events.contains(&GameEvent::BodyGrabbed { pos: Point::new(3, 4) }));
+
+        game.step(Action::Move(Point::new(-1, 0)));
+        assert_eq!(game.player.pos, Point::new(1, 4));
+        assert_eq!(game.map.guards[0].pos, Point::new(2, 4));
+
+        let events = game.step(Action::Drag(Point::new(0, 0)));
+        assert!(events.contains(&GameEvent::BodyDropped { pos: Point::new(2, 4) }));
+        assert!(!game.player.dragging);
+    }
+}
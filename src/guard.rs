@@ -1,9 +1,22 @@
 use crate::cell_grid::*;
+use crate::game::GameEvent;
 use rand::prelude::*;
 use std::cmp::min;
 use std::cmp::max;
 use multiarray::Array2D;
 
+// The exits are sealed once this many guards are chasing the thief at once.
+
+const GUARDS_CHASING_FOR_ALARM: usize = 2;
+
+// A guard's shout carries farther than the thief's noises (squared distance).
+
+const SHOUT_EARSHOT: i32 = 200;
+
+// A patrolling guard stuck behind other guards this many turns turns back.
+
+const MAX_TURNS_WAITING: usize = 3;
+
 pub fn is_guard_at(map: &Map, x: i32, y: i32) -> bool {
     for guard in &map.guards {
         if guard.pos.x == x && guard.pos.y == y {
@@ -13,7 +26,7 @@ pub fn is_guard_at(map: &Map, x: i32, y: i32) -> bool {
     return false;
 }
 
-pub fn guard_act_all(rng: &mut MyRng, lines: &mut Lines, map: &mut Map, player: &mut Player) {
+pub fn guard_act_all(rng: &mut MyRng, lines: &mut Lines, events: &mut Vec<GameEvent>, map: &mut Map, player: &mut Player) {
 
     // Mark if we heard a guard last turn, and clear the speaking flag.
 
@@ -21,17 +34,77 @@ pub fn guard_act_all(rng: &mut MyRng, lines: &mut Lines, map: &mut Map, player:
         guard.pre_turn();
     }
 
-    // Update each guard for this turn.
+    // Each guard reserves the cell it means to step into next, so that guards
+    // in each other's way can go in a sensible order, or swap places.
+
+    for i_guard in 0..map.guards.len() {
+        let pos_next = map.guards[i_guard].planned_step(map);
+        map.guards[i_guard].pos_reserved = pos_next;
+    }
 
-    let mut guards = map.guards.split_off(0);
+    // Update each guard for this turn. The guard acting is taken out of
+    // map.guards, and put back in the same slot afterward.
 
-    for mut guard in guards.drain(..) {
-        guard.act(rng, lines, player, map);
+    for i_guard in acting_order(&map.guards) {
+        let mut guard = map.guards.swap_remove(i_guard);
+        guard.act(rng, lines, events, player, map);
         map.guards.push(guard);
+        let i_last = map.guards.len() - 1;
+        map.guards.swap(i_guard, i_last);
+    }
+
+    // Drop the portcullises when the alarm goes up. They stay wherever the
+    // winch leaves them until the alarm dies down and is raised afresh.
+
+    let alarm_was_raised = map.alarm_raised;
+    map.alarm_raised = alarm_raised(map);
+
+    if map.alarm_raised && !alarm_was_raised && map.lower_portcullises(player.pos) {
+        events.push(GameEvent::PortcullisesLowered);
+    }
+}
+
+// The alarm goes up when an alerted guard gets to a winch, or when enough
+// guards are chasing the thief.
+
+fn alarm_raised(map: &Map) -> bool {
+    let num_chasing = map.guards.iter().filter(|guard| guard.chasing()).count();
+    if num_chasing >= GUARDS_CHASING_FOR_ALARM {
+        return true;
+    }
+
+    map.guards.iter().any(|guard| {
+        guard.alerted() &&
+            map.items.iter().any(|item| item.kind == ItemKind::Winch && guard.adjacent_to(item.pos))
+    })
+}
+
+// Guards go after whoever is standing in the cell they have reserved, so that a
+// line of guards in a corridor moves up together. Guards waiting on each other
+// in a loop go in their usual order.
+
+fn acting_order(guards: &[Guard]) -> Vec<usize> {
+    let mut order = Vec::with_capacity(guards.len());
+    let mut acted = vec![false; guards.len()];
+
+    while order.len() < guards.len() {
+        let waiting = |i_guard: usize| {
+            let pos_reserved = guards[i_guard].pos_reserved;
+            (0..guards.len()).any(|j| j != i_guard && !acted[j] && guards[j].pos == pos_reserved)
+        };
+
+        let i_guard = (0..guards.len())
+            .find(|&i| !acted[i] && !waiting(i))
+            .unwrap_or_else(|| (0..guards.len()).find(|&i| !acted[i]).unwrap());
+
+        acted[i_guard] = true;
+        order.push(i_guard);
     }
+
+    order
 }
 
-fn pos_next_best(map: &Map, distance_field: &Array2D<usize>, pos_from: Point) -> Point {
+fn pos_next_best(map: &Map, distance_field: &Array2D<usize>, pos_from: Point, avoid_guards: bool) -> Point {
     let mut cost_best = INFINITE_COST;
     let mut pos_best = pos_from;
 
@@ -46,15 +119,11 @@ fn pos_next_best(map: &Map, distance_field: &Array2D<usize>, pos_from: Point) ->
             }
 
             let pos = Point{x, y};
-            if map.guard_move_cost(pos_from, pos) == INFINITE_COST {
-                continue;
-            }
-
-            if map.cells[[pos.x as usize, pos.y as usize]].cell_type == CellType::GroundWater {
+            if !map.guard_can_step(pos_from, pos) {
                 continue;
             }
 
-            if map.pos_blocked_by_guard(pos) {
+            if avoid_guards && map.pos_blocked_by_guard(pos) {
                 continue;
             }
 
@@ -95,7 +164,11 @@ pub struct Lines {
     end_investigate: LineIter,
     done_looking: LineIter,
     done_listening: LineIter,
-//  damage: LineIter,
+    notice_dark: LineIter,
+    relight: LineIter,
+    found_body: LineIter,
+    wake: LineIter,
+    damage: LineIter,
 }
 
 pub fn new_lines() -> Lines {
@@ -109,12 +182,42 @@ pub fn new_lines() -> Lines {
         end_investigate: LineIter::new(END_INVESTIGATION_LINES),
         done_looking: LineIter::new(DONE_LOOKING_LINES),
         done_listening: LineIter::new(DONE_LISTENING_LINES),
-//      damage: LineIter::new(DAMAGE_LINES),
+        notice_dark: LineIter::new(NOTICE_DARK_LINES),
+        relight: LineIter::new(RELIGHT_LINES),
+        found_body: LineIter::new(FOUND_BODY_LINES),
+        wake: LineIter::new(WAKE_LINES),
+        damage: LineIter::new(DAMAGE_LINES),
     }
 }
 
 impl Guard {
 
+// A guard on patrol, with no goal region yet.
+
+pub fn new(pos: Point, dir: Point) -> Guard {
+    Guard {
+        pos,
+        dir,
+        mode: GuardMode::Patrol,
+        speaking: false,
+        has_moved: false,
+        pos_reserved: pos,
+        turns_waiting: 0,
+        heard_thief: false,
+        heard_thief_pos: pos,
+        hearing_guard: false,
+        heard_guard: false,
+        heard_guard_pos: pos,
+        goal: pos,
+        mode_timeout: 0,
+        region_goal: INVALID_REGION,
+        region_prev: INVALID_REGION,
+        light_goal: None,
+        key: None,
+        door_to_lock: None,
+    }
+}
+
 fn pre_turn(&mut self) {
     self.heard_guard = self.hearing_guard;
     self.hearing_guard = false;
@@ -122,11 +225,21 @@ fn pre_turn(&mut self) {
     self.has_moved = false;
 }
 
-pub fn hear_thief(&mut self) {
+pub fn hear_thief(&mut self, pos_noise: Point) {
     self.heard_thief = true;
+    self.heard_thief_pos = pos_noise;
 }
 
-fn act(&mut self, rng: &mut MyRng, lines: &mut Lines, player: &mut Player, map: &Map) {
+// The guard acting has been taken out of map.guards for the duration.
+
+fn act(&mut self, rng: &mut MyRng, lines: &mut Lines, events: &mut Vec<GameEvent>, player: &mut Player, map: &mut Map) {
+
+    // Knocked-out guards do nothing until someone comes along and wakes them.
+
+    if self.mode == GuardMode::Unconscious {
+        self.heard_thief = false;
+        return;
+    }
 
     let mode_prev = self.mode;
     let pos_prev = self.pos;
@@ -157,21 +270,43 @@ fn act(&mut self, rng: &mut MyRng, lines: &mut Lines, player: &mut Player, map:
         }
 
         if self.heard_thief {
-            if self.adjacent_to(player.pos) {
+            let pos_noise = self.heard_thief_pos;
+            if pos_noise == player.pos && self.adjacent_to(player.pos) {
                 self.mode = GuardMode::ChaseVisibleTarget;
                 self.goal = player.pos;
             } else if self.mode == GuardMode::Patrol {
                 self.mode = GuardMode::Listen;
                 self.mode_timeout = rng.gen_range(2, 6);
-                self.dir = update_dir(self.dir, player.pos - self.pos);
+                self.dir = update_dir(self.dir, pos_noise - self.pos);
             } else {
                 self.mode = GuardMode::MoveToLastSound;
                 self.mode_timeout = rng.gen_range(2, 6);
-                self.goal = player.pos;
+                self.goal = pos_noise;
             }
         }
     }
 
+    if !self.chasing() && self.mode != GuardMode::FoundBody {
+        if let Some(pos_body) = self.notices_body(map) {
+            self.mode = GuardMode::FoundBody;
+            self.mode_timeout = 10;
+            self.goal = pos_body;
+            self.light_goal = None;
+        }
+    }
+
+    if self.mode == GuardMode::Patrol {
+        if let Some(i_light) = self.notices_doused_light(map) {
+            let light = &map.lights[i_light];
+            self.mode = GuardMode::Relight;
+            self.mode_timeout = 10;
+            self.goal = light.pos + light.dir;
+            self.light_goal = Some(i_light);
+        }
+    }
+
+    let mut relit_light = false;
+
     // Pass time in the current mode
 
     match self.mode {
@@ -190,6 +325,7 @@ fn act(&mut self, rng: &mut MyRng, lines: &mut Lines, player: &mut Player, map:
                 self.dir = update_dir(self.dir, self.goal - self.pos);
                 if mode_prev == GuardMode::ChaseVisibleTarget {
                     player.apply_damage(1);
+                    events.push(GameEvent::PlayerDamaged { amount: 1, text: lines.damage.next() });
                 }
             } else {
                 self.move_toward_goal(map, player);
@@ -207,6 +343,56 @@ fn act(&mut self, rng: &mut MyRng, lines: &mut Lines, player: &mut Player, map:
                 self.setup_goal_region(rng, map);
             }
         },
+        GuardMode::Relight => match self.light_goal {
+            Some(i_light) => {
+                let light = &map.lights[i_light];
+
+                if light.lit {
+                    // Someone else got to it first.
+                    self.mode_timeout = 0;
+                } else if self.adjacent_to(light.pos) {
+                    self.dir = update_dir(self.dir, light.pos - self.pos);
+                    events.push(GameEvent::LightRelit { pos: light.pos, kind: light.kind });
+                    map.set_light_lit(i_light, true);
+                    relit_light = true;
+                    self.mode_timeout = 0;
+                } else if !self.move_toward_goal(map, player) {
+                    self.mode_timeout -= 1;
+                }
+
+                if self.mode_timeout == 0 {
+                    self.mode = GuardMode::Patrol;
+                    self.light_goal = None;
+                    self.setup_goal_region(rng, map);
+                }
+            },
+            None => {
+                // No light to go to; go back to patrolling.
+                self.mode = GuardMode::Patrol;
+                self.setup_goal_region(rng, map);
+            },
+        },
+        GuardMode::FoundBody => {
+            if self.adjacent_to(self.goal) {
+                self.dir = update_dir(self.dir, self.goal - self.pos);
+                if let Some(body) = map.guards.iter_mut().find(|guard| guard.pos == self.goal && guard.mode == GuardMode::Unconscious) {
+                    body.wake(rng, lines, events, player);
+                    self.mode = GuardMode::Look;
+                    self.mode_timeout = rng.gen_range(2, 6);
+                } else {
+                    // The body has been moved since we saw it.
+                    self.mode_timeout = 0;
+                }
+            } else if !self.move_toward_goal(map, player) {
+                self.mode_timeout -= 1;
+            }
+
+            if self.mode_timeout == 0 {
+                self.mode = GuardMode::Patrol;
+                self.setup_goal_region(rng, map);
+            }
+        },
+        GuardMode::Unconscious => {},
     }
 
     // If we moved, update state based on target visibility from new position
@@ -230,6 +416,8 @@ fn act(&mut self, rng: &mut MyRng, lines: &mut Lines, player: &mut Player, map:
         }
     }
 
+    self.maybe_close_door_behind(rng, map, player, pos_prev);
+
     // Clear heard-thief flag
 
     self.heard_thief = false;
@@ -237,39 +425,54 @@ fn act(&mut self, rng: &mut MyRng, lines: &mut Lines, player: &mut Player, map:
     // Say something to indicate state changes
 
     if mode_prev != self.mode {
+        events.push(GameEvent::GuardModeChanged { pos: self.pos, mode_prev, mode: self.mode });
+
         match self.mode {
             GuardMode::Patrol => {
                 if mode_prev == GuardMode::Look {
-                    self.say(player, lines.done_looking.next());
+                    self.say(events, player, lines.done_looking.next());
                 } else if mode_prev == GuardMode::Listen {
-                    self.say(player, lines.done_listening.next());
+                    self.say(events, player, lines.done_listening.next());
                 }
-                else if mode_prev == GuardMode::MoveToLastSound || mode_prev == GuardMode::MoveToGuardShout {
-                    self.say(player, lines.end_investigate.next());
+                else if mode_prev == GuardMode::MoveToLastSound || mode_prev == GuardMode::MoveToGuardShout || mode_prev == GuardMode::FoundBody {
+                    self.say(events, player, lines.end_investigate.next());
                 }
                 else if mode_prev == GuardMode::MoveToLastSighting {
-                    self.say(player, lines.end_chase.next());
+                    self.say(events, player, lines.end_chase.next());
+                }
+                else if mode_prev == GuardMode::Relight && relit_light {
+                    self.say(events, player, lines.relight.next());
                 }
             },
             GuardMode::Look => {
-                self.say(player, lines.see.next());
+                if mode_prev != GuardMode::FoundBody {
+                    self.say(events, player, lines.see.next());
+                }
             },
             GuardMode::Listen => {
-                self.say(player, lines.hear.next());
+                self.say(events, player, lines.hear.next());
             },
             GuardMode::ChaseVisibleTarget => {
                 if mode_prev != GuardMode::MoveToLastSighting {
-//                  self.alert_nearby_guards(map);
-                    self.say(player, lines.chase.next());
+                    self.shout(events, lines.chase.next());
+                    self.alert_nearby_guards(map);
                 }
             },
             GuardMode::MoveToLastSighting => {
             },
             GuardMode::MoveToLastSound => {
-                self.say(player, lines.investigate.next());
+                self.say(events, player, lines.investigate.next());
             },
             GuardMode::MoveToGuardShout => {
-                self.say(player, lines.hear_guard.next());
+                self.say(events, player, lines.hear_guard.next());
+            },
+            GuardMode::Relight => {
+                self.say(events, player, lines.notice_dark.next());
+            },
+            GuardMode::FoundBody => {
+                self.say(events, player, lines.found_body.next());
+            },
+            GuardMode::Unconscious => {
             },
         }
     }
@@ -290,40 +493,145 @@ pub fn overhead_icon(&self, map: &Map, player: &Player) -> Option<usize> {
         }
     }
 
-    Some(if self.mode == GuardMode::ChaseVisibleTarget {216} else {215})
+    Some(match self.mode {
+        GuardMode::ChaseVisibleTarget => 216,
+        GuardMode::Unconscious => 122, // 'z'
+        _ => 215,
+    })
 }
 
-fn say(&mut self, player: &Player, msg: &str) {
+// Called by the guard who finds this one lying unconscious.
+
+fn wake(&mut self, rng: &mut MyRng, lines: &mut Lines, events: &mut Vec<GameEvent>, player: &Player) {
+    self.mode = GuardMode::Look;
+    self.mode_timeout = rng.gen_range(2, 6);
+    self.heard_thief = false;
+    events.push(GameEvent::GuardModeChanged { pos: self.pos, mode_prev: GuardMode::Unconscious, mode: self.mode });
+    self.say(events, player, lines.wake.next());
+}
+
+fn say(&mut self, events: &mut Vec<GameEvent>, player: &Player, msg: &'static str) {
     let d = self.pos - player.pos;
     let dist_squared = d.length_squared();
 
     if dist_squared < 200 || player.see_all {
-        println!("{}", msg);
-//      txt::guard_speech(self.pos, msg);
+        events.push(GameEvent::GuardSpoke { pos: self.pos, text: msg });
     }
 
     self.speaking = true;
 }
 
+fn shout(&mut self, events: &mut Vec<GameEvent>, msg: &'static str) {
+    events.push(GameEvent::GuardShouted { pos: self.pos, text: msg });
+    self.speaking = true;
+}
+
+// Guards within earshot of a shout head for the shouting guard next turn. The
+// guard shouting has been taken out of map.guards, so it doesn't hear itself.
+
+fn alert_nearby_guards(&self, map: &mut Map) {
+    for guard in map.find_guards_in_earshot(self.pos, SHOUT_EARSHOT) {
+        if guard.unconscious() {
+            continue;
+        }
+        guard.hearing_guard = true;
+        guard.heard_guard_pos = self.pos;
+    }
+}
+
+// A patrolling guard notices a doused light if it can see the spot the light
+// would normally be shining on.
+
+fn notices_doused_light(&self, map: &Map) -> Option<usize> {
+    for (i_light, light) in map.lights.iter().enumerate() {
+        if light.lit {
+            continue;
+        }
+
+        let pos = light.pos + light.dir;
+        let d = pos - self.pos;
+
+        if Point::dot(self.dir, d) < 0 {
+            continue;
+        }
+
+        if d.length_squared() >= self.cutoff_lit() {
+            continue;
+        }
+
+        if line_of_sight(map, self.pos, pos) {
+            return Some(i_light);
+        }
+    }
+
+    None
+}
+
+// Any guard can spot a knocked-out colleague, unless the body has been hidden
+// away under a table or in the bushes.
+
+fn notices_body(&self, map: &Map) -> Option<Point> {
+    for body in map.guards.iter().filter(|guard| guard.mode == GuardMode::Unconscious) {
+        let cell = &map.cells[[body.pos.x as usize, body.pos.y as usize]];
+        if cell.hides_player {
+            continue;
+        }
+
+        let d = body.pos - self.pos;
+
+        if Point::dot(self.dir, d) < 0 {
+            continue;
+        }
+
+        if d.length_squared() >= self.sight_cutoff(cell.lit) {
+            continue;
+        }
+
+        if line_of_sight(map, self.pos, body.pos) {
+            return Some(body.pos);
+        }
+    }
+
+    None
+}
+
+pub fn unconscious(&self) -> bool {
+    self.mode == GuardMode::Unconscious
+}
+
+fn chasing(&self) -> bool {
+    self.mode == GuardMode::ChaseVisibleTarget || self.mode == GuardMode::MoveToLastSighting
+}
+
+fn alerted(&self) -> bool {
+    self.chasing() || self.mode == GuardMode::MoveToGuardShout || self.mode == GuardMode::FoundBody
+}
+
 fn adjacent_to(&self, pos: Point) -> bool {
     let d = pos - self.pos;
     d.x.abs() < 2 && d.y.abs() < 2
 }
 
 fn sees_thief(&self, map: &Map, player: &Player) -> bool {
-    let d = player.pos - self.pos;
+    self.sees_thief_at(map, player, player.pos)
+}
+
+// Whether the guard would see the thief if the thief were standing at pos.
+
+pub fn sees_thief_at(&self, map: &Map, player: &Player, pos: Point) -> bool {
+    let d = pos - self.pos;
     if Point::dot(self.dir, d) < 0 {
         return false;
     }
 
-    let player_is_lit = map.cells[[player.pos.x as usize, player.pos.y as usize]].lit;
+    let player_is_lit = map.cells[[pos.x as usize, pos.y as usize]].lit;
 
     let d2 = d.length_squared();
     if d2 >= self.sight_cutoff(player_is_lit) {
         return false;
     }
 
-    if !player.hidden(map) && line_of_sight(map, self.pos, player.pos) {
+    if !player.hidden_at(map, pos) && line_of_sight(map, self.pos, pos) {
         return true;
     }
 
@@ -346,9 +654,23 @@ fn sight_cutoff(&self, lit_target: bool) -> i32 {
     if lit_target {self.cutoff_lit()} else {self.cutoff_unlit()}
 }
 
-fn patrol_step(&mut self, map: &Map, player: &mut Player, rng: &mut MyRng) {
+fn patrol_step(&mut self, map: &mut Map, player: &mut Player, rng: &mut MyRng) {
+    let pos_prev = self.pos;
     let bumped_thief = self.move_toward_region(map, player);
 
+    // A guard that has been kept waiting by others for a while gives up and
+    // heads back the way it came, which breaks up jams in doorways.
+
+    if self.pos != pos_prev || self.pos_reserved == self.pos || !map.pos_blocked_by_guard(self.pos_reserved) {
+        self.turns_waiting = 0;
+    } else {
+        self.turns_waiting += 1;
+        if self.turns_waiting >= MAX_TURNS_WAITING && self.region_prev != INVALID_REGION {
+            std::mem::swap(&mut self.region_goal, &mut self.region_prev);
+            self.turns_waiting = 0;
+        }
+    }
+
     if map.cells[[self.pos.x as usize, self.pos.y as usize]].region == self.region_goal {
         let region_prev = self.region_prev;
         self.region_prev = self.region_goal;
@@ -368,36 +690,96 @@ pub fn initial_dir(&self, map: &Map) -> Point
         return self.dir;
     }
 
-    let distance_field = map.compute_distances_to_region(self.region_goal);
+    let distance_field = map.distances_to_region(self.region_goal);
 
-    let pos_next = pos_next_best(map, &distance_field, self.pos);
+    let pos_next = pos_next_best(map, &distance_field, self.pos, true);
 
     update_dir(self.dir, pos_next - self.pos)
 }
 
-fn move_toward_region(&mut self, map: &Map, player: &Player) -> bool {
-    if self.region_goal == INVALID_REGION {
+// Where the guard would step this turn if no other guards were in the way.
+
+fn planned_step(&self, map: &Map) -> Point {
+    match self.mode {
+        GuardMode::Look |
+        GuardMode::Listen |
+        GuardMode::Unconscious => self.pos,
+        GuardMode::Patrol => {
+            if self.region_goal == INVALID_REGION {
+                return self.pos;
+            }
+            pos_next_best(map, &map.distances_to_region(self.region_goal), self.pos, false)
+        },
+        _ => pos_next_best(map, &map.compute_distances_to_position_from(self.goal, self.pos), self.pos, false),
+    }
+}
+
+// The next step toward whatever the distance field leads to. Other guards are
+// stepped around if that still makes progress; otherwise we wait for them to
+// move, unless one is coming the other way, in which case we swap places.
+
+fn choose_step(&self, map: &Map, distance_field: &Array2D<usize>) -> Point {
+    let pos_want = pos_next_best(map, distance_field, self.pos, false);
+
+    let oncoming = map.guards.iter().any(|guard| {
+        guard.pos == pos_want && guard.pos_reserved == self.pos && !guard.has_moved && !guard.unconscious()
+    });
+
+    if pos_want != self.pos && oncoming {
+        return pos_want;
+    }
+
+    pos_next_best(map, distance_field, self.pos, true)
+}
+
+fn step_to(&mut self, map: &mut Map, pos_next: Point) {
+    let pos_prev = self.pos;
+
+    if let Some(guard) = map.guards.iter_mut().find(|guard| guard.pos == pos_next) {
+        guard.dir = update_dir(guard.dir, pos_prev - pos_next);
+        guard.pos = pos_prev;
+        guard.has_moved = true;
+    }
+
+    self.pos = pos_next;
+    self.has_moved = true;
+}
+
+fn move_toward_region(&mut self, map: &mut Map, player: &Player) -> bool {
+    if self.region_goal == INVALID_REGION || self.has_moved {
         return false;
     }
 
-    let distance_field = map.compute_distances_to_region(self.region_goal);
+    let distance_field = map.distances_to_region(self.region_goal);
 
-    let pos_next = pos_next_best(map, &distance_field, self.pos);
+    let pos_next = self.choose_step(map, &distance_field);
 
     if player.pos == pos_next {
         return true;
     }
 
     self.dir = update_dir(self.dir, pos_next - self.pos);
-    self.pos = pos_next;
+
+    if self.open_door(map, pos_next) {
+        return false;
+    }
+
+    self.step_to(map, pos_next);
 
     false
 }
 
-fn move_toward_goal(&mut self, map: &Map, player: &Player) -> bool {
-    let dist_field = map.compute_distances_to_position(self.goal);
+// Returns whether the guard got anywhere. Having been swapped into a new cell
+// by another guard already this turn counts.
+
+fn move_toward_goal(&mut self, map: &mut Map, player: &Player) -> bool {
+    if self.has_moved {
+        return true;
+    }
 
-    let pos_next = pos_next_best(map, &dist_field, self.pos);
+    let dist_field = map.compute_distances_to_position_from(self.goal, self.pos);
+
+    let pos_next = self.choose_step(map, &dist_field);
     if pos_next == self.pos {
         return false;
     }
@@ -408,10 +790,63 @@ fn move_toward_goal(&mut self, map: &Map, player: &Player) -> bool {
         return false;
     }
 
-    self.pos = pos_next;
+    if self.open_door(map, pos_next) {
+        return true;
+    }
+
+    self.step_to(map, pos_next);
     true
 }
 
+// Opening a closed door in the way takes the guard's move for the turn. Guards
+// carry the household keys, so locked doors don't stop them, but they remember
+// to lock them again once they're through.
+
+fn open_door(&mut self, map: &mut Map, pos: Point) -> bool {
+    match map.closed_door_at(pos) {
+        Some(i_door) => {
+            if is_locked_door(map.items[i_door].kind) {
+                self.door_to_lock = Some(i_door);
+            }
+            map.set_door_open(i_door, true);
+            true
+        },
+        None => false,
+    }
+}
+
+// Patrolling guards close the doors they've just walked through about half the
+// time. Doors they unlocked always get closed and locked.
+
+fn maybe_close_door_behind(&mut self, rng: &mut MyRng, map: &mut Map, player: &Player, pos_prev: Point) {
+    if let Some(i_door) = self.door_to_lock {
+        if !self.adjacent_to(map.items[i_door].pos) {
+            self.door_to_lock = None;
+        }
+    }
+
+    if self.pos == pos_prev || player.pos == pos_prev || map.pos_blocked_by_guard(pos_prev) {
+        return;
+    }
+
+    let i_door = match map.door_at(pos_prev) {
+        Some(i_door) => i_door,
+        None => return,
+    };
+
+    if is_closed_door(map.items[i_door].kind) {
+        return;
+    }
+
+    if self.door_to_lock == Some(i_door) {
+        map.set_door_open(i_door, false);
+        map.set_door_locked(i_door, true);
+        self.door_to_lock = None;
+    } else if self.mode == GuardMode::Patrol && rng.gen_bool(0.5) {
+        map.set_door_open(i_door, false);
+    }
+}
+
 pub fn setup_goal_region(&mut self, rng: &mut MyRng, map: &Map) {
     let region_cur = map.cells[[self.pos.x as usize, self.pos.y as usize]].region;
 
@@ -447,42 +882,7 @@ fn update_dir(dir_forward: Point, dir_aim: Point) -> Point {
 }
 
 fn line_of_sight(map: &Map, from: Point, to: Point) -> bool {
-    let mut x = from.x;
-    let mut y = from.y;
-
-    let dx = to.x - x;
-    let dy = to.y - y;
-
-    let mut ax = dx.abs();
-    let mut ay = dy.abs();
-
-    let x_inc = if dx > 0 {1} else {-1};
-    let y_inc = if dy > 0 {1} else {-1};
-
-    let mut error = ay - ax;
-
-    let mut n = ax + ay - 1;
-
-    ax *= 2;
-    ay *= 2;
-
-    while n > 0 {
-        if error > 0 {
-            y += y_inc;
-            error -= ax;
-        } else {
-            x += x_inc;
-            error += ay;
-        }
-
-        if map.blocks_sight(x, y) {
-            return false;
-        }
-
-        n -= 1;
-    }
-
-    true
+    map.line_clear(from, to, |cell| cell.blocks_sight)
 }
 
 static SEE_LINES: &[&str] = &[
@@ -599,7 +999,38 @@ static DONE_LISTENING_LINES: &[&str] = &[
     "Did I imagine that?",
 ];
 
-/*
+static NOTICE_DARK_LINES: &[&str] = &[
+    "Who put out that light?",
+    "It's dark over there...",
+    "That torch went out.",
+    "Better relight that.",
+    "Hmm, a draft?",
+    "Why is it so dark?",
+];
+
+static RELIGHT_LINES: &[&str] = &[
+    "There, that's better.",
+    "Much better.",
+    "That's better.",
+    "Let there be light.",
+    "Stupid draft.",
+];
+
+static FOUND_BODY_LINES: &[&str] = &[
+    "Man down!",
+    "Who did this?",
+    "Hey! Wake up!",
+    "Somebody's been here...",
+    "Sleeping on the job?",
+];
+
+static WAKE_LINES: &[&str] = &[
+    "Ugh... my head.",
+    "What hit me?",
+    "Who was that?",
+    "Huh? What happened?",
+];
+
 static DAMAGE_LINES: &[&str] = &[
     "Oof!",
     "Krak!",
@@ -608,4 +1039,268 @@ static DAMAGE_LINES: &[&str] = &[
     "Smack!",
     "Bif!",
 ];
-*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two dark halls with a solid wall between them, so the guard in the west
+    // hall can't see the thief in the east one.
+
+    const HALLS: &str = "\
+thiefrl3-map 1
+cells
+#############
+#.....#.....#
+#.....#.....#
+#.....#.....#
+#############
+items
+.............
+.............
+..>......@...
+.............
+.............
+region 1 1 6 4
+";
+
+    fn act_all(map: &mut Map, player: &mut Player) -> Vec<GameEvent> {
+        let mut rng = MyRng::seed_from_u64(1);
+        let mut lines = new_lines();
+        let mut events = Vec::new();
+        guard_act_all(&mut rng, &mut lines, &mut events, map, player);
+        events
+    }
+
+    fn map_and_player(text: &str) -> (Map, Player) {
+        let map = Map::from_ascii(text).unwrap();
+        let player = make_player(&map.pos_start);
+        (map, player)
+    }
+
+    #[test]
+    fn relighting_with_no_light_goes_back_to_patrol() {
+        let (mut map, mut player) = map_and_player(HALLS);
+        map.guards[0].mode = GuardMode::Relight;
+        map.guards[0].mode_timeout = 10;
+        map.guards[0].light_goal = None;
+
+        act_all(&mut map, &mut player);
+
+        assert_eq!(map.guards[0].mode, GuardMode::Patrol);
+    }
+
+    #[test]
+    fn heard_noise_is_listened_for_then_investigated() {
+        let (mut map, mut player) = map_and_player(HALLS);
+        let pos_noise = Point::new(5, 3);
+
+        map.guards[0].hear_thief(pos_noise);
+        act_all(&mut map, &mut player);
+        let guard = &map.guards[0];
+        assert_eq!(guard.mode, GuardMode::Listen);
+        assert!(Point::dot(guard.dir, pos_noise - guard.pos) > 0);
+
+        // A second noise while listening sends the guard to look.
+
+        map.guards[0].hear_thief(pos_noise);
+        act_all(&mut map, &mut player);
+        assert_eq!(map.guards[0].mode, GuardMode::MoveToLastSound);
+        assert_eq!(map.guards[0].goal, pos_noise);
+
+        for _ in 0..4 {
+            act_all(&mut map, &mut player);
+        }
+        assert_eq!(map.guards[0].pos, pos_noise);
+    }
+
+    #[test]
+    fn thief_heard_next_to_guard_is_chased() {
+        let (mut map, mut player) = map_and_player(HALLS);
+        player.pos = Point::new(1, 2);
+
+        map.guards[0].hear_thief(player.pos);
+        act_all(&mut map, &mut player);
+
+        assert_eq!(map.guards[0].mode, GuardMode::ChaseVisibleTarget);
+        assert_eq!(map.guards[0].goal, player.pos);
+    }
+
+
+    // A long dark hall with three guards facing away from the thief, the
+    // second just within earshot of a shout from the first and the third
+    // just beyond it.
+
+    const LONG_HALL: &str = "\
+thiefrl3-map 1
+cells
+########################
+#......................#
+#......................#
+#......................#
+########################
+items
+........................
+........................
+.@>............>..>.....
+........................
+........................
+region 1 1 23 4
+";
+
+    #[test]
+    fn shout_alerts_guards_within_earshot() {
+        let (mut map, mut player) = map_and_player(LONG_HALL);
+        assert!((map.guards[1].pos - map.guards[0].pos).length_squared() < SHOUT_EARSHOT);
+        assert!((map.guards[2].pos - map.guards[0].pos).length_squared() >= SHOUT_EARSHOT);
+
+        map.guards[0].hear_thief(player.pos);
+        let events = act_all(&mut map, &mut player);
+        assert!(events.iter().any(|event| matches!(event, GameEvent::GuardShouted { pos, .. } if *pos == Point::new(2, 2))));
+
+        act_all(&mut map, &mut player);
+        assert_eq!(map.guards[1].mode, GuardMode::MoveToGuardShout);
+        assert_eq!(map.guards[1].goal, Point::new(2, 2));
+        assert_eq!(map.guards[2].mode, GuardMode::Patrol);
+
+        // A knocked-out guard sleeps through it.
+
+        let (mut map, mut player) = map_and_player(LONG_HALL);
+        map.guards[1].mode = GuardMode::Unconscious;
+        map.guards[0].hear_thief(player.pos);
+        act_all(&mut map, &mut player);
+        act_all(&mut map, &mut player);
+        assert_eq!(map.guards[1].mode, GuardMode::Unconscious);
+        assert!(!map.guards[1].heard_guard);
+    }
+
+
+    // A corridor one cell wide, with the thief shut away below it.
+
+    const CORRIDOR: &str = "\
+thiefrl3-map 1
+cells
+############
+#..........#
+############
+#.##########
+items
+............
+............
+............
+.@..........
+region 1 2 11 3
+";
+
+    fn corridor_with_guards(guards: &[(Point, GuardMode, Point)]) -> (Map, Player) {
+        let (mut map, player) = map_and_player(CORRIDOR);
+        for &(pos, mode, goal) in guards {
+            let mut guard = Guard::new(pos, Point::new(1, 0));
+            guard.mode = mode;
+            guard.mode_timeout = 5;
+            guard.goal = goal;
+            map.guards.push(guard);
+        }
+        (map, player)
+    }
+
+    #[test]
+    fn guards_meeting_in_a_corridor_swap_places() {
+        let (mut map, mut player) = corridor_with_guards(&[
+            (Point::new(3, 2), GuardMode::MoveToLastSound, Point::new(9, 2)),
+            (Point::new(4, 2), GuardMode::MoveToLastSound, Point::new(1, 2)),
+        ]);
+
+        act_all(&mut map, &mut player);
+
+        assert_eq!(map.guards[0].pos, Point::new(4, 2));
+        assert_eq!(map.guards[1].pos, Point::new(3, 2));
+    }
+
+    #[test]
+    fn guards_in_a_line_move_up_together() {
+        let (mut map, mut player) = corridor_with_guards(&[
+            (Point::new(3, 2), GuardMode::MoveToLastSound, Point::new(9, 2)),
+            (Point::new(4, 2), GuardMode::MoveToLastSound, Point::new(9, 2)),
+        ]);
+
+        act_all(&mut map, &mut player);
+
+        assert_eq!(map.guards[0].pos, Point::new(4, 2));
+        assert_eq!(map.guards[1].pos, Point::new(5, 2));
+    }
+
+    #[test]
+    fn guard_waits_behind_a_guard_standing_still() {
+        let (mut map, mut player) = corridor_with_guards(&[
+            (Point::new(3, 2), GuardMode::MoveToLastSound, Point::new(9, 2)),
+            (Point::new(4, 2), GuardMode::Look, Point::new(4, 2)),
+        ]);
+
+        act_all(&mut map, &mut player);
+
+        assert_eq!(map.guards[0].pos, Point::new(3, 2));
+        assert_eq!(map.guards[1].pos, Point::new(4, 2));
+        assert_eq!(map.guards[1].pos_reserved, Point::new(4, 2));
+    }
+
+
+    // A moonlit courtyard with a pillar just east of a guard facing east. The
+    // thief is in the far corner, out of sight.
+
+    const COURTYARD: &str = "\
+thiefrl3-map 1
+cells
+###########
+#.........#
+#.........#
+#....#....#
+#.........#
+###########
+items
+...........
+.........@.
+...........
+..>........
+...........
+...........
+moonlit 0 0 11 6
+region 1 1 10 5
+";
+
+    #[test]
+    fn vision_covers_lit_cells_in_front_and_in_line_of_sight() {
+        let (map, player) = map_and_player(COURTYARD);
+        let guard = &map.guards[0];
+        let sees = |x: i32, y: i32| guard.sees_thief_at(&map, &player, Point::new(x, y));
+
+        assert!(sees(4, 2));
+        assert!(sees(2, 4)); // off to the side
+        assert!(!sees(1, 2)); // behind
+        assert!(!sees(6, 2)); // behind the pillar
+        assert!(!sees(9, 4)); // too far
+    }
+
+    #[test]
+    fn vision_in_the_dark_only_reaches_next_door() {
+        let (map, player) = map_and_player(&COURTYARD.replace("moonlit 0 0 11 6\n", ""));
+        let guard = &map.guards[0];
+        let sees = |x: i32, y: i32| guard.sees_thief_at(&map, &player, Point::new(x, y));
+
+        assert!(sees(3, 2));
+        assert!(sees(3, 3));
+        assert!(!sees(4, 2));
+    }
+
+    #[test]
+    fn thief_in_the_vision_area_is_seen() {
+        let (mut map, mut player) = map_and_player(COURTYARD);
+        player.pos = Point::new(4, 3);
+        assert!(map.guards[0].sees_thief_at(&map, &player, player.pos));
+
+        act_all(&mut map, &mut player);
+
+        assert_eq!(map.guards[0].mode, GuardMode::Look);
+        assert_eq!(map.guards[0].goal, player.pos);
+    }
+}
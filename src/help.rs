@@ -0,0 +1,40 @@
+use quicksilver::{
+    geom::Rectangle,
+    graphics::{Background::Col, Color, Image},
+    lifecycle::Window,
+};
+use thiefrl3::color_preset;
+use thiefrl3::help_text::HELP_PAGES;
+use crate::fontdata::LINE_HEIGHT;
+use crate::rgba;
+use crate::speech_bubbles::puts_proportional;
+
+const BACKGROUND_COLOR: Color = Color { r: 0.0, g: 0.0, b: 0.0, a: 0.875 };
+const TITLE_COLOR: Color = rgba(color_preset::LIGHT_YELLOW);
+const TEXT_COLOR: Color = rgba(color_preset::LIGHT_GRAY);
+
+pub fn num_pages() -> usize {
+    HELP_PAGES.len()
+}
+
+pub fn draw_help(window: &mut Window, font_image: &Image, page: usize, y_top: i32, y_bottom: i32) {
+    let screen_size_x: i32 = window.screen_size().x as i32;
+
+    window.draw(
+        &Rectangle::new((0, y_top), (screen_size_x, y_bottom - y_top)),
+        Col(BACKGROUND_COLOR),
+    );
+
+    let help_page = &HELP_PAGES[page];
+
+    let x = 32;
+    let mut y = y_top + LINE_HEIGHT;
+
+    puts_proportional(window, font_image, x, y, help_page.title, &TITLE_COLOR);
+    y += 2 * LINE_HEIGHT;
+
+    for line in help_page.lines {
+        puts_proportional(window, font_image, x, y, line, &TEXT_COLOR);
+        y += LINE_HEIGHT;
+    }
+}
@@ -0,0 +1,113 @@
+// The help pages, shared by both frontends so they describe the same keys.
+
+pub struct HelpPage {
+    pub title: &'static str,
+    pub lines: &'static [&'static str],
+}
+
+pub static HELP_PAGES: &[HelpPage] = &[
+    HelpPage {
+        title: "Getting Around",
+        lines: &[
+            "You are a thief. Collect all of the gold coins in the mansion and see every part",
+            "of it, then leave by walking off the edge of the map.",
+            "",
+            "Move with the arrow keys or the numeric keypad. The keypad (or Home, End,",
+            "Page Up and Page Down) moves diagonally. Numpad 5 or . waits a turn.",
+            "",
+            "Moving diagonally into a wall slides you along it.",
+            "",
+            "Walk into a closed door to open it. Press C and then a direction to open or",
+            "close a door next to you. Closed doors block sight, both yours and the guards'.",
+            "",
+            "Locked doors are drawn in red. Walking into one with its key unlocks it. Keys",
+            "lie about the mansion, and some guards carry one: bump into a patrolling guard",
+            "from behind to lift it. Without a key, press P and a direction to pick the lock;",
+            "it takes several turns and the clicking can be heard.",
+            "",
+            "Ctrl+R restarts the current level with the same layout.",
+            "Ctrl+N starts a new game.",
+            "Escape saves and quits; the game continues when you start it again.",
+            "",
+            "M shows the history of everything guards have said and heard.",
+        ],
+    },
+    HelpPage {
+        title: "Light and Shadow",
+        lines: &[
+            "Guards see much farther into lit areas than dark ones. Dark cells are drawn in",
+            "blue; you are drawn in blue while standing in the dark, and gray when lit.",
+            "",
+            "Courtyards are lit by the moon. Indoors, light comes from torches on the walls",
+            "and candles on tables; rooms without them are dark.",
+            "",
+            "Press D next to a torch or candle to put it out. A guard who notices the",
+            "darkness will come over to relight it.",
+            "",
+            "A guard only sees what is in front of it. Guards that are already suspicious",
+            "see farther than guards on a routine patrol. A guard who spots you shouts, and",
+            "any other guard within earshot comes running.",
+            "",
+            "Guards cannot see you while you are hidden under a table or in a bush, unless",
+            "one of them is already chasing you.",
+            "",
+            "Press V to shade the cells where the guards you can see would spot you.",
+        ],
+    },
+    HelpPage {
+        title: "Knocking Out Guards",
+        lines: &[
+            "Press K and a direction to knock out a patrolling guard from behind. A guard",
+            "who is facing you, or already suspicious, hears you coming and turns on you.",
+            "",
+            "Other guards who see the body will go and wake it, and start searching. Press",
+            "G and a direction to drag a body along behind you; hide it under a table or in",
+            "a bush so it won't be found. Press G again to let go.",
+            "",
+            "An unconscious guard's key can be taken just by walking into it.",
+        ],
+    },
+    HelpPage {
+        title: "Water, Floors and Windows",
+        lines: &[
+            "You can dive into water to hide. The Air meter on the status bar shows how",
+            "long you can stay under; once it runs out you surface and can be seen.",
+            "Guards will not follow you into water.",
+            "",
+            "Some wooden floorboards creak when stepped on. The noise alerts any guard",
+            "within earshot, and they will come to investigate.",
+            "",
+            "One-way windows can only be climbed through in the direction they face, and",
+            "guards can't see through them.",
+        ],
+    },
+    HelpPage {
+        title: "Portcullises",
+        lines: &[
+            "The gates in the outer wall are portcullises. They start out raised, but if",
+            "two guards are chasing you at once, or an alarmed guard gets to the winch, they",
+            "come crashing down and you can't leave the way you came in.",
+            "",
+            "Walk into the winch inside one of the gates to raise them again. It is loud,",
+            "and any guard within earshot will hear it. The gates stay up until the guards",
+            "calm down and the alarm is raised afresh.",
+        ],
+    },
+    HelpPage {
+        title: "Tools",
+        lines: &[
+            "Stones, smoke bombs and flasks of water lie about the mansion. Walk over them",
+            "to pick them up; what you carry is shown on the status bar. Tools don't carry",
+            "over to the next level.",
+            "",
+            "Press 1 to throw a stone, 2 a smoke bomb or 3 a water flask. Move the cursor",
+            "with the movement keys and press Enter or Space to throw, or Escape to cancel.",
+            "The cursor is red where the throw can't reach.",
+            "",
+            "A stone clatters where it lands. A patrolling guard in earshot stops to listen",
+            "and turns toward it; a second stone sends guards over to investigate.",
+            "Smoke hides you and blocks the guards' sight for a few turns. Water puts out",
+            "torches and candles next to where the flask lands.",
+        ],
+    },
+];
@@ -0,0 +1,90 @@
+use crate::cell_grid::ItemKind;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ToolKind {
+    Stone,      // thrown to make a noise where it lands
+    SmokeBomb,  // thrown to fill a patch of cells with smoke that guards can't see through
+    WaterFlask, // thrown to put out a torch or candle
+}
+
+pub const TOOL_KINDS: [ToolKind; 3] = [ToolKind::Stone, ToolKind::SmokeBomb, ToolKind::WaterFlask];
+
+// Number of turns smoke hangs in the air.
+
+pub const SMOKE_TURNS: usize = 10;
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Inventory {
+    counts: [usize; TOOL_KINDS.len()],
+}
+
+impl ToolKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ToolKind::Stone => "stone",
+            ToolKind::SmokeBomb => "smoke bomb",
+            ToolKind::WaterFlask => "water flask",
+        }
+    }
+
+    // "a stone", "3 stones"
+
+    pub fn describe_count(self, count: usize) -> String {
+        if count == 1 {
+            format!("a {}", self.name())
+        } else {
+            format!("{} {}s", count, self.name())
+        }
+    }
+}
+
+impl Inventory {
+    pub fn new() -> Inventory {
+        Inventory::default()
+    }
+
+    pub fn count(&self, tool: ToolKind) -> usize {
+        self.counts[tool as usize]
+    }
+
+    pub fn add(&mut self, tool: ToolKind, count: usize) {
+        self.counts[tool as usize] += count;
+    }
+
+    // Use up one of a tool. Returns false if there weren't any.
+
+    pub fn take(&mut self, tool: ToolKind) -> bool {
+        let count = &mut self.counts[tool as usize];
+        if *count == 0 {
+            return false;
+        }
+        *count -= 1;
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.counts = [0; TOOL_KINDS.len()];
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.iter().all(|&count| count == 0)
+    }
+
+    // The tools carried, with how many of each, in TOOL_KINDS order.
+
+    pub fn tools(&self) -> impl Iterator<Item = (ToolKind, usize)> + '_ {
+        TOOL_KINDS.iter().map(move |&tool| (tool, self.count(tool))).filter(|&(_, count)| count > 0)
+    }
+}
+
+// What picking up an item gives the player, if it is a tool pickup.
+
+pub fn tool_for_item(kind: ItemKind) -> Option<(ToolKind, usize)> {
+    match kind {
+        ItemKind::Stones => Some((ToolKind::Stone, 3)),
+        ItemKind::SmokeBomb => Some((ToolKind::SmokeBomb, 1)),
+        ItemKind::WaterFlask => Some((ToolKind::WaterFlask, 1)),
+        _ => None,
+    }
+}
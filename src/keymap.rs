@@ -0,0 +1,90 @@
+use crate::cell_grid::Point;
+use crate::game::Action;
+use crate::inventory::ToolKind;
+use crate::session::DirectionPrompt;
+
+// Keys as both frontends see them, whichever library reads them, so that the
+// game is played with the same keys in a window and in a terminal.
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Key {
+    Char(char), // a letter (lower case), digit or symbol on the main keyboard
+    Numpad(u8), // a digit on the numeric keypad
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Enter,
+    Escape,
+    F(u8),
+}
+
+// What a key does while the game is being played, with no other screen up and
+// no question waiting on an answer.
+
+#[derive(Clone, Copy)]
+pub enum Command {
+    Perform(Action),
+    Prompt(DirectionPrompt), // ask for a direction, then perform
+    Throw(ToolKind), // aim, then throw
+    Help,
+    History,
+    ToggleVision,
+    NewGame,
+    Quit, // saving first
+}
+
+// The keypad moves, as do the arrows and Home, End, Page Up and Page Down,
+// which are what the keypad sends with Num Lock off.
+
+pub fn direction_for_key(key: Key) -> Option<Point> {
+    match key {
+        Key::Numpad(1) | Key::End      => Some(Point::new(-1, -1)),
+        Key::Numpad(2) | Key::Down     => Some(Point::new( 0, -1)),
+        Key::Numpad(3) | Key::PageDown => Some(Point::new( 1, -1)),
+        Key::Numpad(4) | Key::Left     => Some(Point::new(-1,  0)),
+        Key::Numpad(6) | Key::Right    => Some(Point::new( 1,  0)),
+        Key::Numpad(7) | Key::Home     => Some(Point::new(-1,  1)),
+        Key::Numpad(8) | Key::Up       => Some(Point::new( 0,  1)),
+        Key::Numpad(9) | Key::PageUp   => Some(Point::new( 1,  1)),
+        _ => None
+    }
+}
+
+pub fn command_for_key(key: Key, ctrl: bool) -> Option<Command> {
+    if let Some(dir) = direction_for_key(key) {
+        return Some(Command::Perform(Action::Move(dir)));
+    }
+
+    let command = match (key, ctrl) {
+        (Key::Numpad(5), false) | (Key::Char('.'), false) => Command::Perform(Action::Wait),
+        (Key::Escape, false)                              => Command::Quit,
+        (Key::F(1), false) | (Key::Char('?'), false)      => Command::Help,
+        (Key::Char('m'), false)                           => Command::History,
+        (Key::Char('v'), false)                           => Command::ToggleVision,
+        (Key::Char('d'), false)                           => Command::Perform(Action::DouseLight),
+        (Key::Char('c'), false)                           => Command::Prompt(DirectionPrompt::ToggleDoor),
+        (Key::Char('p'), false)                           => Command::Prompt(DirectionPrompt::PickLock),
+        (Key::Char('k'), false)                           => Command::Prompt(DirectionPrompt::Knockout),
+        (Key::Char('g'), false)                           => Command::Prompt(DirectionPrompt::Drag),
+        (Key::Char('1'), false)                           => Command::Throw(ToolKind::Stone),
+        (Key::Char('2'), false)                           => Command::Throw(ToolKind::SmokeBomb),
+        (Key::Char('3'), false)                           => Command::Throw(ToolKind::WaterFlask),
+        (Key::Char('n'), true)                            => Command::NewGame,
+        (Key::Char('r'), true)                            => Command::Perform(Action::RestartLevel),
+
+        // Debug hot-keys. Ctrl+C is left alone, as terminals use it to quit.
+
+        (Key::Char('a'), true)                            => Command::Perform(Action::ToggleSeeAll),
+        (Key::Char('s'), true)                            => Command::Perform(Action::MarkAllSeen),
+        (Key::Char('u'), true)                            => Command::Perform(Action::MarkAllUnseen),
+
+        _ => return None,
+    };
+
+    Some(command)
+}
@@ -0,0 +1,17 @@
+#![allow(clippy::needless_range_loop, clippy::needless_return, clippy::too_many_arguments, clippy::manual_div_ceil)]
+
+pub mod ascii_map;
+pub mod campaign;
+pub mod cell_grid;
+pub mod color_preset;
+pub mod game;
+pub mod guard;
+pub mod help_text;
+pub mod inventory;
+pub mod keymap;
+pub mod message_log;
+pub mod random_map;
+pub mod replay;
+pub mod save;
+pub mod session;
+pub mod tiled_map;
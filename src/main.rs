@@ -1,15 +1,29 @@
-mod cell_grid;
-mod color_preset;
+#![allow(clippy::single_match, clippy::too_many_arguments)]
+
+mod debug_layers;
 mod fontdata;
-mod guard;
-mod random_map;
+mod help;
+mod messages;
 mod speech_bubbles;
-
-use rand::prelude::*;
-
-use crate::cell_grid::*;
-use crate::guard::*;
+mod storage;
+mod targeting;
+
+use std::cmp::min;
+use std::path::PathBuf;
+
+use thiefrl3::cell_grid::*;
+use thiefrl3::color_preset;
+use thiefrl3::game::*;
+use thiefrl3::inventory::*;
+use thiefrl3::keymap::{self, command_for_key, Command};
+use thiefrl3::message_log::MessageLog;
+use thiefrl3::replay::*;
+use thiefrl3::session::*;
+
+use crate::debug_layers::DebugView;
 use crate::speech_bubbles::*;
+use crate::storage::QuicksilverStorage;
+use crate::targeting::draw_targeting;
 
 use quicksilver::{
     geom::{Rectangle, Vector},
@@ -24,20 +38,29 @@ const BAR_BACKGROUND_COLOR: Color = Color { r: 0.0625, g: 0.0625, b: 0.0625, a:
 
 const TILE_SIZE: Vector = Vector { x: 16.0, y: 16.0 };
 
-struct Game {
-    rng: MyRng,
-    level: usize,
-    map: Map,
-    lines: Lines,
-    player: Player,
-    font_image: Image,
-}
+// Number of update ticks (at 60 per second) between replayed turns.
+const PLAYBACK_TICKS_PER_STEP: u32 = 8;
+const PLAYBACK_TICKS_PER_STEP_FAST: u32 = 1;
 
 struct CrappyAppWrapper {
-    game: Game,
+    session: Session,
+    playback: Option<PlaybackControl>,
+    help_page: Option<usize>, // help page being shown, if any
+    history_scroll: Option<usize>, // messages scrolled back in the history view, if it is open
+    show_vision: bool, // shade the cells the guards in view could see the thief in
+    debug_view: DebugView, // pathing and hearing overlay, if one is turned on
+    speech_bubbles: SpeechBubbles,
+    font_image: Image,
     tileset_asset: Asset<Vec<Image>>,
 }
 
+struct PlaybackControl {
+    playback: Playback,
+    paused: bool,
+    fast_forward: bool,
+    ticks_until_step: u32,
+}
+
 fn main() {
     let settings = Settings {
         scale: quicksilver::graphics::ImageScaleStrategy::Pixelate,
@@ -47,500 +70,689 @@ fn main() {
     run::<CrappyAppWrapper>("ThiefRL 3", Vector::new(880, 760), settings);
 }
 
-fn move_player(game: &mut Game, mut dx: i32, mut dy: i32) {
-    let player = &mut game.player;
-
-    // Can't move if you're dead.
+const fn rgba(color: color_preset::Color) -> Color {
+    Color { r: color.r, g: color.g, b: color.b, a: color.a }
+}
 
-    if player.health == 0 {
-        return;
+fn color_for_item(kind: ItemKind) -> Color {
+    match kind {
+        ItemKind::Chair => rgba(color_preset::DARK_BROWN),
+        ItemKind::Table => rgba(color_preset::DARK_BROWN),
+        ItemKind::Bush => rgba(color_preset::DARK_GREEN),
+        ItemKind::Coin => rgba(color_preset::LIGHT_YELLOW),
+        ItemKind::DoorNS => rgba(color_preset::DARK_BROWN),
+        ItemKind::DoorEW => rgba(color_preset::DARK_BROWN),
+        ItemKind::DoorOpenNS => rgba(color_preset::DARK_BROWN),
+        ItemKind::DoorOpenEW => rgba(color_preset::DARK_BROWN),
+        ItemKind::DoorLockedNS => rgba(color_preset::DARK_RED),
+        ItemKind::DoorLockedEW => rgba(color_preset::DARK_RED),
+        ItemKind::PortcullisNS => rgba(color_preset::LIGHT_GRAY),
+        ItemKind::PortcullisEW => rgba(color_preset::LIGHT_GRAY),
+        ItemKind::PortcullisLoweredNS => rgba(color_preset::LIGHT_GRAY),
+        ItemKind::PortcullisLoweredEW => rgba(color_preset::LIGHT_GRAY),
+        ItemKind::Winch => rgba(color_preset::DARK_BROWN),
+        ItemKind::Stones => rgba(color_preset::LIGHT_GRAY),
+        ItemKind::SmokeBomb => rgba(color_preset::WHITE),
+        ItemKind::WaterFlask => rgba(color_preset::LIGHT_BLUE),
     }
+}
 
-    // Are we trying to exit the level?
+impl State for CrappyAppWrapper {
+    /// Load the assets and initialise the game
+    fn new() -> Result<Self> {
+        let tiles_file = "tiles.png";
 
-    let pos_new = Point::new(player.pos.x + dx, player.pos.y + dy);
+        let tileset_asset = Asset::new(Image::load(tiles_file).and_then(move |tiles| {
+            let mut tileset = Vec::with_capacity(256);
+            for y in 0..16 {
+                for x in 0..16 {
+                    let pos_px = TILE_SIZE.times(Vector::new(x, 15 - y));
+                    let rect = Rectangle::new(pos_px, TILE_SIZE);
+                    let tile = tiles.subimage(rect);
+                    tileset.push(tile);
+                }
+            }
+            Ok(tileset)
+        }));
 
-    if !on_level(&game.map.cells, pos_new) && game.map.all_seen() && game.map.all_loot_collected() {
-        game.level += 1;
-        game.map = random_map::generate_map(&mut game.rng, game.level);
+        let font_image = Image::from_bytes(&crate::fontdata::BITMAP_DATA).unwrap();
 
-        game.player.pos = game.map.pos_start;
-        game.player.dir = Point::new(0, 0);
-        game.player.gold = 0;
-        game.player.noisy = false;
-        game.player.damaged_last_turn = false;
-        game.player.finished_level = false;
-        game.player.turns_remaining_underwater = 0;
-        game.player.game_over = false;
+        if std::env::args().any(|arg| arg == "--replay") {
+            if let Some((playback, game)) = load_playback() {
+                return Ok(Self {
+                    session: Session::new(Box::new(QuicksilverStorage), game, None),
+                    playback: Some(PlaybackControl {
+                        playback,
+                        paused: false,
+                        fast_forward: false,
+                        ticks_until_step: PLAYBACK_TICKS_PER_STEP,
+                    }),
+                    help_page: None,
+                    history_scroll: None,
+                    show_vision: false,
+                    debug_view: DebugView::new(),
+                    speech_bubbles: SpeechBubbles::new(),
+                    font_image,
+                    tileset_asset,
+                });
+            }
+        }
 
-        update_map_visibility(&mut game.map, game.player.pos);
+        let session = match level_file_arg() {
+            Some(path) => Session::on_level_file(Box::new(QuicksilverStorage), &path).unwrap_or_else(|err| {
+                eprintln!("{}: {}", path.display(), err);
+                std::process::exit(1);
+            }),
+            None => Session::continue_or_new(Box::new(QuicksilverStorage)),
+        };
 
-        return;
+        Ok(Self {
+            session,
+            playback: None,
+            help_page: None,
+            history_scroll: None,
+            show_vision: false,
+            debug_view: DebugView::new(),
+            speech_bubbles: SpeechBubbles::new(),
+            font_image,
+            tileset_asset,
+        })
     }
 
-    if dx == 0 || dy == 0 {
-        if blocked(&game.map, &player.pos, &pos_new) {
-            return;
+    /// Advance replay playback
+    fn update(&mut self, _window: &mut Window) -> Result<()> {
+        if let Some(control) = &mut self.playback {
+            if control.paused || control.playback.finished() {
+                return Ok(());
+            }
+
+            if control.ticks_until_step > 0 {
+                control.ticks_until_step -= 1;
+                return Ok(());
+            }
+
+            control.ticks_until_step = if control.fast_forward {PLAYBACK_TICKS_PER_STEP_FAST} else {PLAYBACK_TICKS_PER_STEP};
+
+            self.playback_step();
         }
-    } else if blocked(&game.map, &player.pos, &pos_new) {
-        if halts_slide(&game.map, &pos_new) {
-            return;
-        } else {
-            // Attempting to move diagonally; may be able to slide along a wall.
-
-            let v_blocked = blocked(&game.map, &player.pos, &(player.pos + Point::new(dx, 0)));
-            let h_blocked = blocked(&game.map, &player.pos, &(player.pos + Point::new(0, dy)));
-
-            if v_blocked {
-                if h_blocked {
-                    return;
-                }
+        Ok(())
+    }
 
-                dx = 0;
-            } else {
-                if !h_blocked {
-                    return;
+    /// Handle input
+    fn event(&mut self, event: &Event, window: &mut Window) -> Result<()> {
+        if let Some(control) = &mut self.playback {
+            if let Event::Key(key, quicksilver::input::ButtonState::Pressed) = event {
+                match key {
+                    Key::Space => control.paused = !control.paused,
+                    Key::F => control.fast_forward = !control.fast_forward,
+                    Key::Numpad6 | Key::Right if control.paused => self.playback_step(),
+                    Key::Escape => window.close(),
+                    _ => ()
                 }
+            }
+            return Ok(());
+        }
 
-                dy = 0;
+        // The help screen swallows key presses until it is closed.
+
+        if let (Some(page), Event::Key(key, quicksilver::input::ButtonState::Pressed)) = (self.help_page, event) {
+            match key {
+                Key::Numpad4 | Key::Left  => self.help_page = Some(page.saturating_sub(1)),
+                Key::Numpad6 | Key::Right => self.help_page = Some(min(page + 1, help::num_pages() - 1)),
+                Key::F1 | Key::Escape     => self.help_page = None,
+                _ => ()
             }
+            return Ok(());
         }
-    }
 
-    pre_turn(game);
+        if let (Some(scroll), Event::Key(key, quicksilver::input::ButtonState::Pressed)) = (self.history_scroll, event) {
+            let page_lines = messages::history_page_lines(BAR_HEIGHT, window.screen_size().y as i32 - BAR_HEIGHT);
+            let scroll_max = self.session.message_log.messages.len().saturating_sub(1);
+            match key {
+                Key::Numpad8 | Key::Up       => self.history_scroll = Some(min(scroll + 1, scroll_max)),
+                Key::Numpad2 | Key::Down     => self.history_scroll = Some(scroll.saturating_sub(1)),
+                Key::Numpad9 | Key::PageUp   => self.history_scroll = Some(min(scroll + page_lines, scroll_max)),
+                Key::Numpad3 | Key::PageDown => self.history_scroll = Some(scroll.saturating_sub(page_lines)),
+                Key::M | Key::Escape         => self.history_scroll = None,
+                _ => ()
+            }
+            return Ok(());
+        }
+
+        // While aiming a tool the movement keys move the cursor. The throw is only
+        // made if it can reach the target.
+
+        if let (Some(_), Event::Key(key, quicksilver::input::ButtonState::Pressed)) = (self.session.targeting, event) {
+            if let Some(dir) = direction_for_key(*key) {
+                self.session.move_target(dir);
+            } else {
+                match key {
+                    Key::Return | Key::Space => {
+                        let events = self.session.throw_at_target();
+                        self.show_events(&events);
+                    },
+                    Key::Escape => self.session.targeting = None,
+                    _ => (),
+                }
+            }
+            return Ok(());
+        }
 
-    let dpos = Point::new(dx, dy);
-    game.player.dir = dpos;
-    game.player.pos += dpos;
-    game.player.gold += game.map.collect_loot_at(game.player.pos);
+        // After C, P, K or G, the next direction key picks the target; anything else cancels.
 
-    // Generate movement noises.
+        if let (Some(_), Event::Key(key, quicksilver::input::ButtonState::Pressed)) = (self.session.direction_prompt, event) {
+            let events = self.session.answer_direction_prompt(direction_for_key(*key));
+            self.show_events(&events);
+            return Ok(());
+        }
 
-    let cell_type = game.map.cells[[game.player.pos.x as usize, game.player.pos.y as usize]].cell_type;
+        match event {
+            Event::Key(key, quicksilver::input::ButtonState::Pressed) => {
+                let ctrl = ctrl_down(window);
+                match key {
 
-    if cell_type == CellType::GroundWoodCreaky {
-        make_noise(&mut game.map, &mut game.player, "\u{AE}creak\u{AF}");
+                    // Debug hot-keys for the overlays, which the terminal doesn't have
+
+                    Key::F2 if ctrl => self.debug_view.cycle_layer(),
+                    Key::F3 if ctrl => self.debug_view.select_next_guard(self.session.game.map.guards.len()),
+
+                    _ => match shared_key(*key).and_then(|key| command_for_key(key, ctrl)) {
+                        Some(Command::Perform(action)) => self.perform(action),
+                        Some(Command::Prompt(prompt)) => self.prompt_for_direction(prompt),
+                        Some(Command::Throw(tool)) => self.session.start_targeting(tool),
+                        Some(Command::Help) => self.help_page = Some(0),
+                        Some(Command::History) => self.history_scroll = Some(0),
+                        Some(Command::ToggleVision) => self.show_vision = !self.show_vision,
+                        Some(Command::NewGame) => self.new_game(),
+                        Some(Command::Quit) => self.quit(window),
+                        None => (),
+                    },
+                }
+            },
+            Event::Closed => self.quit(window),
+            _ => ()
+        }
+        Ok(())
     }
 
-    advance_time(game);
-}
+    /// Draw stuff on the screen
+    fn draw(&mut self, window: &mut Window) -> Result<()> {
+        window.clear(rgba(color_preset::BLACK))?;
 
-fn make_noise(map: &mut Map, player: &mut Player, _noise: &str) {
-    player.noisy = true;
-//  txt::noise(game.player.pos, noise);
+        let game = &self.session.game;
+        let font_image = &self.font_image;
+        let playback = &self.playback;
+        let help_page = self.help_page;
+        let history_scroll = self.history_scroll;
+        let status = if history_scroll.is_some() {HISTORY_STATUS_MESSAGE.to_string()} else {self.session.status_message()};
+        let targeting = self.session.targeting.as_ref();
+        let show_vision = self.show_vision;
+        let debug_view = &self.debug_view;
+        let speech_bubbles = &mut self.speech_bubbles;
+        let message_log = &self.session.message_log;
 
-    let guards = map.find_guards_in_earshot(player.pos, 75);
+        self.tileset_asset.execute(|tileset| {
+            draw_to_window(game, font_image, tileset, speech_bubbles, message_log, help_page, history_scroll, &status, targeting, show_vision, debug_view, window);
+            if let Some(control) = playback {
+                draw_playback_status(window, font_image, control);
+            }
+            Ok(())
+        })?;
 
-    for guard in guards {
-        guard.hear_thief();
+        Ok(())
     }
 }
 
-fn halts_slide(map: &Map, pos: &Point) -> bool {
-    if pos.x < 0 || pos.x >= map.cells.extents()[0] as i32 || pos.y < 0 || pos.y >= map.cells.extents()[1] as i32 {
-        return false;
+impl CrappyAppWrapper {
+    // Escape and closing the window both come through here, and this is the only
+    // place the game is saved on the way out. Closing the window from code
+    // doesn't raise Event::Closed, so the save can't happen twice.
+
+    fn quit(&mut self, window: &mut Window) {
+        self.save_game();
+        window.close();
     }
 
-    if is_guard_at(map, pos.x, pos.y) {
-        return true;
+    fn save_game(&mut self) {
+        self.session.save_game();
+        report_errors(&mut self.session);
     }
 
-    false
-}
+    fn new_game(&mut self) {
+        self.session.new_game();
+        self.speech_bubbles.clear();
+    }
 
-fn pre_turn(game: &mut Game) {
-//  s_show_msgs = true;
-//  txt::clear();
-    game.player.noisy = false;
-    game.player.damaged_last_turn = false;
-    game.player.dir = Point::new(0, 0);
-}
+    fn perform(&mut self, action: Action) {
+        let events = self.session.perform(action);
+        self.show_events(&events);
+    }
 
-const DIRS: [Point; 4] = [
-    Point { x: -1, y:  0 },
-    Point { x:  1, y:  0 },
-    Point { x:  0, y: -1 },
-    Point { x:  0, y:  1 },
-];
-
-fn advance_time(game: &mut Game) {
-    if game.map.cells[[game.player.pos.x as usize, game.player.pos.y as usize]].cell_type == CellType::GroundWater {
-        if game.player.turns_remaining_underwater > 0 {
-            game.player.turns_remaining_underwater -= 1;
-        }
-    } else {
-        game.player.turns_remaining_underwater = 7;
+    fn prompt_for_direction(&mut self, prompt: DirectionPrompt) {
+        let events = self.session.prompt_for_direction(prompt);
+        self.show_events(&events);
     }
 
-    guard_act_all(&mut game.rng, &mut game.lines, &mut game.map, &mut game.player);
+    fn playback_step(&mut self) {
+        let control = match &mut self.playback {
+            Some(control) => control,
+            None => return,
+        };
 
-    update_map_visibility(&mut game.map, game.player.pos);
+        let desynced = control.playback.desync_turn.is_some();
 
-    if game.map.all_seen() && game.map.all_loot_collected() {
-        game.player.finished_level = true;
-    }
-}
+        let events = match control.playback.step(&mut self.session.game) {
+            Some(events) => events,
+            None => return,
+        };
+
+        // Only report the first turn that goes wrong.
 
-fn update_map_visibility(map: &mut Map, pos_viewer: Point) {
-    map.recompute_visibility(pos_viewer);
+        let desync_turn = if desynced {None} else {control.playback.desync_turn};
 
-    for dir in &DIRS {
-        let pos = pos_viewer + *dir;
-        if !blocked(map, &pos_viewer, &pos) {
-            map.recompute_visibility(pos);
+        if desync_turn.is_some() {
+            control.paused = true;
         }
-    }
-}
 
-fn on_level(map: &CellGrid, pos: Point) -> bool {
-    let size_x = map.extents()[0] as i32;
-    let size_y = map.extents()[1] as i32;
-    pos.x >= 0 && pos.y >= 0 && pos.x < size_x && pos.y < size_y
-}
+        self.show_events(&events);
 
-fn blocked(map: &Map, pos_old: &Point, pos_new: &Point) -> bool {
-    if !on_level(&map.cells, *pos_new) {
-        return true;
+        if let Some(turn) = desync_turn {
+            let msg = format!("Replay desynchronized at turn {}", turn);
+            eprintln!("{}", msg);
+            self.speech_bubbles.narration(&msg);
+        }
     }
 
-    let tile_type = map.cells[[pos_new.x as usize, pos_new.y as usize]].cell_type;
-    let tile = tile_def(tile_type);
+    // The session has already logged the events; they also go up as speech
+    // bubbles. Any trouble saving the replay along the way is reported here.
 
-    if tile.blocks_player {
-        return true;
-    }
+    fn show_events(&mut self, events: &[GameEvent]) {
+        report_errors(&mut self.session);
 
-    if tile_type == CellType::OneWayWindowE && pos_new.x <= pos_old.x {
-        return true;
+        for event in events {
+            match *event {
+                GameEvent::TurnStarted | GameEvent::LevelStarted { .. } => self.speech_bubbles.clear(),
+                GameEvent::GuardSpoke { pos, text } => self.speech_bubbles.guard_speech(pos, text),
+                GameEvent::GuardShouted { pos, text } => self.speech_bubbles.guard_shout(pos, text),
+                GameEvent::NoiseMade { pos, text } => self.speech_bubbles.noise(pos, text),
+                GameEvent::PortcullisesLowered => self.speech_bubbles.narration("The portcullises crash down!"),
+                _ => (),
+            }
+        }
     }
+}
 
-    if tile_type == CellType::OneWayWindowW && pos_new.x >= pos_old.x {
-        return true;
-    }
+fn direction_for_key(key: Key) -> Option<Point> {
+    shared_key(key).and_then(keymap::direction_for_key)
+}
 
-    if tile_type == CellType::OneWayWindowN && pos_new.y <= pos_old.y {
-        return true;
-    }
+// The keys the game is played with, named as the terminal frontend names them
+// too. The keypad is told apart from the digits above the letters, which pick
+// tools.
+
+fn shared_key(key: Key) -> Option<keymap::Key> {
+    let key = match key {
+        Key::Key0 => keymap::Key::Char('0'),
+        Key::Key1 => keymap::Key::Char('1'),
+        Key::Key2 => keymap::Key::Char('2'),
+        Key::Key3 => keymap::Key::Char('3'),
+        Key::Key4 => keymap::Key::Char('4'),
+        Key::Key5 => keymap::Key::Char('5'),
+        Key::Key6 => keymap::Key::Char('6'),
+        Key::Key7 => keymap::Key::Char('7'),
+        Key::Key8 => keymap::Key::Char('8'),
+        Key::Key9 => keymap::Key::Char('9'),
+        Key::A => keymap::Key::Char('a'),
+        Key::B => keymap::Key::Char('b'),
+        Key::C => keymap::Key::Char('c'),
+        Key::D => keymap::Key::Char('d'),
+        Key::E => keymap::Key::Char('e'),
+        Key::F => keymap::Key::Char('f'),
+        Key::G => keymap::Key::Char('g'),
+        Key::H => keymap::Key::Char('h'),
+        Key::I => keymap::Key::Char('i'),
+        Key::J => keymap::Key::Char('j'),
+        Key::K => keymap::Key::Char('k'),
+        Key::L => keymap::Key::Char('l'),
+        Key::M => keymap::Key::Char('m'),
+        Key::N => keymap::Key::Char('n'),
+        Key::O => keymap::Key::Char('o'),
+        Key::P => keymap::Key::Char('p'),
+        Key::Q => keymap::Key::Char('q'),
+        Key::R => keymap::Key::Char('r'),
+        Key::S => keymap::Key::Char('s'),
+        Key::T => keymap::Key::Char('t'),
+        Key::U => keymap::Key::Char('u'),
+        Key::V => keymap::Key::Char('v'),
+        Key::W => keymap::Key::Char('w'),
+        Key::X => keymap::Key::Char('x'),
+        Key::Y => keymap::Key::Char('y'),
+        Key::Z => keymap::Key::Char('z'),
+        Key::Period => keymap::Key::Char('.'),
+        Key::Space => keymap::Key::Char(' '),
+        Key::Numpad0 => keymap::Key::Numpad(0),
+        Key::Numpad1 => keymap::Key::Numpad(1),
+        Key::Numpad2 => keymap::Key::Numpad(2),
+        Key::Numpad3 => keymap::Key::Numpad(3),
+        Key::Numpad4 => keymap::Key::Numpad(4),
+        Key::Numpad5 => keymap::Key::Numpad(5),
+        Key::Numpad6 => keymap::Key::Numpad(6),
+        Key::Numpad7 => keymap::Key::Numpad(7),
+        Key::Numpad8 => keymap::Key::Numpad(8),
+        Key::Numpad9 => keymap::Key::Numpad(9),
+        Key::Up => keymap::Key::Up,
+        Key::Down => keymap::Key::Down,
+        Key::Left => keymap::Key::Left,
+        Key::Right => keymap::Key::Right,
+        Key::Home => keymap::Key::Home,
+        Key::End => keymap::Key::End,
+        Key::PageUp => keymap::Key::PageUp,
+        Key::PageDown => keymap::Key::PageDown,
+        Key::Return => keymap::Key::Enter,
+        Key::Escape => keymap::Key::Escape,
+        Key::F1 => keymap::Key::F(1),
+        Key::F2 => keymap::Key::F(2),
+        Key::F3 => keymap::Key::F(3),
+        _ => return None,
+    };
+    Some(key)
+}
 
-    if tile_type == CellType::OneWayWindowS && pos_new.y >= pos_old.y {
-        return true;
-    }
+fn ctrl_down(window: &Window) -> bool {
+    let keyboard = window.keyboard();
+    keyboard[Key::LControl].is_down() || keyboard[Key::RControl].is_down()
+}
 
-    if is_guard_at(map, pos_new.x, pos_new.y) {
-        return true;
-    }
+// The file given with --level FILE, to start a new game on instead of
+// continuing the saved one.
 
-    false
+fn level_file_arg() -> Option<PathBuf> {
+    let mut args = std::env::args().skip_while(|arg| arg != "--level");
+    args.next()?;
+    args.next().map(PathBuf::from)
 }
 
-fn glyph_for_item(kind: ItemKind) -> usize {
-    match kind {
-        ItemKind::Chair => 148,
-        ItemKind::Table => 146,
-        ItemKind::Bush => 144,
-        ItemKind::Coin => 158,
-        ItemKind::DoorNS => 169,
-        ItemKind::DoorEW => 167,
-        ItemKind::PortcullisNS => 194,
-        ItemKind::PortcullisEW => 194,
+fn report_errors(session: &mut Session) {
+    for err in session.errors.drain(..) {
+        eprintln!("{}", err);
     }
 }
 
-fn color_for_item(kind: ItemKind) -> Color {
-    match kind {
-        ItemKind::Chair => color_preset::DARK_BROWN,
-        ItemKind::Table => color_preset::DARK_BROWN,
-        ItemKind::Bush => color_preset::DARK_GREEN,
-        ItemKind::Coin => color_preset::LIGHT_YELLOW,
-        ItemKind::DoorNS => color_preset::DARK_BROWN,
-        ItemKind::DoorEW => color_preset::DARK_BROWN,
-        ItemKind::PortcullisNS => color_preset::LIGHT_GRAY,
-        ItemKind::PortcullisEW => color_preset::LIGHT_GRAY,
+fn load_playback() -> Option<(Playback, GameState)> {
+    let replay = match load_replay(&QuicksilverStorage) {
+        Ok(Some(replay)) => replay,
+        Ok(None) => {
+            eprintln!("Not playing back; no replay has been saved");
+            return None;
+        },
+        Err(err) => {
+            eprintln!("Not playing back; {}", err);
+            return None;
+        }
+    };
+
+    match Playback::new(replay) {
+        Ok(playback) => Some(playback),
+        Err(err) => {
+            eprintln!("Not playing back; {}", err);
+            None
+        }
     }
 }
 
-impl State for CrappyAppWrapper {
-    /// Load the assets and initialise the game
-    fn new() -> Result<Self> {
-        let tiles_file = "tiles.png";
+fn draw_to_window(game: &GameState, font_image: &Image, tileset: &[Image], speech_bubbles: &mut SpeechBubbles, message_log: &MessageLog, help_page: Option<usize>, history_scroll: Option<usize>, status: &str, targeting: Option<&Targeting>, show_vision: bool, debug_view: &DebugView, window: &mut Window) {
 
-        let tileset_asset = Asset::new(Image::load(tiles_file).and_then(move |tiles| {
-            let mut tileset = Vec::with_capacity(256);
-            for y in 0..16 {
-                for x in 0..16 {
-                    let pos_px = TILE_SIZE.times(Vector::new(x, 15 - y));
-                    let rect = Rectangle::new(pos_px, TILE_SIZE);
-                    let tile = tiles.subimage(rect);
-                    tileset.push(tile);
-                }
-            }
-            Ok(tileset)
-        }));
+    let screen_size = window.screen_size();
+    let screen_size_x: usize = screen_size.x as usize;
+    let screen_size_y: usize = screen_size.y as usize;
 
-        let font_image = Image::from_bytes(&crate::fontdata::BITMAP_DATA).unwrap();
+    let map = &game.map;
+    let map_size_x = map.cells.extents()[0];
+    let map_size_y = map.cells.extents()[1];
 
-        let random_seed = rand::random::<u64>();
-        let mut rng = MyRng::seed_from_u64(random_seed);
-        let level = 0;
-        let mut map = random_map::generate_map(&mut rng, level);
-        let player = make_player(&map.pos_start);
-        let lines = new_lines();
+    let map_screen_size = TILE_SIZE.times(Vector::new(map_size_x as f32, map_size_y as f32));
 
-        update_map_visibility(&mut map, player.pos);
+    let offset_px = Vector::new((screen_size_x as f32 - map_screen_size.x) / 2.0, BAR_HEIGHT as f32 + (screen_size_y as f32 - ((2 * BAR_HEIGHT) as f32 + map_screen_size.y)) / 2.0);
 
-        Ok(Self {
-            game: Game {
-                rng,
-                level: 0,
-                lines,
-                map,
-                player,
-                font_image,
-            },
-            tileset_asset,
-       })
-    }
+    let items = &game.map.items;
+    let player = &game.player;
+    let guards = &game.map.guards;
 
-    /// Handle input
-    fn event(&mut self, event: &Event, window: &mut Window) -> Result<()> {
-        match event {
-            Event::Key(key, quicksilver::input::ButtonState::Pressed) =>
-                match key {
-                    Key::Numpad1 | Key::End      => move_player(&mut self.game, -1, -1),
-                    Key::Numpad2 | Key::Down     => move_player(&mut self.game,  0, -1),
-                    Key::Numpad3 | Key::PageDown => move_player(&mut self.game,  1, -1),
-                    Key::Numpad4 | Key::Left     => move_player(&mut self.game, -1,  0),
-                    Key::Numpad5                 => move_player(&mut self.game,  0,  0),
-                    Key::Numpad6 | Key::Right    => move_player(&mut self.game,  1,  0),
-                    Key::Numpad7 | Key::Home     => move_player(&mut self.game, -1,  1),
-                    Key::Numpad8 | Key::Up       => move_player(&mut self.game,  0,  1),
-                    Key::Numpad9 | Key::PageUp   => move_player(&mut self.game,  1,  1),
-                    Key::Escape                  => window.close(),
-                    
-                    // TODO: Hot-keys should require Ctrl to be presssed
-
-                    Key::A => self.game.player.see_all = !self.game.player.see_all,
-                    Key::S => self.game.map.mark_all_seen(),
-                    Key::C => {
-                        self.game.map.mark_all_unseen();
-                        update_map_visibility(&mut self.game.map, self.game.player.pos);
-                    },
-                    _ => ()
-                }
-            _ => ()
+    for x in 0..map_size_x {
+        for y in 0..map_size_y {
+            let pos = Vector::new(x as f32, ((map_size_y - 1) - y) as f32);
+            let cell = &map.cells[[x, y]];
+            if !cell.seen && !player.see_all {
+                continue;
+            }
+            let tile = tile_def(cell.cell_type);
+            let image = &tileset[tile.glyph];
+            let pos_px = offset_px + TILE_SIZE.times(pos);
+            let color = if cell.lit || tile.ignores_lighting {rgba(tile.color)} else {rgba(color_preset::DARK_BLUE)};
+            window.draw(
+                &Rectangle::new(pos_px, image.area().size()),
+                Blended(image, color),
+            )
         }
-        Ok(())
     }
+    for item in items {
+        let pos = Vector::new(item.pos.x, (map_size_y - 1) as i32 - item.pos.y);
+        let cell = &map.cells[[item.pos.x as usize, item.pos.y as usize]];
+        if !cell.seen && !player.see_all {
+            continue;
+        }
+        let pos_px = offset_px + pos.times(TILE_SIZE);
+        let glyph = glyph_for_item(item.kind);
+        let color = if cell.lit {color_for_item(item.kind)} else {rgba(color_preset::DARK_BLUE)};
+        let image = &tileset[glyph];
+        window.draw(
+            &Rectangle::new(pos_px, image.area().size()),
+            Blended(image, color),
+        );
+    }
+    for key in &map.keys {
+        let cell = &map.cells[[key.pos.x as usize, key.pos.y as usize]];
+        if !cell.seen && !player.see_all {
+            continue;
+        }
+        let pos = Vector::new(key.pos.x, (map_size_y - 1) as i32 - key.pos.y);
+        let pos_px = offset_px + pos.times(TILE_SIZE);
+        let image = &tileset[GLYPH_KEY];
+        let color = if cell.lit {rgba(color_preset::LIGHT_CYAN)} else {rgba(color_preset::DARK_BLUE)};
+        window.draw(
+            &Rectangle::new(pos_px, image.area().size()),
+            Blended(image, color),
+        );
+    }
+    for light in &map.lights {
+        let cell = &map.cells[[light.pos.x as usize, light.pos.y as usize]];
+        if !cell.seen && !player.see_all {
+            continue;
+        }
+        let pos = Vector::new(light.pos.x, (map_size_y - 1) as i32 - light.pos.y);
 
-    /// Draw stuff on the screen
-    fn draw(&mut self, window: &mut Window) -> Result<()> {
-        window.clear(color_preset::BLACK)?;
-
-        let game = &self.game;
+        // Candles sit on top of their tables, so draw them raised a little.
 
-        self.tileset_asset.execute(|tileset| {
-            game.draw_to_window(tileset, window);
-            Ok(())
-        })?;
-
-        Ok(())
+        let raise = if light.kind == LightKind::Candle {Vector::new(0, 6)} else {Vector::new(0, 0)};
+        let pos_px = offset_px + pos.times(TILE_SIZE) - raise;
+        let image = &tileset[glyph_for_light(light.kind)];
+        let color = if light.lit {rgba(color_preset::LIGHT_YELLOW)} else {rgba(color_preset::DARK_GRAY)};
+        window.draw(
+            &Rectangle::new(pos_px, image.area().size()),
+            Blended(image, color),
+        );
     }
-}
 
-impl Game {
-    fn draw_to_window(&self, tileset: &Vec<Image>, window: &mut Window) {
+    // Smoke hangs over everything on the ground.
 
-        let screen_size = window.screen_size();
-        let screen_size_x: usize = screen_size.x as usize;
-        let screen_size_y: usize = screen_size.y as usize;
+    const SMOKE_COLOR: Color = Color { r: 0.75, g: 0.75, b: 0.75, a: 0.5 };
 
-        let map = &self.map;
-        let map_size_x = map.cells.extents()[0];
-        let map_size_y = map.cells.extents()[1];
+    for x in 0..map_size_x {
+        for y in 0..map_size_y {
+            let cell = &map.cells[[x, y]];
+            if cell.smoke == 0 || (!cell.seen && !player.see_all) {
+                continue;
+            }
+            let pos = Vector::new(x as f32, ((map_size_y - 1) - y) as f32);
+            let pos_px = offset_px + TILE_SIZE.times(pos);
+            window.draw(&Rectangle::new(pos_px, TILE_SIZE), Col(SMOKE_COLOR));
+        }
+    }
 
-        let map_screen_size = TILE_SIZE.times(Vector::new(map_size_x as f32, map_size_y as f32));
+    // Shade where the guards in view would spot the thief, if asked to.
 
-        let offset_px = Vector::new((screen_size_x as f32 - map_screen_size.x) / 2.0, BAR_HEIGHT as f32 + (screen_size_y as f32 - ((2 * BAR_HEIGHT) as f32 + map_screen_size.y)) / 2.0);
+    if show_vision {
+        const VISION_COLOR: Color = Color { r: 1.0, g: 0.25, b: 0.25, a: 0.3 };
 
-        let items = &self.map.items;
-        let player = &self.player;
-        let guards = &self.map.guards;
-        let font_image = &self.font_image;
+        let watchers: Vec<&Guard> = guards.iter().filter(|guard| {
+            let cell = &map.cells[[guard.pos.x as usize, guard.pos.y as usize]];
+            !guard.unconscious() && (player.see_all || cell.seen || guard.speaking)
+        }).collect();
 
         for x in 0..map_size_x {
             for y in 0..map_size_y {
-                let pos = Vector::new(x as f32, ((map_size_y - 1) - y) as f32);
-                let cell = &map.cells[[x, y]];
-                if !cell.seen && !player.see_all {
+                let pos_cell = Point::new(x as i32, y as i32);
+                if !watchers.iter().any(|guard| guard.sees_thief_at(map, player, pos_cell)) {
                     continue;
                 }
-                let tile = tile_def(cell.cell_type);
-                let image = &tileset[tile.glyph];
+                let pos = Vector::new(x as f32, ((map_size_y - 1) - y) as f32);
                 let pos_px = offset_px + TILE_SIZE.times(pos);
-                let color = if cell.lit || tile.ignores_lighting {tile.color} else {color_preset::DARK_BLUE};
-                window.draw(
-                    &Rectangle::new(pos_px, image.area().size()),
-                    Blended(&image, color),
-                )
+                window.draw(&Rectangle::new(pos_px, TILE_SIZE), Col(VISION_COLOR));
             }
         }
-        for item in items {
-            let pos = Vector::new(item.pos.x, (map_size_y - 1) as i32 - item.pos.y);
-            let cell = &map.cells[[item.pos.x as usize, item.pos.y as usize]];
-            if !cell.seen && !player.see_all {
+    }
+    {
+        let glyph = GLYPH_PLAYER;
+
+        let lit = map.cells[[player.pos.x as usize, player.pos.y as usize]].lit;
+        let noisy = player.noisy;
+        let damaged = player.damaged_last_turn;
+        let hidden = player.hidden(map);
+
+        let color =
+            if damaged {Color {r: 1.0, g: 0.0, b: 0.0, a: 1.0}}
+            else if noisy {rgba(color_preset::LIGHT_CYAN)}
+            else if hidden {Color {r: 0.0625, g: 0.0625, b: 0.0625, a: 0.875}}
+            else if lit {rgba(color_preset::LIGHT_GRAY)}
+            else {rgba(color_preset::LIGHT_BLUE)};
+
+        let image = &tileset[glyph];
+        let pos = Vector::new(player.pos.x, (map_size_y - 1) as i32 - player.pos.y);
+        let pos_px = offset_px + pos.times(TILE_SIZE);
+        window.draw(
+            &Rectangle::new(pos_px, image.area().size()),
+            Blended(image, color),
+        );
+    }
+    for guard in guards {
+        let glyph = glyph_for_guard(guard.dir);
+
+        let image = &tileset[glyph];
+        let pos = Vector::new(guard.pos.x, (map_size_y - 1) as i32 - guard.pos.y);
+        let pos_px = offset_px + pos.times(TILE_SIZE);
+        
+        let cell = &map.cells[[guard.pos.x as usize, guard.pos.y as usize]];
+        
+        let visible = player.see_all || cell.seen || guard.speaking;
+
+        if !visible {
+            let dpos = player.pos - guard.pos;
+            if dpos.length_squared() > 36 {
                 continue;
             }
-            let pos_px = offset_px + pos.times(TILE_SIZE);
-            let glyph = glyph_for_item(item.kind);
-            let color = if cell.lit {color_for_item(item.kind)} else {color_preset::DARK_BLUE};
-            let image = &tileset[glyph];
-            window.draw(
-                &Rectangle::new(pos_px, image.area().size()),
-                Blended(&image, color),
-            );
         }
-        {
-            let glyph = 208;
-
-            let lit = map.cells[[player.pos.x as usize, player.pos.y as usize]].lit;
-            let noisy = player.noisy;
-            let damaged = player.damaged_last_turn;
-            let hidden = player.hidden(map);
 
-            let color =
-                if damaged {Color {r: 1.0, g: 0.0, b: 0.0, a: 1.0}}
-                else if noisy {color_preset::LIGHT_CYAN}
-                else if hidden {Color {r: 0.0625, g: 0.0625, b: 0.0625, a: 0.875}}
-                else if lit {color_preset::LIGHT_GRAY}
-                else {color_preset::LIGHT_BLUE};
+        let color =
+            if !visible {
+                rgba(color_preset::DARK_GRAY)
+            } else if guard.unconscious() {
+                rgba(color_preset::DARK_MAGENTA)
+            } else if guard.mode == GuardMode::Patrol && !guard.speaking && !cell.lit {
+                rgba(color_preset::DARK_BLUE)
+            } else {
+                rgba(color_preset::LIGHT_MAGENTA)
+            };
 
+        window.draw(
+            &Rectangle::new(pos_px, image.area().size()),
+            Blended(image, color)
+        );
+    }
+    for guard in guards {
+        if let Some(glyph) = guard.overhead_icon(map, player) {
             let image = &tileset[glyph];
-            let pos = Vector::new(player.pos.x, (map_size_y - 1) as i32 - player.pos.y);
-            let pos_px = offset_px + pos.times(TILE_SIZE);
+            let pos = Vector::new(guard.pos.x, (map_size_y - 1) as i32 - guard.pos.y);
+            let pos_px = offset_px + pos.times(TILE_SIZE) - Vector::new(0, 10);
+            let color = rgba(color_preset::LIGHT_YELLOW);
             window.draw(
                 &Rectangle::new(pos_px, image.area().size()),
-                Blended(&image, color),
+                Blended(image, color)
             );
         }
-        for guard in guards {
-            let glyph =
-                if guard.dir.y > 0 {210}
-                else if guard.dir.y < 0 {212}
-                else if guard.dir.x > 0 {209}
-                else if guard.dir.x < 0 {211}
-                else {212};
+    }
+    if let Some(targeting) = targeting {
+        draw_targeting(window, targeting, map, player.pos, offset_px);
+    }
 
-            let image = &tileset[glyph];
-            let pos = Vector::new(guard.pos.x, (map_size_y - 1) as i32 - guard.pos.y);
-            let pos_px = offset_px + pos.times(TILE_SIZE);
-            
-            let cell = &map.cells[[guard.pos.x as usize, guard.pos.y as usize]];
-            
-            let visible = player.see_all || cell.seen || guard.speaking;
+    debug_view.draw(window, font_image, tileset, map, player, offset_px);
 
-            if !visible {
-                let dpos = player.pos - guard.pos;
-                if dpos.length_squared() > 36 {
-                    continue;
-                }
-            }
+    {
+        let view_min = Point::new(0, BAR_HEIGHT);
+        let view_max = Point::new(screen_size_x as i32, screen_size_y as i32 - BAR_HEIGHT);
+        let cell_px = |pos: Point| {
+            let pos_px = offset_px + Vector::new(pos.x, (map_size_y - 1) as i32 - pos.y).times(TILE_SIZE);
+            Point::new(pos_px.x as i32, pos_px.y as i32)
+        };
+        speech_bubbles.layout(view_min, view_max, player.pos, cell_px);
+        speech_bubbles.draw(window, font_image);
+    }
 
-            let color =
-                if !visible {
-                    color_preset::DARK_GRAY
-                } else if guard.mode == GuardMode::Patrol && !guard.speaking && !cell.lit {
-                    color_preset::DARK_BLUE
-                } else {
-                    color_preset::LIGHT_MAGENTA
-                };
+    window.flush().unwrap();
 
-            window.draw(
-                &Rectangle::new(pos_px, image.area().size()),
-                Blended(&image, color)
-            );
-        }
-        for guard in guards {
-            if let Some(glyph) = guard.overhead_icon(map, player) {
-                let image = &tileset[glyph];
-                let pos = Vector::new(guard.pos.x, (map_size_y - 1) as i32 - guard.pos.y);
-                let pos_px = offset_px + pos.times(TILE_SIZE) - Vector::new(0, 10);
-                let color = color_preset::LIGHT_YELLOW;
-                window.draw(
-                    &Rectangle::new(pos_px, image.area().size()),
-                    Blended(&image, color)
-                );
-            }
-        }
+    let view_top = BAR_HEIGHT;
+    let view_bottom = screen_size_y as i32 - BAR_HEIGHT;
 
-/*
-        if let Some(guard) = guards.first() {
-            if guard.region_goal != INVALID_REGION {
-                let distance_field = map.compute_distances_to_region(guard.region_goal);
-                for x in 0..map_size_x {
-                    for y in 0..map_size_y {
-                        let pos = Vector::new(x as f32, ((map_size_y - 1) - y) as f32);
-                        let d = distance_field[[x, y]];
-                        if d == 0 || d == INFINITE_COST {
-                            continue;
-                        }
-                        let digit = (d % 10) + 48;
-                        let band = d / 10;
-                        let image = &tileset[digit];
-                        let pos_px = offset_px + TILE_SIZE.times(pos);
-                        let color = if band == 0 {color_preset::WHITE} else if band == 1 {color_preset::LIGHT_YELLOW} else {color_preset::DARK_GRAY};
-                        window.draw(
-                            &Rectangle::new(pos_px, image.area().size()),
-                            Blended(&image, color),
-                        )
-                    }
-                }
-            }
-        }
-*/
-
-/*
-        if let Some(guard) = guards.first() {
-            let image = &tileset[255];
-            if guard.region_prev != INVALID_REGION {
-
-                let region = &map.patrol_regions[guard.region_prev];
-                for x in region.pos_min.x .. region.pos_max.x {
-                    for y in region.pos_min.y .. region.pos_max.y {
-                        let pos = Vector::new(x as f32, ((map_size_y - 1) as i32 - y) as f32);
-                        let pos_px = offset_px + TILE_SIZE.times(pos);
-                        let color = Color {r:1.0, g:0.0, b:0.0, a:0.25};
-                        window.draw(
-                            &Rectangle::new(pos_px, image.area().size()),
-                            Blended(&image, color),
-                        )
-                    }
-                }
-            }
-            if guard.region_goal != INVALID_REGION {
-                let region = &map.patrol_regions[guard.region_goal];
-                for x in region.pos_min.x .. region.pos_max.x {
-                    for y in region.pos_min.y .. region.pos_max.y {
-                        let pos = Vector::new(x as f32, ((map_size_y - 1) as i32 - y) as f32);
-                        let pos_px = offset_px + TILE_SIZE.times(pos);
-                        let color = Color {r:0.0, g:1.0, b:0.0, a:0.25};
-                        window.draw(
-                            &Rectangle::new(pos_px, image.area().size()),
-                            Blended(&image, color),
-                        )
-                    }
-                }
-            }
-        }
-*/
+    if let Some(page) = help_page {
+        help::draw_help(window, font_image, page, view_top, view_bottom);
+    } else if let Some(scroll) = history_scroll {
+        messages::draw_history(window, font_image, message_log, scroll, view_top, view_bottom);
+    } else {
+        messages::draw_panel(window, font_image, message_log, view_bottom);
+    }
+
+    draw_top_status_bar(window, font_image, help_page, status);
+    draw_bottom_status_bar(window, font_image, tileset, map, player, game.level);
+}
 
-        window.flush().unwrap();
+fn draw_playback_status(window: &mut Window, font_image: &Image, control: &PlaybackControl) {
+    let screen_size_x: i32 = window.screen_size().x as i32;
 
-        draw_top_status_bar(window, font_image, player, self.level);
-        draw_bottom_status_bar(window, font_image, tileset, map, player, self.level);
-    }
+    let playback = &control.playback;
+
+    let state =
+        if playback.finished() {"finished"}
+        else if control.paused {"paused"}
+        else if control.fast_forward {"fast"}
+        else {"playing"};
+
+    let msg = match playback.desync_turn {
+        Some(turn) => format!("Replay {}/{} ({}) DESYNC at turn {}", playback.next_step, playback.num_steps(), state, turn),
+        None => format!("Replay {}/{} ({})", playback.next_step, playback.num_steps(), state),
+    };
+
+    let (x_min, x_max) = get_horizontal_extents(&msg);
+    let x = screen_size_x - (8 + (x_max - x_min));
+
+    window.draw(
+        &Rectangle::new((x - 8, 0), (screen_size_x - (x - 8), BAR_HEIGHT)),
+        Col(BAR_BACKGROUND_COLOR),
+    );
+
+    let color = if playback.desync_turn.is_some() {rgba(color_preset::LIGHT_RED)} else {rgba(color_preset::LIGHT_CYAN)};
+    puts_proportional(window, font_image, x, 0, &msg, &color);
 }
 
-fn draw_bottom_status_bar(window: &mut Window, font_image: &Image, tileset: &Vec<Image>, map: &Map, player: &Player, level: usize) {
+fn draw_bottom_status_bar(window: &mut Window, font_image: &Image, tileset: &[Image], map: &Map, player: &Player, level: usize) {
     let screen_size = window.screen_size();
     let screen_size_x: i32 = screen_size.x as i32;
     let screen_size_y: i32 = screen_size.y as i32;
@@ -576,6 +788,20 @@ fn draw_bottom_status_bar(window: &mut Window, font_image: &Image, tileset: &Vec
         x += TILE_SIZE_X;
     }
 
+    if !player.keys.is_empty() {
+        x += 12;
+
+        let tile_key = &tileset[GLYPH_KEY];
+
+        for _ in 0..player.keys.len() {
+            window.draw(
+                &Rectangle::new((x, y_base + 5), tile_key.area().size()),
+                Blended(tile_key, rgba(color_preset::LIGHT_CYAN))
+            );
+            x += TILE_SIZE_X;
+        }
+    }
+
     let player_underwater = map.cells[[player.pos.x as usize, player.pos.y as usize]].cell_type == CellType::GroundWater && player.turns_remaining_underwater > 0;
 
     if player_underwater {
@@ -605,6 +831,28 @@ fn draw_bottom_status_bar(window: &mut Window, font_image: &Image, tileset: &Vec
         }
     }
 
+    // Draw the tools being carried, each with its count.
+
+    if !player.inventory.is_empty() {
+        x = screen_size_x * 5 / 8;
+
+        for (tool, count) in player.inventory.tools() {
+            let item_kind = match tool {
+                ToolKind::Stone => ItemKind::Stones,
+                ToolKind::SmokeBomb => ItemKind::SmokeBomb,
+                ToolKind::WaterFlask => ItemKind::WaterFlask,
+            };
+            let tile = &tileset[glyph_for_item(item_kind)];
+            window.draw(
+                &Rectangle::new((x, y_base + 5), tile.area().size()),
+                Blended(tile, color_for_item(item_kind))
+            );
+            x += TILE_SIZE_X + 2;
+            x = puts_proportional(window, font_image, x, y_base, &count.to_string(), &rgba(color_preset::LIGHT_GRAY));
+            x += 12;
+        }
+    }
+
     // Draw the tallies of what's been seen and collected.
 
     let percent_seen: usize = map.percent_seen();
@@ -631,7 +879,7 @@ fn draw_bottom_status_bar(window: &mut Window, font_image: &Image, tileset: &Vec
     }
 }
 
-fn draw_top_status_bar(window: &mut Window, font_image: &Image, player: &Player, level: usize) {
+fn draw_top_status_bar(window: &mut Window, font_image: &Image, help_page: Option<usize>, status: &str) {
     let screen_size = window.screen_size();
     let screen_size_x: i32 = screen_size.x as i32;
     window.draw(
@@ -641,34 +889,17 @@ fn draw_top_status_bar(window: &mut Window, font_image: &Image, player: &Player,
 
     let y_base = 0;
 
-/*
-    if s_showHelp {
-        sprintf_s(msgBuffer, sizeof(msgBuffer), "Page %d of %d", s_helpPage + 1, 2);
-
-        int xMin, xMax;
-        txt::getHorizontalExtents(msgBuffer, xMin, xMax);
+    if let Some(page) = help_page {
+        let page_msg = format!("Page {} of {}", page + 1, help::num_pages());
 
-        int x = screenSizeX - (8 + (xMax - xMin));
-
-        txt::putsProportional(x, y_base, msgBuffer);
+        let (x_min, x_max) = get_horizontal_extents(&page_msg);
+        let x = screen_size_x - (8 + (x_max - x_min));
 
-        "Press left/right arrow keys to view help, or F1 to close"
-    } else
-*/
-    {
-        let msg =
-            if player.game_over || player.health == 0 {
-                format!("You are dead! Press Ctrl+N for a new game or Ctrl+R to restart.")
-            } else if player.finished_level {
-                format!("Level {} complete! Move off the edge of the map to advance to the next level.", level + 1)
-            } else if level == 0 {
-                format!("Welcome to level {}. Collect the gold coins and reveal the whole mansion. (Press F1 for help.)", level + 1)
-            } else if level == 1 {
-                format!("Welcome to level {}. Watch out for the patrolling guard! (Press F1 for help.)", level + 1)
-            } else {
-                format!("Press F1 for help")
-            };
+        puts_proportional(window, font_image, x, y_base, &page_msg, &rgba(color_preset::WHITE));
 
-        puts_proportional(window, font_image, 8, y_base, &msg, &color_preset::WHITE);
+        let msg = "Press left/right arrow keys to view help, or F1 to close";
+        puts_proportional(window, font_image, 8, y_base, msg, &rgba(color_preset::WHITE));
+    } else {
+        puts_proportional(window, font_image, 8, y_base, status, &rgba(color_preset::WHITE));
     }
 }
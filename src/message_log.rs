@@ -0,0 +1,151 @@
+use crate::cell_grid::{LightKind, Point};
+use crate::color_preset::{self, Color};
+use crate::game::GameEvent;
+
+// Oldest messages are dropped once the log holds this many.
+
+const MAX_MESSAGES: usize = 1000;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MessageCategory {
+    GuardSpeech,
+    Noise,
+    Damage,
+    Light,
+    Lock,
+    Tool,
+    Level,
+}
+
+pub struct Message {
+    pub category: MessageCategory,
+    pub turn: usize,
+    pub pos: Option<Point>, // where it happened, if anywhere in particular
+    pub text: String,
+}
+
+// A running record of what has happened, built from the events returned by
+// GameState::step. Frontends show the tail of it and let the player scroll back.
+
+pub struct MessageLog {
+    pub messages: Vec<Message>,
+    pub turn: usize,
+}
+
+impl MessageCategory {
+    pub fn color(self) -> Color {
+        match self {
+            MessageCategory::GuardSpeech => color_preset::LIGHT_MAGENTA,
+            MessageCategory::Noise => color_preset::LIGHT_CYAN,
+            MessageCategory::Damage => color_preset::LIGHT_RED,
+            MessageCategory::Light => color_preset::LIGHT_YELLOW,
+            MessageCategory::Lock => color_preset::LIGHT_GREEN,
+            MessageCategory::Tool => color_preset::DARK_CYAN,
+            MessageCategory::Level => color_preset::WHITE,
+        }
+    }
+}
+
+impl Message {
+    // The message with its turn and position, for the history view.
+
+    pub fn describe(&self) -> String {
+        match self.pos {
+            Some(pos) => format!("{:5}  ({}, {})  {}", self.turn, pos.x, pos.y, self.text),
+            None => format!("{:5}  {}", self.turn, self.text),
+        }
+    }
+}
+
+impl MessageLog {
+    pub fn new() -> MessageLog {
+        MessageLog {
+            messages: Vec::new(),
+            turn: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.messages.clear();
+        self.turn = 0;
+    }
+
+    pub fn add(&mut self, category: MessageCategory, pos: Option<Point>, text: String) {
+        if self.messages.len() >= MAX_MESSAGES {
+            self.messages.remove(0);
+        }
+        self.messages.push(Message { category, turn: self.turn, pos, text });
+    }
+
+    pub fn add_events(&mut self, events: &[GameEvent]) {
+        for event in events {
+            match *event {
+                GameEvent::TurnStarted => self.turn += 1,
+                GameEvent::LevelStarted { level } =>
+                    self.add(MessageCategory::Level, None, format!("Level {} begins.", level + 1)),
+                GameEvent::GuardSpoke { pos, text } =>
+                    self.add(MessageCategory::GuardSpeech, Some(pos), format!("Guard: {}", text)),
+                GameEvent::GuardShouted { pos, text } =>
+                    self.add(MessageCategory::GuardSpeech, Some(pos), format!("Guard shouts: {}", text)),
+                GameEvent::NoiseMade { pos, text } =>
+                    self.add(MessageCategory::Noise, Some(pos), text.to_string()),
+                GameEvent::LightDoused { pos, kind } =>
+                    self.add(MessageCategory::Light, Some(pos), format!("You put out the {}.", light_name(kind))),
+                GameEvent::LightRelit { pos, kind } =>
+                    self.add(MessageCategory::Light, Some(pos), format!("A guard relights the {}.", light_name(kind))),
+                GameEvent::PortcullisesLowered =>
+                    self.add(MessageCategory::Noise, None, "The portcullises crash down!".to_string()),
+                GameEvent::PortcullisesRaised =>
+                    self.add(MessageCategory::Noise, None, "You winch the portcullises back up.".to_string()),
+                GameEvent::DoorLocked { pos } =>
+                    self.add(MessageCategory::Lock, Some(pos), "The door is locked.".to_string()),
+                GameEvent::DoorUnlocked { pos, picked } =>
+                    self.add(MessageCategory::Lock, Some(pos),
+                        if picked {"You pick the lock.".to_string()} else {"You unlock the door.".to_string()}),
+                GameEvent::LockpickInterrupted { pos } =>
+                    self.add(MessageCategory::Lock, Some(pos), "You stop picking the lock.".to_string()),
+                GameEvent::KeyCollected { pos, from_guard } =>
+                    self.add(MessageCategory::Lock, Some(pos),
+                        if from_guard {"You lift a key from the guard's belt.".to_string()} else {"You pick up a key.".to_string()}),
+                GameEvent::ToolCollected { pos, tool, count } =>
+                    self.add(MessageCategory::Tool, Some(pos), format!("You pick up {}.", tool.describe_count(count))),
+                GameEvent::ToolUsed { pos, tool } =>
+                    self.add(MessageCategory::Tool, Some(pos), format!("You throw {}.", tool.describe_count(1))),
+                GameEvent::GuardKnockedOut { pos } =>
+                    self.add(MessageCategory::Damage, Some(pos), "You knock the guard out cold.".to_string()),
+                GameEvent::KnockoutFailed { pos } =>
+                    self.add(MessageCategory::Damage, Some(pos), "The guard hears you coming!".to_string()),
+                GameEvent::BodyGrabbed { pos } =>
+                    self.add(MessageCategory::Tool, Some(pos), "You take hold of the guard.".to_string()),
+                GameEvent::BodyDropped { pos } =>
+                    self.add(MessageCategory::Tool, Some(pos), "You let go of the guard.".to_string()),
+                GameEvent::PlayerDamaged { text, .. } =>
+                    self.add(MessageCategory::Damage, None, format!("{} You are hit.", text)),
+                GameEvent::LevelCompleted =>
+                    self.add(MessageCategory::Level, None, "Level complete! Leave by any edge of the map.".to_string()),
+                GameEvent::PlayerDied =>
+                    self.add(MessageCategory::Level, None, "You have been killed.".to_string()),
+                _ => (),
+            }
+        }
+    }
+
+    // The last count messages, oldest first.
+
+    pub fn recent(&self, count: usize) -> &[Message] {
+        &self.messages[self.messages.len().saturating_sub(count)..]
+    }
+}
+
+fn light_name(kind: LightKind) -> &'static str {
+    match kind {
+        LightKind::Torch => "torch",
+        LightKind::Candle => "candle",
+    }
+}
+
+impl Default for MessageLog {
+    fn default() -> MessageLog {
+        MessageLog::new()
+    }
+}
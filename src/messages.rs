@@ -0,0 +1,81 @@
+use std::cmp::min;
+use quicksilver::{
+    geom::Rectangle,
+    graphics::{Background::Col, Color, Image},
+    lifecycle::Window,
+};
+use thiefrl3::color_preset;
+use thiefrl3::message_log::MessageLog;
+use crate::fontdata::LINE_HEIGHT;
+use crate::rgba;
+use crate::speech_bubbles::puts_proportional;
+
+const PANEL_LINES: usize = 4;
+const PANEL_TURNS: usize = 5;
+
+const BACKGROUND_COLOR: Color = Color { r: 0.0, g: 0.0, b: 0.0, a: 0.75 };
+const HISTORY_BACKGROUND_COLOR: Color = Color { r: 0.0, g: 0.0, b: 0.0, a: 0.875 };
+
+// Draws the most recent messages in a strip along the bottom of the view. Messages
+// drop off after a few turns so the panel isn't covering the map when things are quiet.
+
+pub fn draw_panel(window: &mut Window, font_image: &Image, log: &MessageLog, y_bottom: i32) {
+    let messages = log.recent(PANEL_LINES);
+    let first_current = messages.iter().position(|message| message.turn + PANEL_TURNS > log.turn).unwrap_or(messages.len());
+    let messages = &messages[first_current..];
+    if messages.is_empty() {
+        return;
+    }
+
+    let screen_size_x: i32 = window.screen_size().x as i32;
+    let y_top = y_bottom - messages.len() as i32 * LINE_HEIGHT;
+
+    window.draw(
+        &Rectangle::new((0, y_top), (screen_size_x, y_bottom - y_top)),
+        Col(BACKGROUND_COLOR),
+    );
+
+    let mut y = y_top;
+    for message in messages {
+        puts_proportional(window, font_image, 8, y, &message.text, &rgba(message.category.color()));
+        y += LINE_HEIGHT;
+    }
+}
+
+// Number of messages that fit on one page of the history view.
+
+pub fn history_page_lines(y_top: i32, y_bottom: i32) -> usize {
+    ((y_bottom - y_top) / LINE_HEIGHT - 2).max(1) as usize
+}
+
+// Draws a page of the full history. scroll is how many messages back from the
+// newest the bottom line of the page is; it is clamped to the length of the log.
+
+pub fn draw_history(window: &mut Window, font_image: &Image, log: &MessageLog, scroll: usize, y_top: i32, y_bottom: i32) {
+    let screen_size_x: i32 = window.screen_size().x as i32;
+
+    window.draw(
+        &Rectangle::new((0, y_top), (screen_size_x, y_bottom - y_top)),
+        Col(HISTORY_BACKGROUND_COLOR),
+    );
+
+    let x = 32;
+    let mut y = y_top + LINE_HEIGHT / 2;
+
+    puts_proportional(window, font_image, x, y, "Message History", &rgba(color_preset::LIGHT_YELLOW));
+    y += LINE_HEIGHT * 3 / 2;
+
+    if log.messages.is_empty() {
+        puts_proportional(window, font_image, x, y, "Nothing has happened yet.", &rgba(color_preset::LIGHT_GRAY));
+        return;
+    }
+
+    let page_lines = history_page_lines(y_top, y_bottom);
+    let end = log.messages.len() - min(scroll, log.messages.len() - 1);
+    let start = end.saturating_sub(page_lines);
+
+    for message in &log.messages[start..end] {
+        puts_proportional(window, font_image, x, y, &message.describe(), &rgba(message.category.color()));
+        y += LINE_HEIGHT;
+    }
+}
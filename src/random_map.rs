@@ -90,13 +90,17 @@ fn generate_siheyuan(rng: &mut MyRng, level: usize) -> Map {
     // Create exits connecting rooms.
 
     let mut map = Map {
-        cells: cells,
+        cells,
         items: Vec::new(),
+        lights: Vec::new(),
+        keys: Vec::new(),
         patrol_regions: Vec::new(),
         patrol_routes: Vec::new(),
         guards: Vec::new(),
         pos_start: Point::new(0, 0),
         total_loot: 0,
+        alarm_raised: false,
+        distance_fields: DistanceFieldCache::default(),
     };
 
     let (rooms, adjacencies, pos_start) = create_exits(
@@ -120,6 +124,14 @@ fn generate_siheyuan(rng: &mut MyRng, level: usize) -> Map {
     place_exterior_bushes(rng, &mut map);
     place_front_pillars(&mut map);
 
+    // Put a winch for the portcullises just inside one of the exits.
+
+    place_winch(rng, &mut map);
+
+    // Place light sources.
+
+    place_lights(rng, &rooms, &mut map);
+
     // Place guards.
 
 //  init_pathing(map);
@@ -130,6 +142,16 @@ fn generate_siheyuan(rng: &mut MyRng, level: usize) -> Map {
 
     cache_cell_info(&mut map);
 
+    // Lock some of the doors, and hide their keys or give them to guards.
+
+    lock_doors(rng, level, &rooms, &mut map);
+
+    // Leave some tools lying around.
+
+    place_tools(rng, level, &mut map);
+
+    map.compute_lighting();
+
     map.total_loot = map.items.iter().filter(|&item| item.kind == ItemKind::Coin).count();
 
     map
@@ -254,8 +276,8 @@ fn offset_walls(mirror_x: bool, mirror_y: bool, inside: &Array2D<bool>, rng: &mu
         }
     }
 
-    let mut room_offset_x = std::i32::MIN;
-    let mut room_offset_y = std::i32::MIN;
+    let mut room_offset_x = i32::MIN;
+    let mut room_offset_y = i32::MIN;
 
     for y in 0..rooms_y {
         room_offset_x = max(room_offset_x, -offset_x[[0, y]]);
@@ -305,19 +327,24 @@ fn plot_walls(inside: &Array2D<bool>, offset_x: &Array2D<i32>, offset_y: &Array2
         cell_type: CellType::GroundNormal,
         move_cost: 0,
         region: INVALID_REGION,
+        blocks_player: false,
         blocks_player_sight: false,
         blocks_sight: false,
         blocks_sound: false,
         hides_player: false,
+        smoke: 0,
+        moonlit: false,
         lit: false,
         seen: false,
     };
     let mut map = CellGrid::new([map_x as usize, map_y as usize], default_cell);
 
-    // Super hacky: put down grass under all the rooms to plug holes, and light the interior.
+    // Put down grass under all the rooms to plug holes. Courtyards are open to the moon.
 
     for rx in 0..cx {
         for ry in 0..cy {
+            let moonlit = !inside[[rx, ry]];
+
             let x0 = offset_x[[rx, ry]];
             let x1 = offset_x[[rx + 1, ry]] + 1;
             let y0 = offset_y[[rx, ry]];
@@ -327,7 +354,7 @@ fn plot_walls(inside: &Array2D<bool>, offset_x: &Array2D<i32>, offset_y: &Array2
                 for y in y0..y1 {
                     let cell = &mut map[[x as usize, y as usize]];
                     cell.cell_type = CellType::GroundGrass;
-                    cell.lit = true;
+                    cell.moonlit = moonlit;
                 }
             }
         }
@@ -378,11 +405,11 @@ fn plot_ew_wall(map: &mut CellGrid, x0: i32, y0: i32, x1: i32) {
     }
 }
 
-fn fixup_walls(map: &mut CellGrid) {
+pub fn fixup_walls(map: &mut CellGrid) {
     for x in 0..map.extents()[0] {
         for y in 0..map.extents()[1] {
             if is_wall(map[[x, y]].cell_type) {
-                map[[x, y]].cell_type = wall_type_from_neighbors(neighboring_walls(&map, x, y));
+                map[[x, y]].cell_type = wall_type_from_neighbors(neighboring_walls(map, x, y));
             }
         }
     }
@@ -410,7 +437,7 @@ fn wall_type_from_neighbors(neighbors: u32) -> CellType {
     }
 }
 
-fn is_wall(cell_type: CellType) -> bool {
+pub fn is_wall(cell_type: CellType) -> bool {
     match cell_type {
         CellType::GroundNormal     => false,
         CellType::GroundGrass      => false,
@@ -521,7 +548,7 @@ fn create_exits(
 
     // Compute a list of room adjacencies.
 
-    let mut adjacencies = compute_adjacencies(mirror_x, mirror_y, &inside, &offset_x, &offset_y, &room_index);
+    let mut adjacencies = compute_adjacencies(mirror_x, mirror_y, inside, offset_x, offset_y, &room_index);
     store_adjacencies_in_rooms(&adjacencies, &mut rooms);
 
     // Connect rooms together.
@@ -946,7 +973,7 @@ fn connect_rooms(rng: &mut MyRng, rooms: &mut [Room], adjacencies: &mut [Adjacen
 
     // Collect sets of edges that are mirrors of each other
 
-    let edge_sets = get_edge_sets(rng, &adjacencies);
+    let edge_sets = get_edge_sets(rng, adjacencies);
 
     // Connect all adjacent courtyard rooms together.
 
@@ -1493,7 +1520,7 @@ fn place_item(map: &mut Map, x: i32, y: i32, item_kind: ItemKind) {
     );
 }
 
-fn place_loot(rng: &mut MyRng, rooms: &Vec<Room>, adjacencies: &[Adjacency], map: &mut Map) {
+fn place_loot(rng: &mut MyRng, rooms: &[Room], adjacencies: &[Adjacency], map: &mut Map) {
 
     // Count number of internal rooms.
 
@@ -1557,6 +1584,11 @@ fn is_item_at_pos(map: &Map, x: i32, y: i32) -> bool {
             return true;
         }
     }
+    for key in &map.keys {
+        if key.pos.x == x && key.pos.y == y {
+            return true;
+        }
+    }
     return false;
 }
 
@@ -1574,7 +1606,7 @@ fn try_place_loot(rng: &mut MyRng, pos_min: Point, pos_max: Point, map: &mut Map
             continue;
         }
 
-        if is_item_at_pos(&map, pos.x, pos.y) {
+        if is_item_at_pos(map, pos.x, pos.y) {
             continue;
         }
 
@@ -1647,8 +1679,8 @@ fn place_front_pillars(map: &mut Map) {
     }
 }
 
-fn place_guards(rng: &mut MyRng, level: usize, rooms: &Vec<Room>, map: &mut Map) {
-    if level <= 0 {
+fn place_guards(rng: &mut MyRng, level: usize, rooms: &[Room], map: &mut Map) {
+    if level == 0 {
         return;
     }
 
@@ -1671,7 +1703,7 @@ fn place_guards(rng: &mut MyRng, level: usize, rooms: &Vec<Room>, map: &mut Map)
         };
 
     while num_guards > 0 {
-        match generate_initial_guard_pos(rng, &map) {
+        match generate_initial_guard_pos(rng, map) {
             None => break,
             Some(pos) => {
                 place_guard(rng, map, pos);
@@ -1698,7 +1730,7 @@ fn generate_initial_guard_pos(rng: &mut MyRng, map: &Map) -> Option<Point> {
             continue;
         }
 
-        if is_item_at_pos(&map, pos.x, pos.y) {
+        if is_item_at_pos(map, pos.x, pos.y) {
             continue;
         }
 
@@ -1709,22 +1741,7 @@ fn generate_initial_guard_pos(rng: &mut MyRng, map: &Map) -> Option<Point> {
 }
 
 fn place_guard(rng: &mut MyRng, map: &mut Map, pos: Point) {
-
-    let mut guard = Guard {
-        pos: pos,
-        dir: Point::new(1, 0),
-        mode: GuardMode::Patrol,
-        speaking: false,
-        has_moved: false,
-        heard_thief: false,
-        hearing_guard: false,
-        heard_guard: false,
-        heard_guard_pos: pos,
-        goal: pos,
-        mode_timeout: 0,
-        region_goal: INVALID_REGION,
-        region_prev: INVALID_REGION,
-    };
+    let mut guard = Guard::new(pos, Point::new(1, 0));
 
     guard.setup_goal_region(rng, map);
     guard.dir = guard.initial_dir(map);
@@ -1732,7 +1749,247 @@ fn place_guard(rng: &mut MyRng, map: &mut Map, pos: Point) {
     map.guards.push(guard);
 }
 
-fn mark_exterior_as_seen(map: &mut Map) {
+fn place_winch(rng: &mut MyRng, map: &mut Map) {
+    let mut candidates: Vec<Point> = Vec::new();
+
+    for item in &map.items {
+        if !is_portcullis(item.kind) {
+            continue;
+        }
+
+        let (dir_in, dir_along) =
+            if item.kind == ItemKind::PortcullisNS {
+                (Point::new(1, 0), Point::new(0, 1))
+            } else {
+                (Point::new(0, 1), Point::new(1, 0))
+            };
+
+        // The inside of the castle is whichever side isn't the plain exterior ground.
+
+        let pos_front = item.pos + dir_in;
+        let dir_in = if map.cells[[pos_front.x as usize, pos_front.y as usize]].cell_type == CellType::GroundNormal {-dir_in} else {dir_in};
+
+        for depth in 1..3 {
+            for &along in &[-2, -1, 1, 2] {
+                candidates.push(item.pos + dir_in * depth + dir_along * along);
+            }
+        }
+    }
+
+    candidates.retain(|&pos| {
+        let cell_type = map.cells[[pos.x as usize, pos.y as usize]].cell_type;
+        cell_type != CellType::GroundNormal && !is_wall(cell_type) && !is_item_at_pos(map, pos.x, pos.y)
+    });
+
+    if candidates.is_empty() {
+        return;
+    }
+
+    let pos = candidates[rng.gen_range(0, candidates.len())];
+    place_item(map, pos.x, pos.y, ItemKind::Winch);
+}
+
+fn place_lights(rng: &mut MyRng, rooms: &[Room], map: &mut Map) {
+
+    // Mount torches on the walls of indoor rooms. Master suites are always lit;
+    // other rooms may be left dark.
+
+    for room in rooms {
+        let num_torches = match room.room_type {
+            RoomType::MasterSuite => 2,
+            RoomType::Interior => rng.gen_range(0, 3),
+            _ => 0,
+        };
+
+        let mut candidates: Vec<(Point, Point)> = Vec::new();
+
+        for x in room.pos_min.x..room.pos_max.x {
+            candidates.push((Point::new(x, room.pos_min.y - 1), Point::new(0, 1)));
+            candidates.push((Point::new(x, room.pos_max.y), Point::new(0, -1)));
+        }
+
+        for y in room.pos_min.y..room.pos_max.y {
+            candidates.push((Point::new(room.pos_min.x - 1, y), Point::new(1, 0)));
+            candidates.push((Point::new(room.pos_max.x, y), Point::new(-1, 0)));
+        }
+
+        candidates.retain(|&(pos, dir)| {
+            let cell_type = map.cells[[pos.x as usize, pos.y as usize]].cell_type;
+            let pos_front = pos + dir;
+            cell_type >= CellType::Wall0001 && cell_type <= CellType::Wall1111 &&
+                !door_adjacent(&map.cells, pos_front.x, pos_front.y)
+        });
+
+        for _ in 0..num_torches {
+            if candidates.is_empty() {
+                break;
+            }
+
+            let (pos, dir) = candidates.swap_remove(rng.gen_range(0, candidates.len()));
+            map.lights.push(Light { pos, dir, kind: LightKind::Torch, lit: true });
+        }
+    }
+
+    // Put candles on some of the tables.
+
+    for item in &map.items {
+        if item.kind == ItemKind::Table && rng.gen_bool(1.0 / 3.0) {
+            map.lights.push(Light { pos: item.pos, dir: Point::new(0, 0), kind: LightKind::Candle, lit: true });
+        }
+    }
+}
+
+fn lock_doors(rng: &mut MyRng, level: usize, rooms: &[Room], map: &mut Map) {
+    if level == 0 {
+        return;
+    }
+
+    // Master suites are usually locked; other doors only occasionally.
+
+    let dirs = [Point::new(1, 0), Point::new(-1, 0), Point::new(0, 1), Point::new(0, -1)];
+
+    for i_item in 0..map.items.len() {
+        let item = &map.items[i_item];
+        if !is_closed_door(item.kind) {
+            continue;
+        }
+
+        let into_master_suite = rooms.iter().any(|room| {
+            room.room_type == RoomType::MasterSuite &&
+                dirs.iter().any(|&dir| {
+                    let pos = item.pos + dir;
+                    pos.x >= room.pos_min.x && pos.y >= room.pos_min.y && pos.x < room.pos_max.x && pos.y < room.pos_max.y
+                })
+        });
+
+        if rng.gen_bool(if into_master_suite {0.5} else {0.05}) {
+            map.set_door_locked(i_item, true);
+        }
+    }
+
+    // Keys are only put where the player can get to without going through any
+    // locked door, so no door's key is ever behind that door.
+
+    let reachable = reachable_without_keys(map);
+
+    let mut key_positions = free_positions_inside(map, &reachable);
+
+    let mut key_guards: Vec<usize> = (0..map.guards.len())
+        .filter(|&i_guard| {
+            let pos = map.guards[i_guard].pos;
+            reachable[[pos.x as usize, pos.y as usize]]
+        })
+        .collect();
+
+    for i_item in 0..map.items.len() {
+        if !is_locked_door(map.items[i_item].kind) {
+            continue;
+        }
+
+        let door = map.items[i_item].pos;
+
+        if !key_guards.is_empty() && rng.gen_bool(0.5) {
+            let i_guard = key_guards.swap_remove(rng.gen_range(0, key_guards.len()));
+            let guard = &mut map.guards[i_guard];
+            guard.key = Some(Key { pos: guard.pos, door });
+        } else if !key_positions.is_empty() {
+            let pos = key_positions.swap_remove(rng.gen_range(0, key_positions.len()));
+            map.keys.push(Key { pos, door });
+        } else {
+            map.set_door_locked(i_item, false);
+        }
+    }
+}
+
+// Scatter a few tools about for the player to find, in places they can reach
+// without needing any keys.
+
+fn place_tools(rng: &mut MyRng, level: usize, map: &mut Map) {
+    if level == 0 {
+        return;
+    }
+
+    let reachable = reachable_without_keys(map);
+
+    let mut positions = free_positions_inside(map, &reachable);
+
+    let num_stones = 2;
+    let num_smoke_bombs = rng.gen_range(0, 2);
+    let num_water_flasks = rng.gen_range(1, 3);
+
+    let kinds = std::iter::repeat_n(ItemKind::Stones, num_stones)
+        .chain(std::iter::repeat_n(ItemKind::SmokeBomb, num_smoke_bombs))
+        .chain(std::iter::repeat_n(ItemKind::WaterFlask, num_water_flasks));
+
+    for kind in kinds {
+        if positions.is_empty() {
+            break;
+        }
+
+        let pos = positions.swap_remove(rng.gen_range(0, positions.len()));
+        place_item(map, pos.x, pos.y, kind);
+    }
+}
+
+// Open, dry, empty cells inside the mansion walls that are marked reachable.
+
+fn free_positions_inside(map: &Map, reachable: &Array2D<bool>) -> Vec<Point> {
+    let mut positions: Vec<Point> = Vec::new();
+
+    for x in 0..map.cells.extents()[0] {
+        for y in 0..map.cells.extents()[1] {
+            let cell = &map.cells[[x, y]];
+            if reachable[[x, y]] && !cell.blocks_player &&
+                cell.cell_type != CellType::GroundNormal && cell.cell_type != CellType::GroundWater &&
+                !is_item_at_pos(map, x as i32, y as i32) {
+                positions.push(Point::new(x as i32, y as i32));
+            }
+        }
+    }
+
+    positions
+}
+
+// Flood out from the start through everything the player can walk through
+// without a key. One-way windows are treated as walls to stay on the safe side.
+
+fn reachable_without_keys(map: &Map) -> Array2D<bool> {
+    let size_x = map.cells.extents()[0] as i32;
+    let size_y = map.cells.extents()[1] as i32;
+
+    let mut reachable = Array2D::new([size_x as usize, size_y as usize], false);
+    let mut to_visit = vec![map.pos_start];
+    reachable[[map.pos_start.x as usize, map.pos_start.y as usize]] = true;
+
+    while let Some(pos) = to_visit.pop() {
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let pos_next = pos + Point::new(dx, dy);
+                if pos_next.x < 0 || pos_next.y < 0 || pos_next.x >= size_x || pos_next.y >= size_y {
+                    continue;
+                }
+
+                if reachable[[pos_next.x as usize, pos_next.y as usize]] {
+                    continue;
+                }
+
+                let cell = &map.cells[[pos_next.x as usize, pos_next.y as usize]];
+                if cell.blocks_player ||
+                    (cell.cell_type >= CellType::OneWayWindowE && cell.cell_type <= CellType::OneWayWindowS) ||
+                    map.locked_door_at(pos_next).is_some() {
+                    continue;
+                }
+
+                reachable[[pos_next.x as usize, pos_next.y as usize]] = true;
+                to_visit.push(pos_next);
+            }
+        }
+    }
+
+    reachable
+}
+
+pub fn mark_exterior_as_seen(map: &mut Map) {
     let sx = map.cells.extents()[0];
     let sy = map.cells.extents()[1];
 
@@ -1753,37 +2010,21 @@ fn mark_exterior_as_seen(map: &mut Map) {
     }
 }
 
-fn cache_cell_info(map: &mut Map) {
+pub fn cache_cell_info(map: &mut Map) {
     let sx = map.cells.extents()[0];
     let sy = map.cells.extents()[1];
 
     for x in 0..sx {
         for y in 0..sy {
-            let cell = &mut map.cells[[x, y]];
-            let cell_type = cell.cell_type;
-            let tile = tile_def(cell_type);
-            cell.move_cost = guard_move_cost_for_tile_type(cell_type);
-            cell.blocks_player_sight = tile.blocks_player_sight;
-            cell.blocks_sight = tile.blocks_sight;
-            cell.blocks_sound = tile.blocks_sound;
-            cell.hides_player = tile.hides_player;
+            cache_tile_info(&mut map.cells[[x, y]]);
         }
     }
 
     for item in &map.items {
-        let cell = &mut map.cells[[item.pos.x as usize, item.pos.y as usize]];
-        let kind = item.kind;
-        cell.move_cost = max(cell.move_cost, guard_move_cost_for_item_kind(kind));
-        if kind == ItemKind::DoorNS || kind == ItemKind::DoorEW {
-            cell.blocks_player_sight = true;
-        }
-        if kind == ItemKind::DoorNS || kind == ItemKind::DoorEW || kind == ItemKind::PortcullisNS || kind == ItemKind::PortcullisEW || kind == ItemKind::Bush {
-            cell.blocks_sight = true;
-        }
-        if kind == ItemKind::Table || kind == ItemKind::Bush {
-            cell.hides_player = true;
-        }
+        cache_item_info(&mut map.cells[[item.pos.x as usize, item.pos.y as usize]], item.kind);
     }
+
+    map.distance_fields.invalidate();
 }
 
 fn generate_patrol_routes(map: &mut Map, rooms: &[Room], adjacencies: &[Adjacency]) {
@@ -0,0 +1,313 @@
+use crate::game::{Action, GameEvent, GameState};
+use crate::save::{load_profile, Storage};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+
+// Bump this whenever simulation changes would make old replays play back differently.
+
+pub const REPLAY_VERSION: u32 = 14;
+
+pub const REPLAY_PROFILE: &str = "replay";
+
+#[derive(Deserialize, Serialize)]
+pub struct Replay {
+    pub version: u32,
+    pub seed: u64,
+    pub steps: Vec<ReplayStep>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ReplayStep {
+    pub turn: usize,
+    pub action: Action,
+    pub checksum: u64, // checksum of the game state after the action
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct IncompatibleReplay {
+    pub version_found: u32,
+    pub version_expected: u32,
+}
+
+impl fmt::Display for IncompatibleReplay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "replay has version {} but this build only plays version {}", self.version_found, self.version_expected)
+    }
+}
+
+impl std::error::Error for IncompatibleReplay {}
+
+impl Replay {
+    pub fn new(seed: u64) -> Replay {
+        Replay {
+            version: REPLAY_VERSION,
+            seed,
+            steps: Vec::new(),
+        }
+    }
+
+    pub fn check_version(&self) -> Result<(), IncompatibleReplay> {
+        if self.version == REPLAY_VERSION {
+            Ok(())
+        } else {
+            Err(IncompatibleReplay { version_found: self.version, version_expected: REPLAY_VERSION })
+        }
+    }
+
+    // Call after each step with the action that was taken and the resulting state.
+
+    pub fn record(&mut self, action: Action, game: &GameState) {
+        let turn = self.steps.len();
+        self.steps.push(ReplayStep { turn, action, checksum: game.checksum() });
+    }
+
+    // Whether this is the recording of the given game up to where it is now,
+    // rather than of some other game left over from an earlier save.
+
+    pub fn matches(&self, game: &GameState) -> bool {
+        self.seed == game.seed &&
+        self.steps.len() == game.num_steps &&
+        self.steps.last().is_none_or(|step| step.checksum == game.checksum())
+    }
+}
+
+// Why the last replay couldn't be loaded.
+
+#[derive(Debug)]
+pub enum LoadReplayError {
+    Read(io::Error),
+    Parse(serde_json::Error),
+    Incompatible(IncompatibleReplay),
+}
+
+impl fmt::Display for LoadReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadReplayError::Read(err) => write!(f, "could not load replay: {}", err),
+            LoadReplayError::Parse(err) => write!(f, "could not load replay: {}", err),
+            LoadReplayError::Incompatible(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for LoadReplayError {}
+
+// Returns None if no replay has been saved.
+
+pub fn load_replay(storage: &dyn Storage) -> Result<Option<Replay>, LoadReplayError> {
+    let text = match load_profile(storage, REPLAY_PROFILE).map_err(LoadReplayError::Read)? {
+        Some(text) => text,
+        None => return Ok(None),
+    };
+
+    let replay: Replay = serde_json::from_str(&text).map_err(LoadReplayError::Parse)?;
+    replay.check_version().map_err(LoadReplayError::Incompatible)?;
+    Ok(Some(replay))
+}
+
+pub fn save_replay(storage: &dyn Storage, replay: &Replay) -> io::Result<()> {
+    let text = serde_json::to_string(replay).map_err(io::Error::from)?;
+    storage.save(REPLAY_PROFILE, &text)
+}
+
+// Clear out the saved replay, so it isn't taken for the recording of a game
+// that isn't being recorded.
+
+pub fn clear_replay(storage: &dyn Storage) -> io::Result<()> {
+    storage.save(REPLAY_PROFILE, "")
+}
+
+// Re-simulates a replay one step at a time against a game created from the replay's seed.
+
+pub struct Playback {
+    pub replay: Replay,
+    pub next_step: usize,
+    pub desync_turn: Option<usize>,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Result<(Playback, GameState), IncompatibleReplay> {
+        replay.check_version()?;
+        let game = GameState::new(replay.seed);
+        let playback = Playback {
+            replay,
+            next_step: 0,
+            desync_turn: None,
+        };
+        Ok((playback, game))
+    }
+
+    pub fn finished(&self) -> bool {
+        self.next_step >= self.replay.steps.len()
+    }
+
+    pub fn num_steps(&self) -> usize {
+        self.replay.steps.len()
+    }
+
+    // Applies the next recorded action. Returns None once the replay has run out. The first
+    // step whose resulting state doesn't match the recorded checksum is noted in desync_turn.
+
+    pub fn step(&mut self, game: &mut GameState) -> Option<Vec<GameEvent>> {
+        let step = self.replay.steps.get(self.next_step)?;
+        self.next_step += 1;
+
+        let events = game.step(step.action);
+
+        if self.desync_turn.is_none() && game.checksum() != step.checksum {
+            self.desync_turn = Some(step.turn);
+        }
+
+        Some(events)
+    }
+}
+
+// FNV-1a; used instead of std's DefaultHasher because checksums must be stable across builds.
+
+struct Checksum(u64);
+
+impl Checksum {
+    fn new() -> Checksum {
+        Checksum(0xcbf29ce484222325)
+    }
+
+    fn add(&mut self, value: u64) {
+        for byte in value.to_le_bytes().iter() {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+impl GameState {
+    pub fn checksum(&self) -> u64 {
+        let mut sum = Checksum::new();
+
+        sum.add(self.level as u64);
+
+        let map = &self.map;
+        for x in 0..map.cells.extents()[0] {
+            for y in 0..map.cells.extents()[1] {
+                let cell = &map.cells[[x, y]];
+                sum.add(cell.cell_type as u64);
+                sum.add(cell.lit as u64 | (cell.seen as u64) << 1);
+                sum.add(cell.smoke as u64);
+            }
+        }
+
+        for item in &map.items {
+            sum.add(item.pos.x as u64);
+            sum.add(item.pos.y as u64);
+            sum.add(item.kind as u64);
+        }
+
+        for light in &map.lights {
+            sum.add(light.lit as u64);
+        }
+
+        for guard in &map.guards {
+            sum.add(guard.pos.x as u64);
+            sum.add(guard.pos.y as u64);
+            sum.add(guard.dir.x as u64);
+            sum.add(guard.dir.y as u64);
+            sum.add(guard.mode as u64);
+            sum.add(guard.mode_timeout as u64);
+            sum.add(guard.heard_thief_pos.x as u64);
+            sum.add(guard.heard_thief_pos.y as u64);
+            sum.add(guard.region_goal as u64);
+        }
+
+        let player = &self.player;
+        sum.add(player.pos.x as u64);
+        sum.add(player.pos.y as u64);
+        sum.add(player.health as u64);
+        sum.add(player.gold as u64);
+        sum.add(player.turns_remaining_underwater as u64);
+        sum.add(player.keys.len() as u64);
+        for tool in &crate::inventory::TOOL_KINDS {
+            sum.add(player.inventory.count(*tool) as u64);
+        }
+        sum.add(player.lockpick_turns as u64);
+        sum.add(player.dragging as u64);
+        sum.add(player.drag_pos.x as u64);
+        sum.add(player.drag_pos.y as u64);
+
+        sum.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell_grid::{MyRng, Point};
+    use rand::{Rng, SeedableRng};
+
+    // Wander about at random, recording as the frontends do.
+
+    fn record_game(seed: u64, num_steps: usize) -> (Replay, GameState) {
+        let mut rng = MyRng::seed_from_u64(seed);
+        let mut game = GameState::new(seed);
+        let mut replay = Replay::new(seed);
+
+        for _ in 0..num_steps {
+            let dir = Point::new(rng.gen_range(-1, 2), rng.gen_range(-1, 2));
+            let action = match rng.gen_range(0, 10) {
+                0 => Action::Wait,
+                1 => Action::DouseLight,
+                2 => Action::ToggleDoor(dir),
+                _ => Action::Move(dir),
+            };
+            game.step(action);
+            replay.record(action, &game);
+        }
+
+        (replay, game)
+    }
+
+    #[test]
+    fn playback_matches_recording() {
+        for seed in 0..4 {
+            let (replay, game_recorded) = record_game(seed, 400);
+            let (mut playback, mut game) = Playback::new(replay).unwrap();
+
+            while playback.step(&mut game).is_some() {}
+
+            assert!(playback.finished());
+            assert_eq!(playback.desync_turn, None);
+            assert_eq!(game.checksum(), game_recorded.checksum());
+            assert_eq!(game.checksum(), playback.replay.steps.last().unwrap().checksum);
+        }
+    }
+
+    #[test]
+    fn playback_notes_first_desync() {
+        let (mut replay, _) = record_game(7, 50);
+        replay.steps[20].checksum ^= 1;
+        replay.steps[30].checksum ^= 1;
+        let (mut playback, mut game) = Playback::new(replay).unwrap();
+
+        while playback.step(&mut game).is_some() {}
+
+        assert_eq!(playback.desync_turn, Some(20));
+    }
+
+    #[test]
+    fn replay_only_matches_its_own_game() {
+        let (replay, mut game) = record_game(3, 40);
+        assert!(replay.matches(&game));
+
+        // The autosave got ahead of the replay.
+
+        game.step(Action::Wait);
+        assert!(!replay.matches(&game));
+
+        // A different game that happens to be as far along.
+
+        let (_, other_game) = record_game(4, 40);
+        assert!(!replay.matches(&other_game));
+
+        assert!(Replay::new(5).matches(&GameState::new(5)));
+        assert!(!Replay::new(5).matches(&GameState::new(6)));
+    }
+}
@@ -0,0 +1,188 @@
+use crate::cell_grid::Point;
+use crate::game::GameState;
+use multiarray::Array2D;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error;
+use std::fmt;
+use std::io;
+
+// Bump this whenever the layout of anything reachable from GameState changes.
+
+pub const SAVE_VERSION: u32 = 14;
+
+pub const APP_NAME: &str = "thiefrl3";
+pub const SAVE_PROFILE: &str = "autosave";
+
+// Only the version is read from this; the rest of the file is ignored so that
+// saves of any layout can be checked before attempting a full load.
+
+#[derive(Deserialize)]
+pub struct SaveHeader {
+    pub version: u32,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SaveGame<G> {
+    pub version: u32,
+    pub game: G,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct IncompatibleSave {
+    pub version_found: u32,
+    pub version_expected: u32,
+}
+
+impl fmt::Display for IncompatibleSave {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "saved game has version {} but this build only reads version {}", self.version_found, self.version_expected)
+    }
+}
+
+impl std::error::Error for IncompatibleSave {}
+
+impl SaveHeader {
+    pub fn check_version(&self) -> Result<(), IncompatibleSave> {
+        if self.version == SAVE_VERSION {
+            Ok(())
+        } else {
+            Err(IncompatibleSave { version_found: self.version, version_expected: SAVE_VERSION })
+        }
+    }
+}
+
+impl GameState {
+    pub fn to_save(&self) -> SaveGame<&GameState> {
+        SaveGame { version: SAVE_VERSION, game: self }
+    }
+
+    pub fn from_save(save: SaveGame<GameState>) -> Result<GameState, IncompatibleSave> {
+        SaveHeader { version: save.version }.check_version()?;
+        Ok(save.game)
+    }
+}
+
+// Where saves and replays are kept, under the names SAVE_PROFILE and
+// REPLAY_PROFILE. Each frontend brings its own, so the library doesn't tie
+// either of them to a way of reaching the disk. Everything is stored as JSON
+// text; a profile that is missing or empty has nothing saved in it, and saving
+// an empty string is how one is cleared.
+
+pub trait Storage {
+    fn load(&self, profile: &str) -> io::Result<Option<String>>;
+    fn save(&self, profile: &str, text: &str) -> io::Result<()>;
+}
+
+// Read a profile, treating an empty one as missing.
+
+pub fn load_profile(storage: &dyn Storage, profile: &str) -> io::Result<Option<String>> {
+    Ok(storage.load(profile)?.filter(|text| !text.is_empty()))
+}
+
+// Why the autosave couldn't be continued. Having no autosave at all isn't an
+// error; there is just nothing to continue.
+
+#[derive(Debug)]
+pub enum LoadGameError {
+    Read(io::Error),
+    Parse(serde_json::Error),
+    Incompatible(IncompatibleSave),
+}
+
+impl fmt::Display for LoadGameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadGameError::Read(err) => write!(f, "Could not load saved game: {}", err),
+            LoadGameError::Parse(err) => write!(f, "Could not load saved game: {}", err),
+            LoadGameError::Incompatible(err) => write!(f, "Discarding saved game: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for LoadGameError {}
+
+pub fn load_saved_game(storage: &dyn Storage) -> Result<Option<GameState>, LoadGameError> {
+    let text = match load_profile(storage, SAVE_PROFILE).map_err(LoadGameError::Read)? {
+        Some(text) => text,
+        None => return Ok(None),
+    };
+
+    // Check the version before loading the rest so that old saves get a clear message
+    // rather than a deserialization error.
+
+    let header: SaveHeader = serde_json::from_str(&text).map_err(LoadGameError::Parse)?;
+    header.check_version().map_err(LoadGameError::Incompatible)?;
+
+    let saved: SaveGame<GameState> = serde_json::from_str(&text).map_err(LoadGameError::Parse)?;
+    GameState::from_save(saved).map(Some).map_err(LoadGameError::Incompatible)
+}
+
+pub fn save_game(storage: &dyn Storage, game: &GameState) -> io::Result<()> {
+    let text = serde_json::to_string(&game.to_save()).map_err(io::Error::from)?;
+    storage.save(SAVE_PROFILE, &text)
+}
+
+// Point (vector2d::Vector2D<i32>) does not implement serde traits, so it is written as an (x, y) pair.
+
+pub mod point {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(pos: &Point, serializer: S) -> Result<S::Ok, S::Error> {
+        (pos.x, pos.y).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Point, D::Error> {
+        let (x, y) = <(i32, i32)>::deserialize(deserializer)?;
+        Ok(Point::new(x, y))
+    }
+}
+
+// Array2D is written as its extents followed by its elements in x-major order.
+
+pub mod array_2d {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Grid<'a, T> {
+        size_x: usize,
+        size_y: usize,
+        elements: Vec<&'a T>,
+    }
+
+    #[derive(Deserialize)]
+    struct GridOwned<T> {
+        size_x: usize,
+        size_y: usize,
+        elements: Vec<T>,
+    }
+
+    pub fn serialize<S: Serializer, T: Serialize>(grid: &Array2D<T>, serializer: S) -> Result<S::Ok, S::Error> {
+        let size_x = grid.extents()[0];
+        let size_y = grid.extents()[1];
+        let mut elements = Vec::with_capacity(size_x * size_y);
+        for x in 0..size_x {
+            for y in 0..size_y {
+                elements.push(&grid[[x, y]]);
+            }
+        }
+        Grid { size_x, size_y, elements }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de> + Clone>(deserializer: D) -> Result<Array2D<T>, D::Error> {
+        let grid = GridOwned::<T>::deserialize(deserializer)?;
+        if grid.size_x == 0 || grid.size_y == 0 || grid.elements.len() != grid.size_x * grid.size_y {
+            return Err(D::Error::custom(format!("grid of size {}x{} has {} elements", grid.size_x, grid.size_y, grid.elements.len())));
+        }
+        let mut elements = grid.elements.into_iter();
+        let mut array = Array2D::new([grid.size_x, grid.size_y], elements.next().unwrap());
+        for x in 0..grid.size_x {
+            for y in 0..grid.size_y {
+                if x == 0 && y == 0 {
+                    continue;
+                }
+                array[[x, y]] = elements.next().unwrap();
+            }
+        }
+        Ok(array)
+    }
+}
@@ -0,0 +1,269 @@
+use crate::campaign::{self, LevelFileError};
+use crate::cell_grid::{Map, Point};
+use crate::game::{Action, GameEvent, GameState};
+use crate::inventory::ToolKind;
+use crate::message_log::MessageLog;
+use crate::replay::{self, Replay};
+use crate::save::{self, Storage};
+use std::path::Path;
+
+// The part of a frontend that doesn't care how the game is drawn or how keys
+// are read: the game being played and its recording, the actions waiting on
+// more input, and the messages the turns have produced. The graphical and
+// terminal frontends each wrap one of these.
+
+pub struct Session {
+    pub storage: Box<dyn Storage>, // where the frontend keeps saves and replays
+    pub game: GameState,
+    pub replay: Option<Replay>, // recording of the current game, if it has one
+    pub direction_prompt: Option<DirectionPrompt>, // action waiting for a direction, if any
+    pub targeting: Option<Targeting>, // tool being aimed, if any
+    pub message_log: MessageLog,
+    pub errors: Vec<String>, // problems saving, for the frontend to report when it can
+}
+
+// Actions that ask for a direction before they're performed.
+
+#[derive(Clone, Copy)]
+pub enum DirectionPrompt {
+    ToggleDoor,
+    PickLock,
+    Knockout,
+    Drag,
+}
+
+impl DirectionPrompt {
+    pub fn action(self, dir: Point) -> Action {
+        match self {
+            DirectionPrompt::ToggleDoor => Action::ToggleDoor(dir),
+            DirectionPrompt::PickLock => Action::PickLock(dir),
+            DirectionPrompt::Knockout => Action::Knockout(dir),
+            DirectionPrompt::Drag => Action::Drag(dir),
+        }
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            DirectionPrompt::ToggleDoor => "Open or close the door in which direction?",
+            DirectionPrompt::PickLock => "Pick the lock in which direction?",
+            DirectionPrompt::Knockout => "Knock out the guard in which direction?",
+            DirectionPrompt::Drag => "Drag the body in which direction?",
+        }
+    }
+}
+
+// A cursor for picking the cell to throw a tool at. It starts on the player
+// and is moved a cell at a time with the movement keys.
+
+#[derive(Clone, Copy)]
+pub struct Targeting {
+    pub tool: ToolKind,
+    pub pos: Point,
+}
+
+impl Targeting {
+    pub fn new(tool: ToolKind, pos: Point) -> Targeting {
+        Targeting { tool, pos }
+    }
+
+    pub fn move_cursor(&mut self, map: &Map, dir: Point) {
+        let pos = self.pos + dir;
+        if pos.x >= 0 && pos.y >= 0 && pos.x < map.cells.extents()[0] as i32 && pos.y < map.cells.extents()[1] as i32 {
+            self.pos = pos;
+        }
+    }
+
+    pub fn message(&self) -> String {
+        format!("Throw {} where? Move the cursor, then press Enter (Escape to cancel)", self.tool.describe_count(1))
+    }
+}
+
+pub const HISTORY_STATUS_MESSAGE: &str = "Press up/down arrow keys or Page Up/Page Down to scroll, or M to close";
+
+impl Session {
+
+// Continue the autosaved game if there is one, or else start a new one.
+// Problems loading are reported on stderr, before any frontend is up.
+
+pub fn continue_or_new(storage: Box<dyn Storage>) -> Session {
+    let saved_game = save::load_saved_game(storage.as_ref()).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        None
+    });
+
+    match saved_game {
+        Some(game) => {
+            let replay = match replay::load_replay(storage.as_ref()) {
+                Ok(Some(replay)) if replay.matches(&game) => Some(replay),
+                Ok(Some(_)) => {
+                    eprintln!("Not recording this game; the saved replay is of a different game");
+                    None
+                },
+                Ok(None) => {
+                    eprintln!("Not recording this game; no replay was saved with it");
+                    None
+                },
+                Err(err) => {
+                    eprintln!("Not recording this game; {}", err);
+                    None
+                },
+            };
+            Session::new(storage, game, replay)
+        },
+        None => {
+            let seed = rand::random::<u64>();
+            Session::new(storage, GameState::new(seed), Some(Replay::new(seed)))
+        }
+    }
+}
+
+// Start a game on a level file, to try it out. It isn't recorded, as replays
+// only know how to make the campaign's levels, so the last game's replay is
+// cleared rather than left to be played back as this one's.
+
+pub fn on_level_file(storage: Box<dyn Storage>, path: &Path) -> Result<Session, LevelFileError> {
+    let text = campaign::load_level_file(path)?;
+    let seed = rand::random::<u64>();
+    let mut session = Session::new(storage, GameState::with_level_file(seed, Some(text)), None);
+    session.save_replay();
+    Ok(session)
+}
+
+pub fn new(storage: Box<dyn Storage>, game: GameState, replay: Option<Replay>) -> Session {
+    Session {
+        storage,
+        game,
+        replay,
+        direction_prompt: None,
+        targeting: None,
+        message_log: MessageLog::new(),
+        errors: Vec::new(),
+    }
+}
+
+pub fn new_game(&mut self) {
+    let seed = rand::random::<u64>();
+    self.game = GameState::new(seed);
+    self.replay = Some(Replay::new(seed));
+    self.direction_prompt = None;
+    self.targeting = None;
+    self.message_log.clear();
+}
+
+pub fn save_game(&mut self) {
+    if let Err(err) = save::save_game(self.storage.as_ref(), &self.game) {
+        self.errors.push(format!("Could not save game: {}", err));
+    }
+    self.save_replay();
+}
+
+// Save the replay alongside the game. A game that isn't being recorded clears
+// the saved replay instead, so that the one on disk always goes with the
+// autosave.
+
+pub fn save_replay(&mut self) {
+    let result = match &self.replay {
+        Some(replay) => replay::save_replay(self.storage.as_ref(), replay),
+        None => replay::clear_replay(self.storage.as_ref()),
+    };
+
+    if let Err(err) = result {
+        self.errors.push(format!("Could not save replay: {}", err));
+    }
+}
+
+// Take a turn, recording it. The replay is saved whenever the level ends, so
+// that it can be watched even if the game is never quit properly.
+
+pub fn perform(&mut self, action: Action) -> Vec<GameEvent> {
+    let events = self.game.step(action);
+
+    if let Some(replay) = &mut self.replay {
+        replay.record(action, &self.game);
+    }
+
+    self.message_log.add_events(&events);
+
+    if events.iter().any(|event| matches!(event, GameEvent::LevelCompleted | GameEvent::PlayerDied)) {
+        self.save_replay();
+    }
+
+    events
+}
+
+// Ask which way to do something. Letting go of a body needs no direction.
+
+pub fn prompt_for_direction(&mut self, prompt: DirectionPrompt) -> Vec<GameEvent> {
+    if let DirectionPrompt::Drag = prompt {
+        if self.game.player.dragging {
+            return self.perform(Action::Drag(Point::new(0, 0)));
+        }
+    }
+
+    self.direction_prompt = Some(prompt);
+    Vec::new()
+}
+
+// Answer the direction prompt. Anything but a direction cancels it.
+
+pub fn answer_direction_prompt(&mut self, dir: Option<Point>) -> Vec<GameEvent> {
+    match (self.direction_prompt.take(), dir) {
+        (Some(prompt), Some(dir)) => self.perform(prompt.action(dir)),
+        _ => Vec::new(),
+    }
+}
+
+pub fn start_targeting(&mut self, tool: ToolKind) {
+    let player = &self.game.player;
+    if player.health > 0 && player.inventory.count(tool) > 0 {
+        self.targeting = Some(Targeting::new(tool, player.pos));
+    }
+}
+
+pub fn move_target(&mut self, dir: Point) {
+    if let Some(targeting) = &mut self.targeting {
+        targeting.move_cursor(&self.game.map, dir);
+    }
+}
+
+// The throw is only made if it can reach the target.
+
+pub fn throw_at_target(&mut self) -> Vec<GameEvent> {
+    match self.targeting {
+        Some(targeting) if self.game.map.can_throw_to(self.game.player.pos, targeting.pos) => {
+            self.targeting = None;
+            self.perform(Action::UseTool { tool: targeting.tool, target: targeting.pos })
+        },
+        _ => Vec::new(),
+    }
+}
+
+// What the top line says while no other screen is up: the question being
+// asked, if any, and otherwise how the game is going.
+
+pub fn status_message(&self) -> String {
+    if let Some(targeting) = self.targeting {
+        return targeting.message();
+    }
+
+    if let Some(prompt) = self.direction_prompt {
+        return prompt.message().to_string();
+    }
+
+    let player = &self.game.player;
+    let level = self.game.level;
+
+    if player.game_over || player.health == 0 {
+        "You are dead! Press Ctrl+N for a new game or Ctrl+R to restart.".to_string()
+    } else if player.finished_level {
+        format!("Level {} complete! Move off the edge of the map to advance to the next level.", level + 1)
+    } else if level == 0 {
+        format!("Welcome to level {}. Collect the gold coins and reveal the whole mansion. (Press F1 for help.)", level + 1)
+    } else if level == 1 {
+        format!("Welcome to level {}. Watch out for the patrolling guard! (Press F1 for help.)", level + 1)
+    } else {
+        "Press F1 for help".to_string()
+    }
+}
+
+}
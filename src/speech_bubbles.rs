@@ -1,28 +1,187 @@
 use std::cmp::{min, max};
 use quicksilver::{
-    geom::Rectangle,
-    graphics::{Background::Blended, Color, Image},
+    geom::{Rectangle, Triangle},
+    graphics::{Background::{Blended, Col}, Color, Image},
     lifecycle::Window,
 };
 use crate::fontdata::{Glyph, GLYPH, LINE_HEIGHT};
-use crate::cell_grid::Point;
+use crate::rgba;
+use thiefrl3::cell_grid::{Point, Rect};
+use thiefrl3::color_preset;
 
-pub fn guard_speech(pos: Point, s: &str) {
+// Text bubbles anchored to map positions: what guards say, the noises the player
+// makes, and narration that isn't attached to anything. They are queued as events
+// come in, laid out once the screen positions are known, and cleared each turn.
+
+const BUBBLE_PAD_X: i32 = 4;
+const BUBBLE_HEIGHT: i32 = LINE_HEIGHT - 4;
+const BUBBLE_SPACING: i32 = 2;
+const TAIL_LENGTH: i32 = 6;
+const TAIL_HALF_WIDTH: i32 = 3;
+
+const BUBBLE_BACKGROUND_COLOR: Color = Color { r: 0.0625, g: 0.0625, b: 0.0625, a: 0.875 };
+
+#[derive(Clone, Copy, PartialEq)]
+enum BubbleKind {
+    GuardSpeech,
+    GuardShout,
+    Noise,
+    Narration,
+}
+
+struct Bubble {
+    kind: BubbleKind,
+    pos: Point, // map cell the bubble points at; unused for narration
+    text: String,
+    rect: Rect, // screen rectangle in pixels, set by layout
+    tail: Option<Point>, // screen position the tail points at, if it has one
+}
+
+pub struct SpeechBubbles {
+    bubbles: Vec<Bubble>,
+}
+
+impl SpeechBubbles {
+
+pub fn new() -> SpeechBubbles {
+    SpeechBubbles { bubbles: Vec::new() }
+}
+
+pub fn guard_speech(&mut self, pos: Point, s: &str) {
+    self.add(BubbleKind::GuardSpeech, pos, s);
+}
+
+pub fn guard_shout(&mut self, pos: Point, s: &str) {
+    self.add(BubbleKind::GuardShout, pos, s);
+}
+
+pub fn noise(&mut self, pos: Point, s: &str) {
+    self.add(BubbleKind::Noise, pos, s);
+}
+
+pub fn narration(&mut self, s: &str) {
+    self.add(BubbleKind::Narration, Point::new(0, 0), s);
+}
+
+pub fn clear(&mut self) {
+    self.bubbles.clear();
+}
+
+fn add(&mut self, kind: BubbleKind, pos: Point, s: &str) {
+    let empty = Rect { pos_min: Point::new(0, 0), pos_max: Point::new(0, 0) };
+    self.bubbles.push(Bubble { kind, pos, text: s.to_string(), rect: empty, tail: None });
+}
+
+// Assign screen rectangles to the bubbles. view_min and view_max bound the area
+// (in pixels) they must stay inside; focus is the map cell they should avoid
+// covering (the player); and cell_px maps a map cell to the pixel position of
+// its top-left corner.
+//
+// Narration is stacked down from the top of the view. Each of the other bubbles
+// tries positions above its cell, then below it, moving further away each time,
+// and takes the first that stays in view without covering a bubble placed before
+// it. If none is free it falls back to the spot directly above the cell.
+
+pub fn layout<F: Fn(Point) -> Point>(&mut self, view_min: Point, view_max: Point, focus: Point, cell_px: F) {
+    let cell_size = crate::TILE_SIZE.x as i32;
+    let focus_min = cell_px(focus);
+    let focus_rect = Rect { pos_min: focus_min, pos_max: focus_min + Point::new(cell_size, cell_size) };
+
+    let mut placed: Vec<Rect> = Vec::with_capacity(self.bubbles.len());
+    let mut y_narration = view_min.y + BUBBLE_SPACING;
+
+    for bubble in self.bubbles.iter_mut().filter(|bubble| bubble.kind == BubbleKind::Narration) {
+        let size_x = bubble_width(&bubble.text);
+        let x = (view_min.x + view_max.x - size_x) / 2;
+        bubble.rect = clamp_to_view(bubble_rect(x, y_narration, size_x), view_min, view_max);
+        bubble.tail = None;
+        placed.push(bubble.rect);
+        y_narration += BUBBLE_HEIGHT + BUBBLE_SPACING;
+    }
+
+    for bubble in self.bubbles.iter_mut().filter(|bubble| bubble.kind != BubbleKind::Narration) {
+        let size_x = bubble_width(&bubble.text);
+        let cell_min = cell_px(bubble.pos);
+        let x_center = cell_min.x + cell_size / 2;
+        let x = x_center - size_x / 2;
+
+        let y_above = cell_min.y - (TAIL_LENGTH + BUBBLE_HEIGHT);
+        let y_below = cell_min.y + cell_size + TAIL_LENGTH;
+        let step = BUBBLE_HEIGHT + BUBBLE_SPACING;
+
+        let mut candidates = Vec::with_capacity(6);
+        for i in 0..3 {
+            candidates.push((clamp_to_view(bubble_rect(x, y_above - i * step, size_x), view_min, view_max), cell_min.y));
+            candidates.push((clamp_to_view(bubble_rect(x, y_below + i * step, size_x), view_min, view_max), cell_min.y + cell_size));
+        }
+
+        let (rect, y_tail) = *candidates.iter()
+            .find(|(rect, _)| {
+                !overlaps(rect, &focus_rect) && !placed.iter().any(|other| overlaps(rect, other))
+            })
+            .unwrap_or(&candidates[0]);
+
+        bubble.rect = rect;
+        bubble.tail = Some(Point::new(x_center, y_tail));
+        placed.push(rect);
+    }
+}
+
+pub fn draw(&self, window: &mut Window, font_image: &Image) {
+    for bubble in &self.bubbles {
+        let rect = &bubble.rect;
+
+        window.draw(
+            &Rectangle::new((rect.pos_min.x, rect.pos_min.y), (rect.pos_max.x - rect.pos_min.x, rect.pos_max.y - rect.pos_min.y)),
+            Col(BUBBLE_BACKGROUND_COLOR),
+        );
+
+        if let Some(tip) = bubble.tail {
+            // The tail leaves from whichever edge of the box faces its tip, as
+            // close to straight above or below the tip as the box allows.
+
+            let x = max(rect.pos_min.x + TAIL_HALF_WIDTH, min(rect.pos_max.x - TAIL_HALF_WIDTH, tip.x));
+            let y = if tip.y >= rect.pos_max.y {rect.pos_max.y} else {rect.pos_min.y};
+
+            window.draw(
+                &Triangle::new((x - TAIL_HALF_WIDTH, y), (x + TAIL_HALF_WIDTH, y), (tip.x, tip.y)),
+                Col(BUBBLE_BACKGROUND_COLOR),
+            );
+        }
+
+        let color = match bubble.kind {
+            BubbleKind::GuardSpeech => rgba(color_preset::LIGHT_MAGENTA),
+            BubbleKind::GuardShout => rgba(color_preset::LIGHT_RED),
+            BubbleKind::Noise => rgba(color_preset::LIGHT_CYAN),
+            BubbleKind::Narration => rgba(color_preset::WHITE),
+        };
+
+        let (x_min, _) = get_horizontal_extents(&bubble.text);
+        puts_proportional(window, font_image, rect.pos_min.x + BUBBLE_PAD_X - x_min, rect.pos_min.y - 3, &bubble.text, &color);
+    }
 }
 
-pub fn noise(pos: Point, s: &str) {
 }
 
-pub fn narration(s: &str) {
+fn bubble_width(s: &str) -> i32 {
+    let (x_min, x_max) = get_horizontal_extents(s);
+    max(0, x_max - x_min) + 2 * BUBBLE_PAD_X
 }
 
-pub fn clear() {
+fn bubble_rect(x: i32, y: i32, size_x: i32) -> Rect {
+    Rect { pos_min: Point::new(x, y), pos_max: Point::new(x + size_x, y + BUBBLE_HEIGHT) }
 }
 
-pub fn layout(view_min: Point, view_max: Point, focus: Point) {
+fn clamp_to_view(rect: Rect, view_min: Point, view_max: Point) -> Rect {
+    let size = rect.pos_max - rect.pos_min;
+    let x = max(view_min.x, min(view_max.x - size.x, rect.pos_min.x));
+    let y = max(view_min.y, min(view_max.y - size.y, rect.pos_min.y));
+    Rect { pos_min: Point::new(x, y), pos_max: Point::new(x, y) + size }
 }
 
-pub fn draw(/* viewport: &Viewport */) {
+fn overlaps(a: &Rect, b: &Rect) -> bool {
+    a.pos_min.x < b.pos_max.x && b.pos_min.x < a.pos_max.x &&
+    a.pos_min.y < b.pos_max.y && b.pos_min.y < a.pos_max.y
 }
 
 pub fn glyph_lookup(c: char) -> Option<&'static Glyph> {
@@ -31,8 +190,8 @@ pub fn glyph_lookup(c: char) -> Option<&'static Glyph> {
 }
 
 pub fn get_horizontal_extents(s: &str) -> (i32, i32) {
-	let mut x_min = std::i32::MAX;
-	let mut x_max = std::i32::MIN;
+	let mut x_min = i32::MAX;
+	let mut x_max = i32::MIN;
 	let mut x = 0;
 
     for c in s.chars() {
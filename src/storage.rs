@@ -0,0 +1,30 @@
+use quicksilver::saving::{load_raw, save_raw, SaveError};
+use std::io;
+use thiefrl3::save::{Storage, APP_NAME};
+
+// Saves go wherever quicksilver keeps them: a file per profile under the
+// user's data directory on the desktop, or local storage in the browser.
+
+pub struct QuicksilverStorage;
+
+impl Storage for QuicksilverStorage {
+    fn load(&self, profile: &str) -> io::Result<Option<String>> {
+        match load_raw(APP_NAME, profile) {
+            Ok(data) => String::from_utf8(data).map(Some).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(SaveError::IOError(ref err)) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(SaveError::SaveNotFound(_)) => Ok(None),
+            Err(err) => Err(io_error(err)),
+        }
+    }
+
+    fn save(&self, profile: &str, text: &str) -> io::Result<()> {
+        save_raw(APP_NAME, profile, text.as_bytes()).map_err(io_error)
+    }
+}
+
+fn io_error(err: SaveError) -> io::Error {
+    match err {
+        SaveError::IOError(err) => err,
+        err => io::Error::other(err.to_string()),
+    }
+}
@@ -0,0 +1,36 @@
+use quicksilver::{
+    geom::{Rectangle, Vector},
+    graphics::{Background::Col, Color},
+    lifecycle::Window,
+};
+use thiefrl3::cell_grid::{Map, Point};
+use thiefrl3::color_preset;
+use thiefrl3::session::Targeting;
+use crate::rgba;
+use crate::TILE_SIZE;
+
+// Outline the target cell: green if the throw can reach it, red if not.
+
+pub fn draw_targeting(window: &mut Window, targeting: &Targeting, map: &Map, pos_player: Point, offset_px: Vector) {
+    let map_size_y = map.cells.extents()[1] as i32;
+    let pos = Vector::new(targeting.pos.x, (map_size_y - 1) - targeting.pos.y);
+    let pos_px = offset_px + pos.times(TILE_SIZE);
+
+    let color =
+        if map.can_throw_to(pos_player, targeting.pos) {
+            rgba(color_preset::LIGHT_GREEN)
+        } else {
+            rgba(color_preset::LIGHT_RED)
+        };
+
+    draw_outline(window, pos_px, TILE_SIZE, color);
+}
+
+fn draw_outline(window: &mut Window, pos: Vector, size: Vector, color: Color) {
+    const THICKNESS: f32 = 2.0;
+
+    window.draw(&Rectangle::new(pos, Vector::new(size.x, THICKNESS)), Col(color));
+    window.draw(&Rectangle::new(pos + Vector::new(0.0, size.y - THICKNESS), Vector::new(size.x, THICKNESS)), Col(color));
+    window.draw(&Rectangle::new(pos, Vector::new(THICKNESS, size.y)), Col(color));
+    window.draw(&Rectangle::new(pos + Vector::new(size.x - THICKNESS, 0.0), Vector::new(THICKNESS, size.y)), Col(color));
+}
@@ -0,0 +1,888 @@
+// Export to and import from the JSON map format of the Tiled editor, so that a
+// generated level can be touched up by hand and brought back into the game.
+//
+// The map uses the game's own tileset, tiles.png, which it expects to find next
+// to the exported file. Two tile layers hold what the game draws: "cells" has
+// each cell's tile from tile_def, and "items" has the tile of whatever sits on
+// it, from glyph_for_item. Everything else goes in object layers:
+//
+//     guards           guard tiles, facing the way the tile points; a guard
+//                      carrying a key has key_door_x and key_door_y properties
+//     start            the thief's tile, where the level starts
+//     lights           light tiles, with kind ("torch" or "candle"), lit, and
+//                      for torches dir_x and dir_y, the way they shine
+//     keys             key tiles, with door_x and door_y for the door they open
+//     patrol regions   rectangles where guards patrol
+//     patrol routes    lines from one patrol region to another
+//     moonlit          rectangles of cells open to the sky
+//     creaky floors    rectangles of wooden floor that creaks
+//     locked doors     rectangles over doors that are locked
+//     extra items      item tiles for a second item on a cell
+//
+// Some things look alike in the tileset, so they are told apart on import: any
+// wall tile is a wall, joined up to its neighbors afresh; doors and portcullises
+// take their orientation from their doorway; and a portcullis tile on plain
+// ground makes that cell a portcullis gateway. As with the ASCII format, a map
+// with no patrol regions gets them made from its rooms.
+
+use crate::ascii_map::*;
+use crate::cell_grid::*;
+use multiarray::Array2D;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+
+const TILE_SIZE: usize = 16;
+const TILESET_NAME: &str = "tiles";
+const TILESET_COLUMNS: usize = 16;
+const TILESET_ROWS: usize = 16;
+const FIRST_GID: u32 = 1;
+
+// Tiled keeps flip and rotation flags in the top bits of each tile number.
+
+const GID_FLAGS: u32 = 0xf000_0000;
+
+const LAYER_CELLS: &str = "cells";
+const LAYER_ITEMS: &str = "items";
+const LAYER_GUARDS: &str = "guards";
+const LAYER_START: &str = "start";
+const LAYER_LIGHTS: &str = "lights";
+const LAYER_KEYS: &str = "keys";
+const LAYER_PATROL_REGIONS: &str = "patrol regions";
+const LAYER_PATROL_ROUTES: &str = "patrol routes";
+const LAYER_MOONLIT: &str = "moonlit";
+const LAYER_CREAKY: &str = "creaky floors";
+const LAYER_LOCKED: &str = "locked doors";
+const LAYER_EXTRA_ITEMS: &str = "extra items";
+
+#[derive(Debug)]
+pub struct ImportTiledError {
+    pub layer: Option<String>, // the layer the problem is in, if it's in one
+    pub reason: String,
+}
+
+impl fmt::Display for ImportTiledError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.layer {
+            Some(layer) => write!(f, "layer \"{}\": {}", layer, self.reason),
+            None => write!(f, "{}", self.reason),
+        }
+    }
+}
+
+impl std::error::Error for ImportTiledError {}
+
+fn import_error(layer: &str, reason: String) -> ImportTiledError {
+    ImportTiledError { layer: Some(layer.to_string()), reason }
+}
+
+// The parts of Tiled's format that are used here. Tiled writes more than this,
+// which is ignored on import.
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+struct TiledMap {
+    #[serde(rename = "type")]
+    map_type: String,
+    version: String,
+    tiledversion: String,
+    orientation: String,
+    renderorder: String,
+    width: usize,
+    height: usize,
+    tilewidth: usize,
+    tileheight: usize,
+    infinite: bool,
+    nextlayerid: usize,
+    nextobjectid: usize,
+    tilesets: Vec<TiledTileset>,
+    layers: Vec<TiledLayer>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+struct TiledTileset {
+    firstgid: u32,
+    name: String,
+    image: String,
+    imagewidth: usize,
+    imageheight: usize,
+    tilewidth: usize,
+    tileheight: usize,
+    tilecount: usize,
+    columns: usize,
+    margin: usize,
+    spacing: usize,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+struct TiledLayer {
+    id: usize,
+    name: String,
+    #[serde(rename = "type")]
+    layer_type: String,
+    x: i32,
+    y: i32,
+    opacity: f64,
+    visible: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    height: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Vec<u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    draworder: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    objects: Option<Vec<TiledObject>>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+struct TiledObject {
+    id: usize,
+    name: String,
+    #[serde(rename = "type")]
+    object_type: String,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    rotation: f64,
+    visible: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    polyline: Option<Vec<TiledPoint>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    properties: Vec<TiledProperty>,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(default)]
+struct TiledPoint {
+    x: f64,
+    y: f64,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+struct TiledProperty {
+    name: String,
+    #[serde(rename = "type")]
+    property_type: String,
+    value: Value,
+}
+
+fn int_property(name: &str, value: i32) -> TiledProperty {
+    TiledProperty { name: name.to_string(), property_type: "int".to_string(), value: Value::from(value) }
+}
+
+fn bool_property(name: &str, value: bool) -> TiledProperty {
+    TiledProperty { name: name.to_string(), property_type: "bool".to_string(), value: Value::from(value) }
+}
+
+fn string_property(name: &str, value: &str) -> TiledProperty {
+    TiledProperty { name: name.to_string(), property_type: "string".to_string(), value: Value::from(value) }
+}
+
+// The game numbers its tiles from the bottom row of the image up, and Tiled
+// from the top down, so the rows are flipped.
+
+fn gid_for_glyph(glyph: usize) -> u32 {
+    let row = TILESET_ROWS - 1 - glyph / TILESET_COLUMNS;
+    let column = glyph % TILESET_COLUMNS;
+    FIRST_GID + (row * TILESET_COLUMNS + column) as u32
+}
+
+fn glyph_for_gid(gid: u32, first_gid: u32) -> Option<usize> {
+    let gid = gid & !GID_FLAGS;
+    if gid < first_gid || gid >= first_gid + (TILESET_ROWS * TILESET_COLUMNS) as u32 {
+        return None;
+    }
+    let tile = (gid - first_gid) as usize;
+    let row = TILESET_ROWS - 1 - tile / TILESET_COLUMNS;
+    Some(row * TILESET_COLUMNS + tile % TILESET_COLUMNS)
+}
+
+// Some cell types share a tile: creaky wood looks like any other wood and
+// portcullises like bare ground, so those come from their own layers, and the
+// first of each set listed here wins. Walls go by way of their ASCII map
+// character like items do, so they all come back as Wall0000 and are joined up
+// to their neighbors afterward.
+
+fn cell_type_for_glyph(glyph: usize) -> Option<CellType> {
+    [
+        CellType::GroundNormal,
+        CellType::GroundGrass,
+        CellType::GroundWater,
+        CellType::GroundMarble,
+        CellType::GroundWood,
+        CellType::Wall0000,
+        CellType::Wall0001,
+        CellType::Wall0010,
+        CellType::Wall0011,
+        CellType::Wall0100,
+        CellType::Wall0101,
+        CellType::Wall0110,
+        CellType::Wall0111,
+        CellType::Wall1000,
+        CellType::Wall1001,
+        CellType::Wall1010,
+        CellType::Wall1011,
+        CellType::Wall1100,
+        CellType::Wall1101,
+        CellType::Wall1110,
+        CellType::Wall1111,
+        CellType::OneWayWindowE,
+        CellType::OneWayWindowW,
+        CellType::OneWayWindowN,
+        CellType::OneWayWindowS,
+        CellType::DoorNS,
+        CellType::DoorEW,
+    ].iter()
+        .find(|&&cell_type| tile_def(cell_type).glyph == glyph)
+        .and_then(|&cell_type| cell_type_for_char(char_for_cell_type(cell_type)))
+}
+
+// Items go by way of their ASCII map character, which leaves the orientation
+// of doors and portcullises to the cell they are in.
+
+fn item_char_for_glyph(glyph: usize) -> Option<char> {
+    [
+        ItemKind::Chair,
+        ItemKind::Table,
+        ItemKind::Bush,
+        ItemKind::Coin,
+        ItemKind::DoorNS,
+        ItemKind::DoorEW,
+        ItemKind::DoorOpenNS,
+        ItemKind::DoorOpenEW,
+        ItemKind::PortcullisNS,
+        ItemKind::PortcullisLoweredNS,
+        ItemKind::Winch,
+        ItemKind::Stones,
+        ItemKind::SmokeBomb,
+        ItemKind::WaterFlask,
+    ].iter()
+        .find(|&&kind| glyph_for_item(kind) == glyph)
+        .map(|&kind| char_for_item_kind(kind))
+}
+
+fn guard_dir_for_glyph(glyph: usize) -> Option<Point> {
+    [Point::new(0, 1), Point::new(0, -1), Point::new(1, 0), Point::new(-1, 0)].iter()
+        .find(|&&dir| glyph_for_guard(dir) == glyph)
+        .copied()
+}
+
+fn locked(kind: ItemKind) -> Option<ItemKind> {
+    match kind {
+        ItemKind::DoorNS => Some(ItemKind::DoorLockedNS),
+        ItemKind::DoorEW => Some(ItemKind::DoorLockedEW),
+        _ => None,
+    }
+}
+
+impl Map {
+
+pub fn to_tiled_json(&self, tileset_image: &str) -> String {
+    let size_x = self.cells.extents()[0];
+    let size_y = self.cells.extents()[1];
+
+    let mut next_object_id = 1;
+
+    let mut tile_object = |name: &str, pos: Point, glyph: usize, properties: Vec<TiledProperty>| -> TiledObject {
+        let id = next_object_id;
+        next_object_id += 1;
+        TiledObject {
+            id,
+            name: name.to_string(),
+            object_type: String::new(),
+            x: (pos.x as usize * TILE_SIZE) as f64,
+            y: ((size_y - pos.y as usize) * TILE_SIZE) as f64,
+            width: TILE_SIZE as f64,
+            height: TILE_SIZE as f64,
+            rotation: 0.0,
+            visible: true,
+            gid: Some(gid_for_glyph(glyph)),
+            polyline: None,
+            properties,
+        }
+    };
+
+    // The cells and the first item on each.
+
+    let mut cell_data = vec![0; size_x * size_y];
+    let mut item_data = vec![0; size_x * size_y];
+    let mut extra_items = Vec::new();
+
+    for x in 0..size_x {
+        for y in 0..size_y {
+            cell_data[(size_y - 1 - y) * size_x + x] = gid_for_glyph(tile_def(self.cells[[x, y]].cell_type).glyph);
+        }
+    }
+
+    for item in &self.items {
+        let i = (size_y - 1 - item.pos.y as usize) * size_x + item.pos.x as usize;
+        if item_data[i] == 0 {
+            item_data[i] = gid_for_glyph(glyph_for_item(item.kind));
+        } else {
+            extra_items.push(tile_object("item", item.pos, glyph_for_item(item.kind), Vec::new()));
+        }
+    }
+
+    // The things that don't fit on a grid.
+
+    let guards: Vec<TiledObject> = self.guards.iter().map(|guard| {
+        let properties = match &guard.key {
+            Some(key) => vec![int_property("key_door_x", key.door.x), int_property("key_door_y", key.door.y)],
+            None => Vec::new(),
+        };
+        tile_object("guard", guard.pos, glyph_for_guard(guard.dir), properties)
+    }).collect();
+
+    let start = vec![tile_object("start", self.pos_start, GLYPH_PLAYER, Vec::new())];
+
+    let lights: Vec<TiledObject> = self.lights.iter().map(|light| {
+        let mut properties = vec![bool_property("lit", light.lit)];
+        let kind = match light.kind {
+            LightKind::Torch => {
+                properties.push(int_property("dir_x", light.dir.x));
+                properties.push(int_property("dir_y", light.dir.y));
+                "torch"
+            },
+            LightKind::Candle => "candle",
+        };
+        properties.push(string_property("kind", kind));
+        tile_object(kind, light.pos, glyph_for_light(light.kind), properties)
+    }).collect();
+
+    let keys: Vec<TiledObject> = self.keys.iter().map(|key| {
+        tile_object("key", key.pos, GLYPH_KEY, vec![int_property("door_x", key.door.x), int_property("door_y", key.door.y)])
+    }).collect();
+
+    // Areas are rectangles, and routes are lines from the middle of one patrol
+    // region to the middle of the other.
+
+    let mut rect_object = |name: String, rect: &Rect| -> TiledObject {
+        let id = next_object_id;
+        next_object_id += 1;
+        TiledObject {
+            id,
+            name,
+            object_type: String::new(),
+            x: (rect.pos_min.x as usize * TILE_SIZE) as f64,
+            y: ((size_y - rect.pos_max.y as usize) * TILE_SIZE) as f64,
+            width: ((rect.pos_max.x - rect.pos_min.x) as usize * TILE_SIZE) as f64,
+            height: ((rect.pos_max.y - rect.pos_min.y) as usize * TILE_SIZE) as f64,
+            rotation: 0.0,
+            visible: true,
+            gid: None,
+            polyline: None,
+            properties: Vec::new(),
+        }
+    };
+
+    let areas = |include: &dyn Fn(&Cell) -> bool| -> Vec<Rect> {
+        let mut marked = Array2D::new([size_x, size_y], false);
+        for x in 0..size_x {
+            for y in 0..size_y {
+                marked[[x, y]] = include(&self.cells[[x, y]]);
+            }
+        }
+        cover_with_rects(&marked)
+    };
+
+    let patrol_regions: Vec<TiledObject> = self.patrol_regions.iter().enumerate()
+        .map(|(i_region, rect)| rect_object(format!("region {}", i_region), rect))
+        .collect();
+
+    let moonlit: Vec<TiledObject> = areas(&|cell| cell.moonlit).iter()
+        .map(|rect| rect_object("moonlit".to_string(), rect))
+        .collect();
+
+    let creaky: Vec<TiledObject> = areas(&|cell| cell.cell_type == CellType::GroundWoodCreaky).iter()
+        .map(|rect| rect_object("creaky".to_string(), rect))
+        .collect();
+
+    let locked_doors: Vec<TiledObject> = self.items.iter()
+        .filter(|item| is_locked_door(item.kind))
+        .map(|item| rect_object("locked".to_string(), &Rect { pos_min: item.pos, pos_max: item.pos + Point::new(1, 1) }))
+        .collect();
+
+    let region_center = |i_region: usize| -> TiledPoint {
+        let rect = &self.patrol_regions[i_region];
+        TiledPoint {
+            x: (rect.pos_min.x + rect.pos_max.x) as f64 * TILE_SIZE as f64 / 2.0,
+            y: (2 * size_y as i32 - rect.pos_min.y - rect.pos_max.y) as f64 * TILE_SIZE as f64 / 2.0,
+        }
+    };
+
+    let patrol_routes: Vec<TiledObject> = self.patrol_routes.iter().map(|&(region0, region1)| {
+        let from = region_center(region0);
+        let to = region_center(region1);
+        let id = next_object_id;
+        next_object_id += 1;
+        TiledObject {
+            id,
+            name: format!("route {} {}", region0, region1),
+            object_type: String::new(),
+            x: from.x,
+            y: from.y,
+            width: 0.0,
+            height: 0.0,
+            rotation: 0.0,
+            visible: true,
+            gid: None,
+            polyline: Some(vec![TiledPoint { x: 0.0, y: 0.0 }, TiledPoint { x: to.x - from.x, y: to.y - from.y }]),
+            properties: Vec::new(),
+        }
+    }).collect();
+
+    // Lay it all out, bottom layer first.
+
+    let mut layers: Vec<TiledLayer> = Vec::new();
+
+    let add_tile_layer = |layers: &mut Vec<TiledLayer>, name: &str, data: Vec<u32>| {
+        layers.push(TiledLayer {
+            id: layers.len() + 1,
+            name: name.to_string(),
+            layer_type: "tilelayer".to_string(),
+            opacity: 1.0,
+            visible: true,
+            width: Some(size_x),
+            height: Some(size_y),
+            data: Some(data),
+            ..TiledLayer::default()
+        });
+    };
+
+    add_tile_layer(&mut layers, LAYER_CELLS, cell_data);
+    add_tile_layer(&mut layers, LAYER_ITEMS, item_data);
+
+    for (name, objects, visible) in [
+        (LAYER_EXTRA_ITEMS, extra_items, true),
+        (LAYER_MOONLIT, moonlit, false),
+        (LAYER_CREAKY, creaky, false),
+        (LAYER_LOCKED, locked_doors, true),
+        (LAYER_PATROL_REGIONS, patrol_regions, true),
+        (LAYER_PATROL_ROUTES, patrol_routes, true),
+        (LAYER_LIGHTS, lights, true),
+        (LAYER_KEYS, keys, true),
+        (LAYER_GUARDS, guards, true),
+        (LAYER_START, start, true),
+    ] {
+        layers.push(TiledLayer {
+            id: layers.len() + 1,
+            name: name.to_string(),
+            layer_type: "objectgroup".to_string(),
+            opacity: 1.0,
+            visible,
+            draworder: Some("index".to_string()),
+            objects: Some(objects),
+            ..TiledLayer::default()
+        });
+    }
+
+    let tiled_map = TiledMap {
+        map_type: "map".to_string(),
+        version: "1.10".to_string(),
+        tiledversion: "1.10.2".to_string(),
+        orientation: "orthogonal".to_string(),
+        renderorder: "right-down".to_string(),
+        width: size_x,
+        height: size_y,
+        tilewidth: TILE_SIZE,
+        tileheight: TILE_SIZE,
+        infinite: false,
+        nextlayerid: layers.len() + 1,
+        nextobjectid: next_object_id,
+        tilesets: vec![TiledTileset {
+            firstgid: FIRST_GID,
+            name: TILESET_NAME.to_string(),
+            image: tileset_image.to_string(),
+            imagewidth: TILESET_COLUMNS * TILE_SIZE,
+            imageheight: TILESET_ROWS * TILE_SIZE,
+            tilewidth: TILE_SIZE,
+            tileheight: TILE_SIZE,
+            tilecount: TILESET_COLUMNS * TILESET_ROWS,
+            columns: TILESET_COLUMNS,
+            margin: 0,
+            spacing: 0,
+        }],
+        layers,
+    };
+
+    serde_json::to_string_pretty(&tiled_map).unwrap()
+}
+
+pub fn from_tiled_json(text: &str) -> Result<Map, ImportTiledError> {
+    let tiled_map: TiledMap = serde_json::from_str(text)
+        .map_err(|err| ImportTiledError { layer: None, reason: format!("not a Tiled JSON map: {}", err) })?;
+
+    if tiled_map.orientation != "orthogonal" || tiled_map.infinite {
+        return Err(ImportTiledError { layer: None, reason: "the map must be orthogonal and of fixed size".to_string() });
+    }
+
+    let first_gid = match tiled_map.tilesets.iter().find(|tileset| tileset.name == TILESET_NAME) {
+        Some(tileset) => tileset.firstgid,
+        None => return Err(ImportTiledError { layer: None, reason: format!("the map has no tileset named \"{}\"", TILESET_NAME) }),
+    };
+
+    let size_x = tiled_map.width;
+    let size_y = tiled_map.height;
+    let tile_x = tiled_map.tilewidth.max(1) as f64;
+    let tile_y = tiled_map.tileheight.max(1) as f64;
+
+    if size_x == 0 || size_y == 0 {
+        return Err(ImportTiledError { layer: None, reason: "the map is empty".to_string() });
+    }
+
+    let tiles = |name: &str| -> Result<&Vec<u32>, ImportTiledError> {
+        let layer = tiled_map.layers.iter().find(|layer| layer.name == name && layer.layer_type == "tilelayer")
+            .ok_or_else(|| ImportTiledError { layer: None, reason: format!("the map has no tile layer named \"{}\"", name) })?;
+        match (layer.encoding.as_deref(), &layer.data) {
+            (None, Some(data)) | (Some("csv"), Some(data)) if data.len() == size_x * size_y => Ok(data),
+            (None, Some(_)) | (Some("csv"), Some(_)) => Err(import_error(name, format!("the layer must be the size of the map, {} by {}", size_x, size_y))),
+            _ => Err(import_error(name, "tile data must be saved as CSV".to_string())),
+        }
+    };
+
+    let objects = |name: &str| -> &[TiledObject] {
+        tiled_map.layers.iter()
+            .find(|layer| layer.name == name && layer.layer_type == "objectgroup")
+            .and_then(|layer| layer.objects.as_deref())
+            .unwrap_or(&[])
+    };
+
+    let cell_tiles = tiles(LAYER_CELLS)?;
+    let item_tiles = tiles(LAYER_ITEMS)?;
+
+    let glyph_at = |data: &Vec<u32>, x: usize, y: usize| -> Option<usize> {
+        let gid = data[(size_y - 1 - y) * size_x + x];
+        if gid == 0 {None} else {glyph_for_gid(gid, first_gid)}
+    };
+
+    // Tile objects sit on the cell under their bottom left corner.
+
+    let cell_of = |layer: &str, object: &TiledObject| -> Result<Point, ImportTiledError> {
+        let x = (object.x / tile_x).round() as i32;
+        let y = size_y as i32 - (object.y / tile_y).round() as i32;
+        if x < 0 || y < 0 || x >= size_x as i32 || y >= size_y as i32 {
+            Err(import_error(layer, format!("object {} at ({}, {}) is off the map", object.id, x, y)))
+        } else {
+            Ok(Point::new(x, y))
+        }
+    };
+
+    let rect_of = |layer: &str, object: &TiledObject| -> Result<Rect, ImportTiledError> {
+        let pos_min = Point::new(
+            (object.x / tile_x).round() as i32,
+            size_y as i32 - ((object.y + object.height) / tile_y).round() as i32);
+        let pos_max = Point::new(
+            ((object.x + object.width) / tile_x).round() as i32,
+            size_y as i32 - (object.y / tile_y).round() as i32);
+        if pos_min.x < 0 || pos_min.y < 0 || pos_max.x > size_x as i32 || pos_max.y > size_y as i32 || pos_min.x >= pos_max.x || pos_min.y >= pos_max.y {
+            Err(import_error(layer, format!("rectangle {} from ({}, {}) to ({}, {}) is empty or runs off the map", object.id, pos_min.x, pos_min.y, pos_max.x, pos_max.y)))
+        } else {
+            Ok(Rect { pos_min, pos_max })
+        }
+    };
+
+    let object_glyph = |layer: &str, object: &TiledObject| -> Result<usize, ImportTiledError> {
+        object.gid.and_then(|gid| glyph_for_gid(gid, first_gid))
+            .ok_or_else(|| import_error(layer, format!("object {} is not a tile from the \"{}\" tileset", object.id, TILESET_NAME)))
+    };
+
+    let int_value = |layer: &str, object: &TiledObject, name: &str| -> Result<Option<i32>, ImportTiledError> {
+        match object.properties.iter().find(|property| property.name == name) {
+            None => Ok(None),
+            Some(property) => property.value.as_i64()
+                .map(|value| Some(value as i32))
+                .ok_or_else(|| import_error(layer, format!("object {} has a {} that is not a whole number", object.id, name))),
+        }
+    };
+
+    let door_value = |layer: &str, object: &TiledObject, name_x: &str, name_y: &str| -> Result<Option<Point>, ImportTiledError> {
+        match (int_value(layer, object, name_x)?, int_value(layer, object, name_y)?) {
+            (Some(x), Some(y)) if x >= 0 && y >= 0 && x < size_x as i32 && y < size_y as i32 => Ok(Some(Point::new(x, y))),
+            (Some(x), Some(y)) => Err(import_error(layer, format!("object {} has a door at ({}, {}), off the map", object.id, x, y))),
+            (None, None) => Ok(None),
+            _ => Err(import_error(layer, format!("object {} needs both {} and {}", object.id, name_x, name_y))),
+        }
+    };
+
+    // Work out the cell types first, since walls join up with doors and
+    // portcullises as well as with each other.
+
+    let mut cell_types: Array2D<CellType> = Array2D::new([size_x, size_y], CellType::GroundNormal);
+
+    for x in 0..size_x {
+        for y in 0..size_y {
+            cell_types[[x, y]] = match glyph_at(cell_tiles, x, y).and_then(cell_type_for_glyph) {
+                Some(cell_type) => cell_type,
+                None => return Err(import_error(LAYER_CELLS, format!("the tile at ({}, {}) is not a cell", x, y))),
+            };
+        }
+    }
+
+    for x in 0..size_x {
+        for y in 0..size_y {
+            let portcullis = match glyph_at(item_tiles, x, y).and_then(item_char_for_glyph).and_then(|c| item_kind_for_char(c, CellType::GroundNormal)) {
+                Some(kind) => is_portcullis(kind),
+                None => false,
+            };
+            if !portcullis || cell_types[[x, y]] != CellType::GroundNormal {
+                continue;
+            }
+            let wall_north = y + 1 < size_y && cell_types[[x, y + 1]] == CellType::Wall0000;
+            let wall_south = y > 0 && cell_types[[x, y - 1]] == CellType::Wall0000;
+            cell_types[[x, y]] = if wall_north && wall_south {CellType::PortcullisNS} else {CellType::PortcullisEW};
+        }
+    }
+
+    for object in objects(LAYER_CREAKY) {
+        let rect = rect_of(LAYER_CREAKY, object)?;
+        for x in rect.pos_min.x..rect.pos_max.x {
+            for y in rect.pos_min.y..rect.pos_max.y {
+                let cell_type = &mut cell_types[[x as usize, y as usize]];
+                if *cell_type != CellType::GroundWood {
+                    return Err(import_error(LAYER_CREAKY, format!("({}, {}) is not a wooden floor", x, y)));
+                }
+                *cell_type = CellType::GroundWoodCreaky;
+            }
+        }
+    }
+
+    let mut map = new_map(&cell_types);
+
+    for object in objects(LAYER_MOONLIT) {
+        let rect = rect_of(LAYER_MOONLIT, object)?;
+        for x in rect.pos_min.x..rect.pos_max.x {
+            for y in rect.pos_min.y..rect.pos_max.y {
+                map.cells[[x as usize, y as usize]].moonlit = true;
+            }
+        }
+    }
+
+    // Items, from the tile layer and then any extras.
+
+    let add_item = |map: &mut Map, layer: &str, glyph: Option<usize>, pos: Point| -> Result<(), ImportTiledError> {
+        let cell_type = map.cells[[pos.x as usize, pos.y as usize]].cell_type;
+        let kind = match glyph.and_then(item_char_for_glyph).and_then(|c| item_kind_for_char(c, cell_type)) {
+            Some(kind) => kind,
+            None => return Err(import_error(layer, format!("the tile at ({}, {}) is not an item", pos.x, pos.y))),
+        };
+        check_item_placement(map, kind, pos).map_err(|reason| import_error(layer, reason))?;
+        map.items.push(Item { pos, kind });
+        Ok(())
+    };
+
+    for y in (0..size_y).rev() {
+        for x in 0..size_x {
+            if item_tiles[(size_y - 1 - y) * size_x + x] != 0 {
+                add_item(&mut map, LAYER_ITEMS, glyph_at(item_tiles, x, y), Point::new(x as i32, y as i32))?;
+            }
+        }
+    }
+
+    for object in objects(LAYER_EXTRA_ITEMS) {
+        let pos = cell_of(LAYER_EXTRA_ITEMS, object)?;
+        let glyph = object_glyph(LAYER_EXTRA_ITEMS, object)?;
+        add_item(&mut map, LAYER_EXTRA_ITEMS, Some(glyph), pos)?;
+    }
+
+    for object in objects(LAYER_LOCKED) {
+        let rect = rect_of(LAYER_LOCKED, object)?;
+        for x in rect.pos_min.x..rect.pos_max.x {
+            for y in rect.pos_min.y..rect.pos_max.y {
+                let pos = Point::new(x, y);
+                match map.items.iter_mut().find(|item| item.pos == pos && locked(item.kind).is_some()) {
+                    Some(item) => item.kind = locked(item.kind).unwrap(),
+                    None => return Err(import_error(LAYER_LOCKED, format!("no closed door to lock at ({}, {})", x, y))),
+                }
+            }
+        }
+    }
+
+    // The start, guards, lights and keys.
+
+    let start = objects(LAYER_START);
+    if start.len() != 1 {
+        return Err(import_error(LAYER_START, format!("there must be exactly one start, not {}", start.len())));
+    }
+    map.pos_start = cell_of(LAYER_START, &start[0])?;
+    if inside_wall(&map, map.pos_start) {
+        return Err(import_error(LAYER_START, format!("the start at ({}, {}) is inside a wall", map.pos_start.x, map.pos_start.y)));
+    }
+
+    for object in objects(LAYER_GUARDS) {
+        let pos = cell_of(LAYER_GUARDS, object)?;
+        let dir = match guard_dir_for_glyph(object_glyph(LAYER_GUARDS, object)?) {
+            Some(dir) => dir,
+            None => return Err(import_error(LAYER_GUARDS, format!("object {} at ({}, {}) is not a guard tile", object.id, pos.x, pos.y))),
+        };
+        if inside_wall(&map, pos) {
+            return Err(import_error(LAYER_GUARDS, format!("guard at ({}, {}) is inside a wall", pos.x, pos.y)));
+        }
+        let mut guard = Guard::new(pos, dir);
+        if let Some(door) = door_value(LAYER_GUARDS, object, "key_door_x", "key_door_y")? {
+            check_key_door(&map, door).map_err(|reason| import_error(LAYER_GUARDS, reason))?;
+            guard.key = Some(Key { pos, door });
+        }
+        map.guards.push(guard);
+    }
+
+    for object in objects(LAYER_LIGHTS) {
+        let pos = cell_of(LAYER_LIGHTS, object)?;
+        let kind = match object.properties.iter().find(|property| property.name == "kind").and_then(|property| property.value.as_str()) {
+            Some("torch") => LightKind::Torch,
+            Some("candle") => LightKind::Candle,
+            _ => return Err(import_error(LAYER_LIGHTS, format!("light at ({}, {}) needs a kind of \"torch\" or \"candle\"", pos.x, pos.y))),
+        };
+        let dir = Point::new(
+            int_value(LAYER_LIGHTS, object, "dir_x")?.unwrap_or(0),
+            int_value(LAYER_LIGHTS, object, "dir_y")?.unwrap_or(0));
+        if kind == LightKind::Torch {
+            check_torch(&map, pos, dir).map_err(|reason| import_error(LAYER_LIGHTS, reason))?;
+        }
+        let lit = object.properties.iter().find(|property| property.name == "lit").and_then(|property| property.value.as_bool()).unwrap_or(true);
+        map.lights.push(Light { pos, dir, kind, lit });
+    }
+
+    for object in objects(LAYER_KEYS) {
+        let pos = cell_of(LAYER_KEYS, object)?;
+        let door = match door_value(LAYER_KEYS, object, "door_x", "door_y")? {
+            Some(door) => door,
+            None => return Err(import_error(LAYER_KEYS, format!("key at ({}, {}) needs door_x and door_y", pos.x, pos.y))),
+        };
+        check_key_door(&map, door).map_err(|reason| import_error(LAYER_KEYS, reason))?;
+        map.keys.push(Key { pos, door });
+    }
+
+    // Patrol regions, and the routes between them, found by where each line
+    // starts and ends.
+
+    let regions = objects(LAYER_PATROL_REGIONS).iter()
+        .map(|object| rect_of(LAYER_PATROL_REGIONS, object))
+        .collect::<Result<Vec<Rect>, ImportTiledError>>()?;
+
+    let region_at = |x: f64, y: f64| -> Option<usize> {
+        let x = x / tile_x;
+        let y = size_y as f64 - y / tile_y;
+        regions.iter().position(|rect| {
+            x >= rect.pos_min.x as f64 && x <= rect.pos_max.x as f64 &&
+            y >= rect.pos_min.y as f64 && y <= rect.pos_max.y as f64
+        })
+    };
+
+    let mut routes: Vec<(usize, usize)> = Vec::new();
+
+    for object in objects(LAYER_PATROL_ROUTES) {
+        let (from, to) = match &object.polyline {
+            Some(points) if points.len() >= 2 => (points[0], points[points.len() - 1]),
+            _ => return Err(import_error(LAYER_PATROL_ROUTES, format!("object {} is not a line", object.id))),
+        };
+        match (region_at(object.x + from.x, object.y + from.y), region_at(object.x + to.x, object.y + to.y)) {
+            (Some(region0), Some(region1)) => routes.push((region0, region1)),
+            _ => return Err(import_error(LAYER_PATROL_ROUTES, format!("route {} does not start and end in patrol regions", object.id))),
+        }
+    }
+
+    if regions.is_empty() {
+        if !routes.is_empty() {
+            return Err(import_error(LAYER_PATROL_ROUTES, "there are routes but no patrol regions".to_string()));
+        }
+        find_patrol_regions(&mut map);
+    } else {
+        match set_patrol_regions(&mut map, &regions, &routes) {
+            Ok(()) => (),
+            Err(PatrolError::Region(_, reason)) => return Err(import_error(LAYER_PATROL_REGIONS, reason)),
+            Err(PatrolError::Route(i_route, reason)) => return Err(import_error(LAYER_PATROL_ROUTES, format!("object {}: {}", objects(LAYER_PATROL_ROUTES)[i_route].id, reason))),
+        }
+    }
+
+    if !map.guards.is_empty() && map.patrol_regions.is_empty() {
+        return Err(import_error(LAYER_PATROL_REGIONS, "there are guards but nowhere for them to patrol".to_string()));
+    }
+
+    finish_map(&mut map);
+
+    Ok(map)
+}
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::campaign::{self, LevelSource};
+    use crate::random_map::generate_map;
+    use rand::SeedableRng;
+
+    // The ASCII form covers everything both formats keep, so the maps are
+    // compared through it.
+
+    fn assert_round_trip(map: &Map, name: &str) {
+        let json = map.to_tiled_json("tiles.png");
+        let map_read = Map::from_tiled_json(&json).unwrap_or_else(|err| panic!("{}: {}", name, err));
+        assert_eq!(map_read.to_ascii(), map.to_ascii(), "{}", name);
+    }
+
+    #[test]
+    fn generated_maps_round_trip() {
+        for seed in 0..12 {
+            for level in 0..4 {
+                let mut rng = MyRng::seed_from_u64(seed);
+                let map = generate_map(&mut rng, level);
+                assert_round_trip(&map, &format!("seed {} level {}", seed, level));
+            }
+        }
+    }
+
+    #[test]
+    fn authored_levels_round_trip() {
+        for source in campaign::CAMPAIGN {
+            if let LevelSource::Authored { name, text } = source {
+                assert_round_trip(&Map::from_ascii(text).unwrap(), name);
+            }
+        }
+    }
+
+    #[test]
+    fn every_cell_glyph_is_read_back() {
+        for &c in &['.', ',', '~', ':', '_', '#', 'E', 'W', 'N', 'S', '|', '-'] {
+            let cell_type = cell_type_for_char(c).unwrap();
+            assert_eq!(cell_type_for_glyph(tile_def(cell_type).glyph), Some(cell_type), "'{}'", c);
+        }
+    }
+
+    #[test]
+    fn torch_must_shine_into_an_open_cell() {
+        let text = campaign::CAMPAIGN.iter().find_map(|source| match source {
+            LevelSource::Authored { text, .. } => Some(*text),
+            _ => None,
+        }).unwrap();
+        let map = Map::from_ascii(text).unwrap();
+        let i_torch = map.lights.iter().position(|light| light.kind == LightKind::Torch).unwrap();
+
+        let import_err = |pos: Point, dir: Point| -> String {
+            let mut map = Map::from_ascii(text).unwrap();
+            map.lights[i_torch].pos = pos;
+            map.lights[i_torch].dir = dir;
+            match Map::from_tiled_json(&map.to_tiled_json("tiles.png")) {
+                Ok(_) => panic!("expected the torch at ({}, {}) shining ({}, {}) to be rejected", pos.x, pos.y, dir.x, dir.y),
+                Err(err) => err.to_string(),
+            }
+        };
+
+        let pos = map.lights[i_torch].pos;
+        assert_eq!(import_err(pos, Point::new(1, 1)),
+            format!("layer \"lights\": the torch at ({}, {}) must shine one step north, south, east or west, not (1, 1)", pos.x, pos.y));
+        assert_eq!(import_err(Point::new(0, 0), Point::new(-1, 0)),
+            "layer \"lights\": the torch at (0, 0) shines off the map");
+    }
+}